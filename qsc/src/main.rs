@@ -20,7 +20,7 @@
 //!
 //! ## USAGE:
 //!
-//!   `qsc [OPTIONS] --targets <TARGETS> --ports <PORTS>`
+//!   `qsc [OPTIONS] --targets <TARGETS> <--ports <PORTS>|--top-ports <TOP_PORTS>>`
 //!
 //! ## OPTIONS:
 //!
@@ -28,12 +28,25 @@
 //!        --batch <BATCH>
 //!            Parallel scan [default: 5000]
 //!
+//!        --dry-run
+//!            Print the number of targets without scanning them
+//!
 //!    -h, --help
 //!            Print help information
 //!
 //!        --json <JSON>
 //!            Path to file whre to save results in json format
 //!
+//!        --output <OUTPUT>
+//!            Results output format, printed to stdout (console output is suppressed) or written
+//!            to --output-file [possible values: json, grepable, csv, xml]
+//!
+//!        --output-file <OUTPUT_FILE>
+//!            Path to file where to write results in the --output format
+//!
+//!        --list-targets
+//!            Print the targets before their number (--dry-run)
+//!
 //!        --mode <MODE>
 //!            Scan mode:
 //!              - 0: TCP connect;
@@ -48,27 +61,38 @@
 //!            Number of maximum retries for each target (ping scan) [default: 1]
 //!
 //!        --ports <PORTS>
-//!            Comma separate list of ports (or port ranges) to scan for each target. E.g., '80',
-//!            '22,443', '1-1024,8080'
+//!            Comma separate list of ports (or port ranges) to scan for each target. Common
+//!            service names and paths to a file containing one of the previous for each line
+//!            are accepted too. E.g., '80', '22,443', '1-1024,8080', 'http,https',
+//!            '/tmp/ports.txt'
 //!
 //!        --printlevel <PRINTLEVEL>
 //!            Console output mode:
 //!              - 0: suppress console output;
 //!              - 1: print ip:port for open ports at the end of the scan;
-//!              - 2: print ip:port:<OPEN|CLOSE> at the end of the scan;
+//!              - 2: print ip:port:<OPEN|CLOSE|FILTERED> at the end of the scan;
 //!              - 3: print ip:port for open ports as soon as they are found;
-//!              - 4: print ip:port:<OPEN:CLOSE> as soon as the scan for a
+//!              - 4: print ip:port:<OPEN|CLOSE|FILTERED> as soon as the scan for a
 //!                   target ends;
+//!              - 5: print ip:port:<OPEN|CLOSE|FILTERED>[:<LATENCY>ms] for all the
+//!                   targets as soon as the scan for a target ends;
 //!                     [default: 3]
 //!
+//!        --top-ports <TOP_PORTS>
+//!            Scan the N most common ports (at most 100) instead of --ports
+//!
 //!        --targets <TARGETS>
 //!            Comma separated list of targets to scan. A target can be an IP, a set of IPs in CIDR
-//!            notation, a domain name or a path to a file containing one of the previous for each
-//!            line. E.g., '8.8.8.8', '192.168.1.0/24', 'www.google.com,/tmp/ips.txt'
+//!            notation, an IPv4 range, a domain name or a path to a file containing one of the
+//!            previous for each line ('-' reads them from the standard input). E.g., '8.8.8.8',
+//!            '192.168.1.0/24', '192.168.1.1-50', 'www.google.com,/tmp/ips.txt', '-'
 //!
 //!        --tcp-tries <TCP_TRIES>
 //!            Number of maximum retries for each target:port pair (TCP Connect scan) [default: 1]
 //!
+//!        --threads <THREADS>
+//!            Number of worker threads of the runtime [default: one for each CPU core]
+//!
 //!        --timeout <TIMEOUT>
 //!            Timeout in ms. If the timeout expires the port is considered close [default: 1500]
 //!
@@ -77,15 +101,22 @@
 //!
 //! ```
 
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::Write;
+use std::future::Future;
+use std::io::{self, Write};
 use std::net::IpAddr;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 
-use qscan::{QSPrintMode, QScanPingState, QScanResult, QScanTcpConnectState, QScanType, QScanner};
+use qscan::{
+    QSPrintMode, QScanPingState, QScanResult, QScanTcpConnectState, QScanType, QScanUdpState,
+    QScanner,
+};
 
-use clap::Parser;
+use clap::{ArgEnum, Parser};
 use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
 
 #[cfg(target_os = "linux")]
 #[cfg(not(debug_assertions))]
@@ -99,18 +130,29 @@ struct Args {
     #[clap(
         long,
         help = "Comma separated list of targets to scan. \
-        A target can be an IP, a set of IPs in CIDR notation, a domain name \
-        or a path to a file containing one of the previous for each line. \
-        E.g., '8.8.8.8', '192.168.1.0/24', 'www.google.com,/tmp/ips.txt'"
+        A target can be an IP, a set of IPs in CIDR notation, an IPv4 range, \
+        a domain name or a path to a file containing one of the previous for each line \
+        ('-' reads them from the standard input). \
+        E.g., '8.8.8.8', '192.168.1.0/24', '192.168.1.1-50', 'www.google.com,/tmp/ips.txt', '-'"
     )]
     targets: String,
 
     #[clap(
         long,
+        required_unless_present = "top-ports",
         help = "Comma separate list of ports (or port ranges) to scan for each target. \
-           E.g., '80', '22,443', '1-1024,8080'"
+           Common service names and paths to a file containing one of the previous \
+           for each line are accepted too. \
+           E.g., '80', '22,443', '1-1024,8080', 'http,https', '/tmp/ports.txt'"
+    )]
+    ports: Option<String>,
+
+    #[clap(
+        long,
+        conflicts_with = "ports",
+        help = "Scan the N most common ports (at most 100) instead of --ports"
     )]
-    ports: String,
+    top_ports: Option<usize>,
 
     #[clap(long, default_value_t = 5000, help = "Parallel scan")]
     batch: u16,
@@ -143,16 +185,24 @@ struct Args {
     )]
     ping_tries: u8,
 
+    #[clap(
+        long,
+        help = "Number of worker threads of the runtime [default: one for each CPU core]"
+    )]
+    threads: Option<NonZeroUsize>,
+
     #[clap(
         long,
         default_value_t = 3,
         help = "Console output mode:
   - 0: suppress console output;
   - 1: print ip:port for open ports at the end of the scan;
-  - 2: print ip:port:<OPEN|CLOSE> at the end of the scan;
+  - 2: print ip:port:<OPEN|CLOSE|FILTERED> at the end of the scan;
   - 3: print ip:port for open ports as soon as they are found;
-  - 4: print ip:port:<OPEN:CLOSE> as soon as the scan for a
+  - 4: print ip:port:<OPEN|CLOSE|FILTERED> as soon as the scan for a
        target ends;
+  - 5: print ip:port:<OPEN|CLOSE|FILTERED>[:<LATENCY>ms] for all the
+       targets as soon as the scan for a target ends;
         "
     )]
     printlevel: u8,
@@ -168,18 +218,113 @@ struct Args {
     )]
     mode: u8,
 
+    #[clap(long, help = "Print the number of targets without scanning them")]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        requires = "dry-run",
+        help = "Print the targets before their number (--dry-run)"
+    )]
+    list_targets: bool,
+
     #[clap(long, help = "Path to file whre to save results in json format")]
     json: Option<PathBuf>,
+
+    #[clap(
+        long,
+        arg_enum,
+        help = "Results output format, printed to stdout (console output is suppressed) \
+        or written to --output-file"
+    )]
+    output: Option<OutputFormat>,
+
+    #[clap(
+        long,
+        requires = "output",
+        help = "Path to file where to write results in the --output format"
+    )]
+    output_file: Option<PathBuf>,
 }
 
+#[derive(ArgEnum, Clone, Debug)]
 #[doc(hidden)]
-fn do_tcp_connect_scan_and_print(scanner: &mut QScanner, args: &Args) {
+enum OutputFormat {
+    Json,
+    Grepable,
+    Csv,
+    Xml,
+}
+
+/// Logger printing the library diagnostics (warnings and errors) to stderr
+#[doc(hidden)]
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[doc(hidden)]
+static LOGGER: StderrLogger = StderrLogger;
+
+/// Cancel `token` when `interrupt` (e.g. Ctrl-C) fires for the first time
+/// and force the exit when it fires again
+#[doc(hidden)]
+async fn cancel_on_interrupt<F, Fut>(token: CancellationToken, mut interrupt: F)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    if interrupt().await.is_err() {
+        return;
+    }
+    eprintln!("Interrupted, stopping the scan (press Ctrl-C again to force exit)");
+    token.cancel();
+
+    if interrupt().await.is_ok() {
+        std::process::exit(130);
+    }
+}
+
+/// Runtime of the scans, with --threads worker threads if set
+#[doc(hidden)]
+fn runtime(args: &Args) -> Runtime {
+    match args.threads {
+        Some(n) => tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(n.get())
+            .enable_all()
+            .build()
+            .unwrap(),
+        None => Runtime::new().unwrap(),
+    }
+}
+
+/// TCP connect scan, stopped on Ctrl-C. Return true if it was interrupted.
+#[doc(hidden)]
+fn do_tcp_connect_scan_and_print(scanner: &mut QScanner, args: &Args) -> bool {
     scanner.set_scan_type(QScanType::TcpConnect);
     scanner.set_ntries(args.tcp_tries);
     set_print_level(scanner, args);
-    let res: &Vec<QScanResult> = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
 
-    if (args.printlevel == 0) && (args.printlevel == 1 || args.printlevel == 2) {
+    let rt = runtime(args);
+    let token = CancellationToken::new();
+    rt.spawn(cancel_on_interrupt(token.clone(), tokio::signal::ctrl_c));
+    let res: &Vec<QScanResult> = rt.block_on(scanner.scan_tcp_connect_with_cancel(token.clone()));
+    let interrupted = token.is_cancelled();
+
+    // With the real time print levels the results have already been printed,
+    // otherwise print the results so far, even when interrupted
+    if console_output(args) && (args.printlevel == 1 || args.printlevel == 2) {
         for r in res {
             if let QScanResult::TcpConnect(sa) = r {
                 if sa.state == QScanTcpConnectState::Open {
@@ -189,27 +334,46 @@ fn do_tcp_connect_scan_and_print(scanner: &mut QScanner, args: &Args) {
                         println!("{}:OPEN", sa.target);
                     }
                 } else if args.printlevel == 2 {
-                    println!("{}:CLOSED", sa.target);
+                    if sa.state == QScanTcpConnectState::Filtered {
+                        println!("{}:FILTERED", sa.target);
+                    } else {
+                        println!("{}:CLOSED", sa.target);
+                    }
                 }
             }
         }
     }
+
+    interrupted
 }
 
+/// Ping scan, stopped on Ctrl-C. Return the results and true if it was
+/// interrupted. Exit if the ping sockets cannot be created.
 #[doc(hidden)]
-fn do_ping_scan<'a>(scanner: &'a mut QScanner, args: &Args) -> &'a Vec<QScanResult> {
+fn do_ping_scan<'a>(scanner: &'a mut QScanner, args: &Args) -> (&'a Vec<QScanResult>, bool) {
     scanner.set_scan_type(QScanType::Ping);
     scanner.set_ntries(args.ping_tries);
     scanner.set_ping_interval_ms(args.ping_interval);
-    Runtime::new().unwrap().block_on(scanner.scan_ping())
+
+    let rt = runtime(args);
+    let token = CancellationToken::new();
+    rt.spawn(cancel_on_interrupt(token.clone(), tokio::signal::ctrl_c));
+    match rt.block_on(scanner.scan_ping_with_cancel(token.clone())) {
+        Ok(res) => (res, token.is_cancelled()),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
+/// Ping scan, stopped on Ctrl-C. Return true if it was interrupted.
 #[doc(hidden)]
-fn do_ping_scan_and_print(scanner: &mut QScanner, args: &Args) {
+fn do_ping_scan_and_print(scanner: &mut QScanner, args: &Args) -> bool {
     set_print_level(scanner, args);
-    let res: &Vec<QScanResult> = do_ping_scan(scanner, args);
+    let (res, interrupted) = do_ping_scan(scanner, args);
 
-    if (args.printlevel == 0) && (args.printlevel == 1 || args.printlevel == 2) {
+    if console_output(args) && (args.printlevel == 1 || args.printlevel == 2) {
         for r in res {
             if let QScanResult::Ping(pr) = r {
                 if pr.state == QScanPingState::Up {
@@ -224,18 +388,263 @@ fn do_ping_scan_and_print(scanner: &mut QScanner, args: &Args) {
             }
         }
     }
+    interrupted
+}
+
+#[doc(hidden)]
+fn print_targets(scanner: &QScanner, args: &Args) {
+    if args.mode == 1 {
+        if args.list_targets {
+            for ip in scanner.get_tagets_ips() {
+                println!("{}", ip);
+            }
+        }
+        println!("{} targets", scanner.get_tagets_ips().len());
+    } else {
+        if args.list_targets {
+            for socket in scanner.enumerate_targets() {
+                println!("{}", socket);
+            }
+        }
+        println!("{} targets", scanner.targets_count());
+    }
 }
 
 #[doc(hidden)]
 fn set_print_level(scanner: &mut QScanner, args: &Args) {
+    if !console_output(args) {
+        scanner.set_print_mode(QSPrintMode::NonRealTime);
+        return;
+    }
+
     match args.printlevel {
         1 | 2 => scanner.set_print_mode(QSPrintMode::NonRealTime),
         3 => scanner.set_print_mode(QSPrintMode::RealTime),
         4 => scanner.set_print_mode(QSPrintMode::RealTimeAll),
+        5 => scanner.set_print_mode(QSPrintMode::RealTimeVerbose),
         _ => {
-            panic!("Unknown print mode {} (allowed 0-4)", args.printlevel);
+            panic!("Unknown print mode {} (allowed 0-5)", args.printlevel);
+        }
+    }
+}
+
+/// Console output is disabled when results are written to stdout with --output
+#[doc(hidden)]
+fn console_output(args: &Args) -> bool {
+    args.output.is_none() || args.output_file.is_some()
+}
+
+#[doc(hidden)]
+fn write_output(scanner: &QScanner, args: &Args, format: &OutputFormat) {
+    let out = match format {
+        OutputFormat::Json => {
+            let results = scanner
+                .get_last_results()
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            serde_json::to_string(results).unwrap()
+        }
+        OutputFormat::Grepable => grepable_output(
+            scanner
+                .get_last_results()
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        ),
+        OutputFormat::Csv => {
+            let mut csv = Vec::new();
+            let results = scanner
+                .get_last_results()
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            qscan::write_results_csv(&mut csv, results).unwrap();
+            // No trailing new line, like the other formats
+            csv.pop();
+            String::from_utf8(csv).unwrap()
+        }
+        OutputFormat::Xml => xml_output(
+            scanner
+                .get_last_results()
+                .map(Vec::as_slice)
+                .unwrap_or_default(),
+        ),
+    };
+
+    if let Some(path) = &args.output_file {
+        if let Err(e) = File::create(path).and_then(|mut f| f.write_all(out.as_bytes())) {
+            eprintln!("Error writing results in {}: {}", path.to_str().unwrap(), e);
+        }
+    } else {
+        println!("{}", out);
+    }
+}
+
+/// Format the results like nmap grepable output (-oG), one line per host:
+/// `Host: 1.2.3.4 () Ports: 22/open/tcp//ssh//, 80/open/tcp//http//`.
+/// Closed ports are omitted, ping results are reported as `Status: Up|Down`.
+#[doc(hidden)]
+fn grepable_output(results: &[QScanResult]) -> String {
+    let mut hosts: BTreeMap<IpAddr, Vec<(u16, &str, &str)>> = BTreeMap::new();
+    let mut status: BTreeMap<IpAddr, &str> = BTreeMap::new();
+
+    for r in results {
+        match r {
+            QScanResult::TcpConnect(r) => {
+                let state = match r.state {
+                    QScanTcpConnectState::Open => "open",
+                    QScanTcpConnectState::Filtered => "filtered",
+                    QScanTcpConnectState::Close => continue,
+                };
+                hosts
+                    .entry(r.target.ip())
+                    .or_default()
+                    .push((r.target.port(), state, "tcp"));
+            }
+            QScanResult::Udp(r) => {
+                let state = match r.state {
+                    QScanUdpState::Open => "open",
+                    QScanUdpState::OpenFiltered => "open|filtered",
+                    QScanUdpState::Close => continue,
+                };
+                hosts
+                    .entry(r.target.ip())
+                    .or_default()
+                    .push((r.target.port(), state, "udp"));
+            }
+            QScanResult::Ping(r) => {
+                let state = match r.state {
+                    QScanPingState::Up => "Up",
+                    QScanPingState::Down => "Down",
+                };
+                status.insert(r.target, state);
+            }
+        }
+    }
+
+    let mut lines: Vec<String> = status
+        .iter()
+        .map(|(ip, state)| format!("Host: {} () Status: {}", ip, state))
+        .collect();
+
+    for (ip, mut ports) in hosts {
+        ports.sort_unstable();
+        let ports = ports
+            .iter()
+            .map(|(port, state, proto)| {
+                format!(
+                    "{}/{}/{}//{}//",
+                    port,
+                    state,
+                    proto,
+                    qscan::service_name(*port).unwrap_or("")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        lines.push(format!("Host: {} () Ports: {}", ip, ports));
+    }
+
+    lines.join("\n")
+}
+
+/// Format the results like a minimal nmap XML output (-oX), with a `<host>`
+/// element for each address listing the state of its scanned ports.
+#[doc(hidden)]
+fn xml_output(results: &[QScanResult]) -> String {
+    // For each host: ping status, PTR name and (port, protocol, state)
+    type Host<'a> = (
+        Option<&'a str>,
+        Option<&'a str>,
+        Vec<(u16, &'a str, &'a str)>,
+    );
+    let mut hosts: BTreeMap<IpAddr, Host> = BTreeMap::new();
+
+    for r in results {
+        match r {
+            QScanResult::TcpConnect(r) => {
+                let state = match r.state {
+                    QScanTcpConnectState::Open => "open",
+                    QScanTcpConnectState::Filtered => "filtered",
+                    QScanTcpConnectState::Close => "closed",
+                };
+                let host = hosts.entry(r.target.ip()).or_default();
+                host.1 = host.1.or(r.hostname.as_deref());
+                host.2.push((r.target.port(), "tcp", state));
+            }
+            QScanResult::Udp(r) => {
+                let state = match r.state {
+                    QScanUdpState::Open => "open",
+                    QScanUdpState::OpenFiltered => "open|filtered",
+                    QScanUdpState::Close => "closed",
+                };
+                hosts
+                    .entry(r.target.ip())
+                    .or_default()
+                    .2
+                    .push((r.target.port(), "udp", state));
+            }
+            QScanResult::Ping(r) => {
+                hosts.entry(r.target).or_default().0 = Some(match r.state {
+                    QScanPingState::Up => "up",
+                    QScanPingState::Down => "down",
+                });
+            }
         }
     }
+
+    let mut lines = vec![
+        r#"<?xml version="1.0" encoding="UTF-8"?>"#.to_string(),
+        format!(
+            r#"<nmaprun scanner="qsc" version="{}" xmloutputversion="1.05">"#,
+            env!("CARGO_PKG_VERSION")
+        ),
+    ];
+
+    for (ip, (status, hostname, mut ports)) in hosts {
+        // Hosts with an open or closed port replied, even if not pinged
+        let replied = ports.iter().any(|p| p.2 == "open" || p.2 == "closed");
+        let status = status.unwrap_or(if replied { "up" } else { "unknown" });
+        let addrtype = if ip.is_ipv4() { "ipv4" } else { "ipv6" };
+
+        lines.push("<host>".to_string());
+        lines.push(format!(r#"<status state="{}"/>"#, status));
+        lines.push(format!(
+            r#"<address addr="{}" addrtype="{}"/>"#,
+            ip, addrtype
+        ));
+        if let Some(name) = hostname {
+            lines.push(format!(
+                r#"<hostnames><hostname name="{}" type="PTR"/></hostnames>"#,
+                xml_escape(name)
+            ));
+        }
+
+        ports.sort_unstable_by_key(|(port, proto, _)| (*proto, *port));
+        lines.push("<ports>".to_string());
+        for (port, proto, state) in ports {
+            let service = qscan::service_name(port)
+                .map(|s| format!(r#"<service name="{}"/>"#, s))
+                .unwrap_or_default();
+            lines.push(format!(
+                r#"<port protocol="{}" portid="{}"><state state="{}"/>{}</port>"#,
+                proto, port, state, service
+            ));
+        }
+        lines.push("</ports>".to_string());
+        lines.push("</host>".to_string());
+    }
+
+    lines.push("</nmaprun>".to_string());
+    lines.join("\n")
+}
+
+/// Escape the XML special characters of `s`
+#[doc(hidden)]
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
 }
 
 /// Simple async tcp connect scanner
@@ -246,53 +655,79 @@ fn main() {
     #[cfg(feature="debugoff")]
     debugoff::multi_ptraceme_or_die();
 
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Warn);
+    }
+
     let args = Args::parse();
     let batch = args.batch;
     let timeout = args.timeout;
     let mut jf: Option<File> = None;
 
-    if args.json.is_some() {
-        jf = if let Ok(f) = File::create(&args.json.as_ref().unwrap().as_path()) {
+    if let Some(path) = &args.json {
+        jf = if let Ok(f) = File::create(path.as_path()) {
             Some(f)
         } else {
-            panic!(
-                "Cannot create file {}",
-                args.json.unwrap().to_str().unwrap()
-            );
+            panic!("Cannot create file {}", path.to_str().unwrap());
         }
     }
 
-    let mut scanner = QScanner::new(&args.targets, &args.ports);
+    let mut scanner = match QScanner::new(&args.targets, args.ports.as_deref().unwrap_or("")) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    if let Some(n) = args.top_ports {
+        scanner.set_top_ports(n);
+    }
 
     scanner.set_batch(batch);
     scanner.set_timeout_ms(timeout);
 
+    if args.dry_run {
+        print_targets(&scanner, &args);
+        return;
+    }
+
     #[cfg(target_os = "linux")]
     #[cfg(not(debug_assertions))]
     #[cfg(feature="debugoff")]
     debugoff::multi_ptraceme_or_die();
 
-    match args.mode {
+    let interrupted = match args.mode {
         0 => do_tcp_connect_scan_and_print(&mut scanner, &args),
         1 => do_ping_scan_and_print(&mut scanner, &args),
         2 => {
             scanner.set_print_mode(QSPrintMode::NonRealTime);
-            let res: &Vec<QScanResult> = do_ping_scan(&mut scanner, &args);
-
-            let mut ips_up: Vec<IpAddr> = Vec::new();
-
-            for r in res {
-                if let QScanResult::Ping(pr) = r {
-                    if let QScanPingState::Up = pr.state {
-                        ips_up.push(pr.target);
+            let (res, interrupted) = do_ping_scan(&mut scanner, &args);
+
+            // When interrupted, the ping results so far are written to --json
+            // and --output without starting the TCP connect scan
+            if interrupted {
+                true
+            } else {
+                let mut ips_up: Vec<IpAddr> = Vec::new();
+
+                for r in res {
+                    if let QScanResult::Ping(pr) = r {
+                        if let QScanPingState::Up = pr.state {
+                            ips_up.push(pr.target);
+                        }
                     }
                 }
-            }
 
-            scanner.set_vec_targets_addr(ips_up);
-            do_tcp_connect_scan_and_print(&mut scanner, &args);
+                scanner.set_vec_targets_addr(ips_up);
+                do_tcp_connect_scan_and_print(&mut scanner, &args)
+            }
         }
         _ => panic!("Unknown scan mode {}", args.mode),
+    };
+
+    if let Some(format) = &args.output {
+        write_output(&scanner, &args, format);
     }
 
     if let Some(mut f) = jf {
@@ -305,4 +740,8 @@ fn main() {
             );
         }
     }
+
+    if interrupted {
+        std::process::exit(130);
+    }
 }
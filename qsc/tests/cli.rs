@@ -0,0 +1,400 @@
+//
+// qscan
+// Copyright (C) 2022  0xor0ne
+// This program is free software: you can redistribute it and/or modify it under
+// the terms of the GNU General Public License as published by the Free Software
+// Foundation, either version 3 of the License, or (at your option) any later
+// version.
+//
+// This program is distributed in the hope that it will be useful, but WITHOUT ANY
+// WARRANTY; without even the implied warranty of MERCHANTABILITY or FITNESS FOR A
+// PARTICULAR PURPOSE. See the GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License along with
+// this program. If not, see <https://www.gnu.org/licenses/>.
+//
+
+use std::net::{TcpListener, TcpStream};
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde_json::Value;
+
+fn qsc() -> Command {
+    Command::new(env!("CARGO_BIN_EXE_qsc"))
+}
+
+#[test]
+fn output_json_stdout() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(format!("{},{}", open_port, closed_port))
+        .args(["--timeout", "500", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+
+    let json: Value = serde_json::from_slice(&out.stdout).unwrap();
+    let results = json.as_array().unwrap();
+    assert_eq!(results.len(), 2);
+
+    for r in results {
+        assert_eq!(r["type"], "TcpConnect");
+        let target = r["target"].as_str().unwrap();
+        if target == format!("127.0.0.1:{}", open_port) {
+            assert_eq!(r["state"], "OPEN");
+        } else {
+            assert_eq!(target, format!("127.0.0.1:{}", closed_port));
+            assert_eq!(r["state"], "CLOSE");
+        }
+    }
+}
+
+#[test]
+fn output_json_file() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let path = std::env::temp_dir().join(format!("qsc_output_{}.json", std::process::id()));
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(open_port.to_string())
+        .args(["--output", "json", "--output-file"])
+        .arg(&path)
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+
+    // Console output is kept when writing results to a file
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout).trim(),
+        format!("127.0.0.1:{}", open_port)
+    );
+
+    let json: Value = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    let results = json.as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["type"], "TcpConnect");
+    assert_eq!(results[0]["target"], format!("127.0.0.1:{}", open_port));
+    assert_eq!(results[0]["state"], "OPEN");
+    assert!(results[0]["latency_ms"].as_f64().is_some());
+}
+
+#[test]
+fn dry_run() {
+    let out = qsc()
+        .args(["--targets", "10.0.0.0/30", "--ports", "80,443", "--dry-run"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(String::from_utf8(out.stdout).unwrap(), "8 targets\n");
+
+    let out = qsc()
+        .args(["--targets", "10.0.0.0/31", "--ports", "80", "--dry-run"])
+        .arg("--list-targets")
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        "10.0.0.0:80\n10.0.0.1:80\n2 targets\n"
+    );
+}
+
+#[test]
+fn output_grepable() {
+    let first = TcpListener::bind("127.0.0.1:0").unwrap();
+    let second = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut ports = [
+        first.local_addr().unwrap().port(),
+        second.local_addr().unwrap().port(),
+    ];
+    ports.sort_unstable();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(format!("{},{},{}", ports[1], closed_port, ports[0]))
+        .args(["--timeout", "500", "--output", "grepable"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert_eq!(
+        stdout,
+        format!(
+            "Host: 127.0.0.1 () Ports: {}/open/tcp////, {}/open/tcp////\n",
+            ports[0], ports[1]
+        )
+    );
+}
+
+#[test]
+fn output_csv() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(format!("{},{}", open_port, closed_port))
+        .args(["--timeout", "500", "--output", "csv"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let mut lines = stdout.lines();
+    assert_eq!(lines.next(), Some("ip,port,state,latency_ms"));
+
+    let mut rows: Vec<Vec<&str>> = lines.map(|l| l.split(',').collect()).collect();
+    rows.sort_by_key(|r| r[2]);
+    assert_eq!(rows.len(), 2);
+    assert_eq!(
+        rows[0],
+        ["127.0.0.1", &closed_port.to_string(), "CLOSE", ""]
+    );
+    assert_eq!(rows[1][..3], ["127.0.0.1", &open_port.to_string(), "OPEN"]);
+    assert!(rows[1][3].parse::<f64>().is_ok());
+}
+
+/// Check that the tags of `xml` are balanced and return the names of the
+/// elements in document order
+fn xml_elements(xml: &str) -> Vec<String> {
+    let mut stack = Vec::new();
+    let mut elements = Vec::new();
+
+    for tag in xml.split('<').skip(1) {
+        let tag = &tag[..tag.find('>').expect("unterminated tag")];
+        if tag.starts_with('?') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            assert_eq!(stack.pop().as_deref(), Some(name), "unbalanced </{}>", name);
+        } else {
+            let name = tag.split_whitespace().next().unwrap().trim_end_matches('/');
+            elements.push(name.to_string());
+            if !tag.ends_with('/') {
+                stack.push(name.to_string());
+            }
+        }
+    }
+    assert!(stack.is_empty(), "unclosed elements {:?}", stack);
+
+    elements
+}
+
+#[test]
+fn output_xml() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(format!("{},{}", open_port, closed_port))
+        .args(["--timeout", "500", "--output", "xml"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    assert!(stdout.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<nmaprun "));
+    let elements = xml_elements(&stdout);
+    assert_eq!(elements.iter().filter(|e| *e == "host").count(), 1);
+    assert_eq!(elements.iter().filter(|e| *e == "port").count(), 2);
+    assert!(stdout.contains(r#"<address addr="127.0.0.1" addrtype="ipv4"/>"#));
+    assert!(stdout.contains(&format!(
+        r#"<port protocol="tcp" portid="{}"><state state="open"/></port>"#,
+        open_port
+    )));
+    assert!(stdout.contains(&format!(
+        r#"<port protocol="tcp" portid="{}"><state state="closed"/></port>"#,
+        closed_port
+    )));
+}
+
+#[test]
+fn printlevel_verbose() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1,127.0.0.2", "--ports"])
+        .arg(format!("{},{}", open_port, closed_port))
+        .args(["--timeout", "500", "--printlevel", "5"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+
+    let stdout = String::from_utf8(out.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines.contains(&format!("127.0.0.1:{}:CLOSE", closed_port).as_str()));
+    assert!(lines
+        .iter()
+        .any(|l| l.starts_with(&format!("127.0.0.1:{}:OPEN:", open_port)) && l.ends_with("ms")));
+}
+
+#[cfg(unix)]
+#[test]
+fn printlevel_at_the_end() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+    let closed = TcpListener::bind("127.0.0.1:0").unwrap();
+    let closed_port = closed.local_addr().unwrap().port();
+    drop(closed);
+
+    let run = |level: &str| {
+        let out = qsc()
+            .args(["--targets", "127.0.0.1", "--ports"])
+            .arg(format!("{},{}", open_port, closed_port))
+            .args(["--timeout", "500", "--printlevel", level])
+            .output()
+            .unwrap();
+        assert!(out.status.success());
+        let mut lines: Vec<String> = String::from_utf8(out.stdout)
+            .unwrap()
+            .lines()
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    };
+
+    assert_eq!(run("1"), [format!("127.0.0.1:{}", open_port)]);
+    let mut expected = vec![
+        format!("127.0.0.1:{}:OPEN", open_port),
+        format!("127.0.0.1:{}:CLOSED", closed_port),
+    ];
+    expected.sort();
+    assert_eq!(run("2"), expected);
+}
+
+#[test]
+fn sigint_prints_partial_results() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let open_port = listener.local_addr().unwrap().port();
+
+    // Once the accept queue is full new connections hang until the timeout
+    let full = TcpListener::bind("127.0.0.1:0").unwrap();
+    let full_addr = full.local_addr().unwrap();
+    let mut queued = Vec::new();
+    while let Ok(s) = TcpStream::connect_timeout(&full_addr, Duration::from_millis(200)) {
+        queued.push(s);
+        assert!(queued.len() < 10000, "accept queue never filled");
+    }
+
+    let start = Instant::now();
+    let child = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(format!("{},{}", open_port, full_addr.port()))
+        .args(["--timeout", "30000", "--printlevel", "1"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    thread::sleep(Duration::from_millis(1000));
+    let kill = Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .unwrap();
+    assert!(kill.success());
+
+    let out = child.wait_with_output().unwrap();
+    assert!(start.elapsed() < Duration::from_secs(10));
+    assert_eq!(out.status.code(), Some(130));
+    assert_eq!(
+        String::from_utf8(out.stdout).unwrap(),
+        format!("127.0.0.1:{}\n", open_port)
+    );
+    assert!(String::from_utf8(out.stderr)
+        .unwrap()
+        .contains("Interrupted"));
+}
+
+#[test]
+fn targets_from_stdin() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let run = |input: &'static str| {
+        let mut child = qsc()
+            .args(["--targets", "-", "--ports"])
+            .arg(port.to_string())
+            .args(["--timeout", "500", "--output", "json"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        thread::spawn(move || std::io::Write::write_all(&mut stdin, input.as_bytes()).unwrap());
+        let out = child.wait_with_output().unwrap();
+        assert!(out.status.success());
+        let json: Value = serde_json::from_slice(&out.stdout).unwrap();
+        json.as_array().unwrap().clone()
+    };
+
+    let results = run("127.0.0.1\n\n127.0.0.2\n");
+    assert_eq!(results.len(), 2);
+    let mut targets: Vec<&str> = results
+        .iter()
+        .map(|r| r["target"].as_str().unwrap())
+        .collect();
+    targets.sort_unstable();
+    assert_eq!(
+        targets,
+        [format!("127.0.0.1:{}", port), format!("127.0.0.2:{}", port)]
+    );
+    assert!(results
+        .iter()
+        .any(|r| r["target"] == format!("127.0.0.1:{}", port) && r["state"] == "OPEN"));
+
+    // Empty input
+    assert!(run("").is_empty());
+}
+
+#[test]
+fn threads() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(port.to_string())
+        .args(["--timeout", "500", "--threads", "1", "--output", "json"])
+        .output()
+        .unwrap();
+    assert!(out.status.success());
+    let json: Value = serde_json::from_slice(&out.stdout).unwrap();
+    let results = json.as_array().unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0]["state"], "OPEN");
+
+    let out = qsc()
+        .args(["--targets", "127.0.0.1", "--ports"])
+        .arg(port.to_string())
+        .args(["--threads", "0"])
+        .output()
+        .unwrap();
+    assert!(!out.status.success());
+}
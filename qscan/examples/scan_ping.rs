@@ -19,7 +19,7 @@ use std::net::IpAddr;
 use tokio::runtime::Runtime;
 
 pub fn main() {
-    let mut scanner = QScanner::new("8.8.8.8,127.0.0.1,1.2.3.4", "");
+    let mut scanner = QScanner::new("8.8.8.8,127.0.0.1,1.2.3.4", "").unwrap();
     scanner.set_batch(5000);
     scanner.set_timeout_ms(2000);
 
@@ -52,7 +52,7 @@ pub fn main() {
 
     scanner.set_scan_type(QScanType::TcpConnect);
     scanner.set_vec_targets_addr(ips_up);
-    scanner.set_targets_port("53,80,443,666");
+    scanner.set_targets_port("53,80,443,666").unwrap();
 
     let res: &Vec<QScanResult> = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
 
@@ -18,7 +18,7 @@ use qscan::{QSPrintMode, QScanResult, QScanTcpConnectState, QScanType, QScanner}
 use tokio::runtime::Runtime;
 
 pub fn main() {
-    let mut scanner = QScanner::new("8.8.8.8,127.0.0.1", "53,80,443");
+    let mut scanner = QScanner::new("8.8.8.8,127.0.0.1", "53,80,443").unwrap();
     scanner.set_batch(5000);
     scanner.set_timeout_ms(2000);
     scanner.set_ntries(1);
@@ -18,21 +18,63 @@
 //!
 //! Asynchronous network ports scanning library
 //!
-//! To enable `qscan::QScanTcpConnectResult` serialization:
+//! To derive `serde::Serialize` and `serde::Deserialize` for the results types
+//! (e.g., `qscan::QScanResult`, `qscan::QScanTcpConnectResult`):
 //!
 //! ```text
 //! [dependencies]
-//! qscan = { path = "../qscan", version = "0.5.0" , features = ["serialize"] }
+//! qscan = { path = "../qscan", version = "0.6.0" , features = ["serde"] }
+//! ```
+//!
+//! The `serialize` feature additionally enables JSON helpers (e.g.,
+//! `QScanner::get_last_results_as_json_string`):
+//!
+//! ```text
+//! [dependencies]
+//! qscan = { path = "../qscan", version = "0.6.0" , features = ["serialize"] }
 //! ```
 
+pub use crate::qscanner::diff_results;
+pub use crate::qscanner::filter_ubiquitous_ports;
+pub use crate::qscanner::format_results_ranges;
+pub use crate::qscanner::merge_results;
+pub use crate::qscanner::service_name;
+pub use crate::qscanner::sockiter::SockIter;
+pub use crate::qscanner::write_results_csv;
+#[cfg(feature = "sqlite")]
+pub use crate::qscanner::write_results_sqlite;
+pub use crate::qscanner::BuildError;
+pub use crate::qscanner::CloseMode;
+pub use crate::qscanner::EgressReport;
+pub use crate::qscanner::HostScanResult;
+pub use crate::qscanner::IterOrder;
+pub use crate::qscanner::PortParseError;
+pub use crate::qscanner::ProbeResult;
+pub use crate::qscanner::ProxyConfig;
 pub use crate::qscanner::QSPrintMode;
+pub use crate::qscanner::QScanError;
+pub use crate::qscanner::QScanHttpInfo;
 pub use crate::qscanner::QScanPingResult;
 pub use crate::qscanner::QScanPingState;
 pub use crate::qscanner::QScanResult;
+pub use crate::qscanner::QScanSkipReason;
 pub use crate::qscanner::QScanTcpConnectResult;
 pub use crate::qscanner::QScanTcpConnectState;
+pub use crate::qscanner::QScanTlsInfo;
 pub use crate::qscanner::QScanType;
+pub use crate::qscanner::QScanUdpResult;
+pub use crate::qscanner::QScanUdpState;
+pub use crate::qscanner::QScanUnixResult;
 pub use crate::qscanner::QScanner;
+pub use crate::qscanner::QScannerBuilder;
+pub use crate::qscanner::ResolvePolicy;
+pub use crate::qscanner::RetryBackoff;
+pub use crate::qscanner::Sample;
+pub use crate::qscanner::ScanDiff;
+pub use crate::qscanner::ScanProgress;
+pub use crate::qscanner::ScanStats;
+pub use crate::qscanner::ServiceProbe;
+pub use crate::qscanner::UdpProbe;
 
 /// Module for asynchronous network ports scanning
 pub mod qscanner;
@@ -14,1150 +14,11037 @@
 // this program. If not, see <https://www.gnu.org/licenses/>.
 //
 
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 
-#[cfg(feature = "serialize")]
-use serde::ser::{Serialize, SerializeStruct, Serializer};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "serialize")]
 use serde_json;
 
 use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Ipv6Addr;
 use std::net::SocketAddr;
 use std::net::ToSocketAddrs;
 
 use std::fs::File;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use std::num::NonZeroU8;
-use std::time::Duration;
+use std::ops::{ControlFlow, RangeInclusive};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 
 use tokio::io;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpStream;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::UdpSocket;
+use tokio::net::{TcpSocket, TcpStream};
 use tokio::time;
 use tokio::time::error::Elapsed;
 use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
 
 use itertools::Itertools;
+use log::{error, warn};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
 
-use cidr_utils::cidr::IpCidr;
+use cidr_utils::cidr::{IpCidr, Ipv4Cidr, Ipv6Cidr};
 
-use futures::stream::{FuturesUnordered, StreamExt};
+use futures::future::{self, BoxFuture, Either};
+use futures::stream::{self, FuturesUnordered, Stream, StreamExt};
 
 use trust_dns_resolver::{
     config::{ResolverConfig, ResolverOpts},
-    Resolver,
+    error::ResolveError,
+    Resolver, TokioAsyncResolver,
 };
 
 /// Scanning mode:
 ///
 /// * `TcpConnect`: TCP connect scan;
+/// * `Ping`: ICMP echo scan;
+/// * `Udp`: UDP probe scan.
 #[derive(Debug)]
 pub enum QScanType {
     TcpConnect,
     Ping,
+    Udp,
 }
 
 /// Printing mode while scanning
 ///
 /// * `NonRealTime`: do not print during async scan
 /// * `RealTime`: print as soon as the result is available
+/// * `RealTimeAll`: print as soon as the result is available, with its state
+/// * `RealTimeVerbose`: print every result as soon as it is available, with
+///   its state and latency (e.g., `127.0.0.1:80:OPEN:0.152ms`)
 #[derive(Debug)]
 pub enum QSPrintMode {
     NonRealTime,
     RealTime,
     RealTimeAll,
+    RealTimeVerbose,
 }
 
-/// Asynchronous network scanner
-#[derive(Debug)]
-pub struct QScanner {
-    ips: Vec<IpAddr>,
-    ports: Vec<u16>,
-    scan_type: QScanType,
-    print_mode: QSPrintMode,
-    batch: u16,
-    to: Duration,
-    tries: NonZeroU8,
-    ping_payload: Vec<u8>,
-    ping_interval: Duration,
-    last_results: Option<Vec<QScanResult>>,
+/// Order of the targets (ip:port pairs) of TCP connect and UDP scans
+///
+/// * `PortMajor`: each port is scanned on all the hosts before the next
+///   port, spreading the load across the hosts. Suited for scanning a
+///   subnet for a few services;
+/// * `HostMajor`: all the ports of a host are scanned before the next host,
+///   hitting a single host with many connections at once. Suited for full
+///   port scans of a few hosts, as the results of each host are complete
+///   early.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IterOrder {
+    PortMajor,
+    HostMajor,
 }
 
-/// Possible states of a TCP connect target
-#[derive(Debug)]
-pub enum QScanResult {
-    TcpConnect(QScanTcpConnectResult),
-    Ping(QScanPingResult),
+/// Subset of the targets scanned, see [QScanner::set_sample]
+///
+/// * `Fraction`: each target is scanned with the given probability, between
+///   0 and 1;
+/// * `Count`: about the given number of targets are scanned, each with the
+///   same probability.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sample {
+    Fraction(f64),
+    Count(u64),
 }
 
-/// Possible states of a TCP connect target
-#[derive(Debug, PartialEq)]
-pub enum QScanTcpConnectState {
-    Open,
-    Close,
+/// Addresses of a domain name which become targets
+///
+/// * `First`: only the first resolved address;
+/// * `AllV4`: all the IPv4 addresses;
+/// * `AllV6`: all the IPv6 addresses;
+/// * `All`: all the resolved addresses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResolvePolicy {
+    First,
+    AllV4,
+    AllV6,
+    All,
 }
 
-/// Result of a TCP Connect Scan for a single target
-#[derive(Debug)]
-pub struct QScanTcpConnectResult {
-    pub target: SocketAddr,
-    pub state: QScanTcpConnectState,
+impl ResolvePolicy {
+    /// Addresses of `ips`, resolved from a domain name, kept by the policy
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::ResolvePolicy;
+    /// use std::net::IpAddr;
+    /// let v4: IpAddr = "127.0.0.1".parse().unwrap();
+    /// let v6: IpAddr = "::1".parse().unwrap();
+    /// assert_eq!(ResolvePolicy::AllV4.select(vec![v6, v4]), vec![v4]);
+    /// ```
+    pub fn select(self, ips: Vec<IpAddr>) -> Vec<IpAddr> {
+        match self {
+            ResolvePolicy::First => ips.into_iter().take(1).collect(),
+            ResolvePolicy::AllV4 => ips.into_iter().filter(IpAddr::is_ipv4).collect(),
+            ResolvePolicy::AllV6 => ips.into_iter().filter(IpAddr::is_ipv6).collect(),
+            ResolvePolicy::All => ips,
+        }
+    }
 }
 
-/// Possible states of a Ping scan taret
-#[derive(Debug, PartialEq)]
-pub enum QScanPingState {
-    Up,
-    Down,
+/// How TCP connect scans close the connections to open ports
+///
+/// * `Graceful`: the connection is shut down with a FIN, leaving the local
+///   socket in TIME_WAIT for a while;
+/// * `Reset`: `SO_LINGER` is set to zero so that the connection is closed
+///   with a RST, avoiding the accumulation of TIME_WAIT sockets (and the
+///   exhaustion of local ports) when scanning many ports from one source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseMode {
+    Graceful,
+    Reset,
 }
 
-/// Result of a ping Scan for a single target
-#[derive(Debug)]
-pub struct QScanPingResult {
-    pub target: IpAddr,
-    pub state: QScanPingState,
+/// Timeout growth across the retries of a single target
+///
+/// * `Fixed`: every try uses the scanner timeout;
+/// * `Linear`: the timeout grows by `step_ms` at each retry;
+/// * `Exponential`: the timeout is multiplied by `factor` at each retry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetryBackoff {
+    Fixed,
+    Linear { step_ms: u64 },
+    Exponential { factor: u32 },
 }
 
-#[derive(Debug, Clone)]
-struct QScanError {
-    msg: String,
-    sock: SocketAddr,
+impl RetryBackoff {
+    /// Timeout of try number `ntry` (starting from 0) given the base timeout
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::RetryBackoff;
+    /// use std::time::Duration;
+    /// let base = Duration::from_millis(100);
+    /// let backoff = RetryBackoff::Linear { step_ms: 50 };
+    /// assert_eq!(backoff.timeout(base, 2), Duration::from_millis(200));
+    /// ```
+    pub fn timeout(&self, base: Duration, ntry: u8) -> Duration {
+        match *self {
+            RetryBackoff::Fixed => base,
+            RetryBackoff::Linear { step_ms } => {
+                base.saturating_add(Duration::from_millis(step_ms.saturating_mul(ntry as u64)))
+            }
+            RetryBackoff::Exponential { factor } => {
+                base.saturating_mul(factor.saturating_pow(ntry as u32))
+            }
+        }
+    }
 }
 
-impl fmt::Display for QScanError {
+/// Callback invoked on each scan result
+struct ResultCallback(Box<dyn Fn(&QScanResult) + Send + Sync>);
+
+impl fmt::Debug for ResultCallback {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "QScanError: {}", self.msg)
+        f.write_str("ResultCallback")
     }
 }
 
-#[cfg(feature = "serialize")]
-impl Serialize for QScanTcpConnectResult {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut s = serializer.serialize_struct("QScanTcpConnectResult", 3)?;
-        s.serialize_field("IP", &self.target.ip())?;
-        s.serialize_field("port", &self.target.port())?;
-        match self.state {
-            QScanTcpConnectState::Open => {
-                s.serialize_field("state", "OPEN")?;
-            }
-            QScanTcpConnectState::Close => {
-                s.serialize_field("state", "CLOSE")?;
-            }
-        }
-        s.end()
+/// Callback of [QScanner::set_on_result_control]
+type ResultControlFn = dyn Fn(&QScanResult) -> ControlFlow<()> + Send + Sync;
+
+/// Callback invoked on each scan result, which can stop the scan, see
+/// [QScanner::set_on_result_control]
+struct ResultControl(Box<ResultControlFn>);
+
+impl fmt::Debug for ResultControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ResultControl")
     }
 }
 
-#[cfg(feature = "serialize")]
-impl Serialize for QScanPingResult {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        let mut s = serializer.serialize_struct("QScanTcpConnectResult", 3)?;
-        s.serialize_field("IP", &self.target)?;
-        match self.state {
-            QScanPingState::Up => {
-                s.serialize_field("state", "UP")?;
-            }
-            QScanPingState::Down => {
-                s.serialize_field("state", "DOWN")?;
-            }
-        }
-        s.end()
-    }
+/// Matcher of the replies to a UDP probe
+type UdpMatcher = Box<dyn Fn(&[u8]) -> bool + Send + Sync>;
+
+/// Protocol specific probe of a UDP scan: the `payload` sent to the target
+/// and a matcher of the reply that confirms the service. See
+/// [QScanner::set_udp_probes].
+pub struct UdpProbe {
+    payload: Vec<u8>,
+    matcher: UdpMatcher,
 }
 
-#[cfg(feature = "serialize")]
-impl Serialize for QScanResult {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: Serializer,
-    {
-        match self {
-            QScanResult::TcpConnect(x) => x.serialize(serializer),
-            QScanResult::Ping(x) => x.serialize(serializer),
-        }
+impl fmt::Debug for UdpProbe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UdpProbe")
+            .field("payload", &self.payload)
+            .finish_non_exhaustive()
     }
 }
 
-/// Defaults
-const SCAN_TYPE: QScanType = QScanType::TcpConnect;
-const PRINT_MODE: QSPrintMode = QSPrintMode::NonRealTime;
-const BATCH_DEF: u16 = 2500;
-const TIMEOUT_DEF: u64 = 1000;
-const TRIES_DEF: u8 = 1;
-const PING_INTERVAL_DEF: u64 = 1000;
-
-impl QScanner {
-    /// Create a new QScanner
-    ///
-    /// # Arguments
-    ///
-    /// * `addresses` - IPs string, comma separated and CIDR notation
-    /// * `ports` - ports string, comma separated and ranges
+impl UdpProbe {
+    /// Probe sending `payload` and accepting the replies for which `matcher`
+    /// returns true
     ///
     /// # Examples
     ///
     /// ```
-    /// use qscan::qscanner::QScanner;
-    /// let scanner1 = QScanner::new("127.0.0.1", "80");
-    /// let scanner2 = QScanner::new("127.0.0.1,127.0.1.0/24", "80,443,1024-2048");
+    /// use qscan::UdpProbe;
+    /// let probe = UdpProbe::new(b"ping", Box::new(|reply| reply == b"pong"));
+    /// assert!(probe.matches(b"pong"));
+    /// assert!(!probe.matches(b"ping"));
     /// ```
-    ///
-    pub fn new(addresses: &str, ports: &str) -> Self {
+    pub fn new(payload: &[u8], matcher: UdpMatcher) -> Self {
         Self {
-            ips: addresses_parse(addresses),
-            ports: ports_parse(ports),
-            scan_type: SCAN_TYPE,
-            print_mode: PRINT_MODE,
-            batch: BATCH_DEF,
-            to: Duration::from_millis(TIMEOUT_DEF),
-            tries: NonZeroU8::new(std::cmp::max(TRIES_DEF, 1)).unwrap(),
-            ping_payload: vec![0; 56],
-            ping_interval: Duration::from_millis(PING_INTERVAL_DEF),
-            last_results: None,
+            payload: Vec::from(payload),
+            matcher,
         }
     }
 
-    pub fn new_from_vecs(ips: Vec<IpAddr>, ports: Vec<u16>) -> Self {
-        Self {
-            ips,
-            ports,
-            scan_type: SCAN_TYPE,
-            print_mode: PRINT_MODE,
-            batch: BATCH_DEF,
-            to: Duration::from_millis(TIMEOUT_DEF),
-            tries: NonZeroU8::new(std::cmp::max(TRIES_DEF, 1)).unwrap(),
-            ping_payload: vec![0; 56],
-            ping_interval: Duration::from_millis(PING_INTERVAL_DEF),
-            last_results: None,
-        }
+    /// DNS probe: a standard query for the root name servers, matched by any
+    /// response carrying the same id
+    pub fn dns() -> Self {
+        let mut payload = vec![
+            0x71, 0x73, // id
+            0x01, 0x00, // standard query, recursion desired
+            0x00, 0x01, // one question
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        payload.extend_from_slice(&[0x00, 0x00, 0x02, 0x00, 0x01]); // . NS IN
+
+        Self::new(
+            &payload,
+            Box::new(|reply| {
+                reply.len() >= 12 && reply[..2] == [0x71, 0x73] && reply[2] & 0x80 != 0
+            }),
+        )
     }
 
-    /// Set the scanner type
-    pub fn set_scan_type(&mut self, scan_type: QScanType) {
-        self.scan_type = scan_type;
+    /// NTP probe: a version 3 client request, matched by a server (mode 4)
+    /// response
+    pub fn ntp() -> Self {
+        let mut payload = vec![0u8; 48];
+        payload[0] = 0x1b;
+
+        Self::new(
+            &payload,
+            Box::new(|reply| reply.len() >= 48 && reply[0] & 0x07 == 4),
+        )
     }
 
-    /// Set the results printing mode
-    pub fn set_print_mode(&mut self, print_mode: QSPrintMode) {
-        self.print_mode = print_mode;
+    /// Payload sent to the targets
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
     }
 
-    /// Set the number of parallel scans
-    pub fn set_batch(&mut self, batch: u16) {
-        self.batch = batch;
+    /// True if `reply` confirms the service
+    pub fn matches(&self, reply: &[u8]) -> bool {
+        (self.matcher)(reply)
     }
+}
 
-    /// Set the scan timeout for each target
-    pub fn set_timeout_ms(&mut self, to_ms: u64) {
-        self.to = Duration::from_millis(to_ms);
+/// Probe set with [QScanner::set_service_probe]
+struct ServiceProbeBox(Box<dyn ServiceProbe>);
+
+impl fmt::Debug for ServiceProbeBox {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ServiceProbe")
     }
+}
 
-    /// Set how many retries for each target
-    /// If `ntries` is 0, it is converted to 1
-    pub fn set_ntries(&mut self, ntries: u8) {
-        self.tries = NonZeroU8::new(std::cmp::max(ntries, 1)).unwrap();
+/// Buffered output of the real-time print modes, see
+/// [QScanner::set_output_flush_interval]
+struct OutputBuffer {
+    writer: std::io::BufWriter<Box<dyn std::io::Write + Send>>,
+    interval: Duration,
+    lines: usize,
+    flushed: Instant,
+}
+
+impl OutputBuffer {
+    fn new(writer: Box<dyn std::io::Write + Send>, interval: Duration) -> Self {
+        Self {
+            writer: std::io::BufWriter::new(writer),
+            interval,
+            lines: 0,
+            flushed: Instant::now(),
+        }
     }
 
-    /// Set ping payload
-    pub fn set_ping_payload(&mut self, payload: &[u8]) {
-        self.ping_payload = Vec::from(payload);
+    /// Write `line`, flushing the buffered lines if there are enough of them
+    /// or the flush interval elapsed
+    fn write_line(&mut self, line: fmt::Arguments) {
+        use std::io::Write;
+        let _ = writeln!(self.writer, "{}", line);
+        self.lines += 1;
+        if self.lines >= OUTPUT_FLUSH_LINES || self.flushed.elapsed() >= self.interval {
+            self.flush();
+        }
     }
 
-    /// Set ping interval in ms
-    pub fn set_ping_interval_ms(&mut self, ping_int_ms: u64) {
-        self.ping_interval = Duration::from_millis(ping_int_ms);
+    fn flush(&mut self) {
+        use std::io::Write;
+        let _ = self.writer.flush();
+        self.lines = 0;
+        self.flushed = Instant::now();
     }
+}
 
-    pub fn get_last_results(&self) -> Option<&Vec<QScanResult>> {
-        match &self.last_results {
-            Some(res) => Some(res),
-            None => None,
+impl fmt::Debug for OutputBuffer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("OutputBuffer")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// Appended record of the scanned targets, see [QScanner::set_audit_log]
+struct AuditLog {
+    writer: std::io::BufWriter<File>,
+}
+
+impl AuditLog {
+    /// Record the outcome of the scan of a target
+    fn record(&mut self, r: &QScanResult) {
+        use std::io::Write;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let (ip, port, _) = result_fields(r);
+        let res = writeln!(
+            self.writer,
+            "{}.{:03} {} {} {}",
+            now.as_secs(),
+            now.subsec_millis(),
+            ip,
+            port.unwrap_or(0),
+            result_state(r)
+        );
+        if let Err(e) = res {
+            error!("cannot write audit log: {}", e);
         }
     }
 
-    /// QScanner caches the results of the latest scan. This function clear the cache.
-    pub fn reset_last_results(&mut self) {
-        if let Some(last_res) = &mut self.last_results {
-            last_res.clear();
-            self.last_results = None;
+    fn flush(&mut self) {
+        use std::io::Write;
+
+        if let Err(e) = self.writer.flush() {
+            error!("cannot write audit log: {}", e);
         }
     }
+}
 
-    /// Return the vector of target IP addresses
-    pub fn get_tagets_ips(&self) -> &Vec<IpAddr> {
-        &self.ips
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("AuditLog")
     }
+}
 
-    /// Return the vector of target ports
-    pub fn get_tagets_ports(&self) -> &Vec<u16> {
-        &self.ports
+/// Connector used by the TLS probe
+#[derive(Clone)]
+struct TlsProbe(tokio_rustls::TlsConnector);
+
+impl fmt::Debug for TlsProbe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("TlsProbe")
     }
+}
 
-    /// Set targets addresses. Old targets are discarded
-    ///
-    /// # Arguments
-    ///
-    /// * `addresses` - IPs string, comma separated and CIDR notation
-    ///
-    pub fn set_targets_addr(&mut self, addresses: &str) {
-        self.ips = addresses_parse(addresses);
+/// State of a TCP connect scan stream
+struct TcpScanState<'a, F> {
+    sock_it: sockiter::SockIter<'a>,
+    /// Host name targets, see [QScanner::set_resolve_at_connect]
+    names: Box<dyn Iterator<Item = (&'a str, u16)> + Send + 'a>,
+    ftrs: FuturesUnordered<F>,
+    token: CancellationToken,
+    /// Progress, with the time and the value of its last report, see
+    /// [QScanner::set_progress_callback]
+    progress: (ScanProgress, Instant, Option<ScanProgress>),
+    /// All the targets were launched
+    drained: bool,
+    batch: usize,
+    controller: Option<adaptive::BatchController>,
+    deadline: Option<Instant>,
+    /// Start and duration of the ramp-up, see [QScanner::set_ramp_up]
+    ramp_up: Option<(Instant, Duration)>,
+    launched: usize,
+    /// See [QScanner::set_stop_on_first_open]
+    host_tokens: Option<HostTokens>,
+    checkpoint: Option<Checkpoint>,
+    /// See [QScanner::set_max_open_results]
+    max_open: Option<usize>,
+    /// Threshold and timeouts of the hosts which did not respond yet (`None`
+    /// once they did), see [QScanner::set_host_down_threshold]
+    host_timeouts: Option<(usize, HashMap<IpAddr, Option<usize>>)>,
+    /// See [QScanner::set_closed_cache]
+    closed_cache: Option<(&'a HashSet<SocketAddr>, Instant)>,
+}
+
+/// Tokens cancelled once a port of the host is open, by address or, for
+/// the host name targets, by name, see [QScanner::set_stop_on_first_open]
+#[derive(Default)]
+struct HostTokens {
+    ips: HashMap<IpAddr, CancellationToken>,
+    names: HashMap<String, CancellationToken>,
+}
+
+impl HostTokens {
+    /// Token of the host of `target`, if it is a socket or a host name
+    fn get(&mut self, target: ScanTarget) -> Option<CancellationToken> {
+        match target {
+            ScanTarget::Socket(socket) => Some(self.ips.entry(socket.ip()).or_default().clone()),
+            ScanTarget::Name(name, _) => match self.names.get(name) {
+                Some(token) => Some(token.clone()),
+                None => {
+                    let token = CancellationToken::new();
+                    self.names.insert(name.to_string(), token.clone());
+                    Some(token)
+                }
+            },
+            ScanTarget::Down(_) | ScanTarget::Cached(_) | ScanTarget::HostOpen(_) => None,
+        }
     }
 
-    /// Set targets port. Old targets are discarded
-    ///
-    /// # Arguments
-    ///
-    /// * `ports` - ports string, comma separated and ranges
-    ///
-    pub fn set_targets_port(&mut self, ports: &str) {
-        self.ports = ports_parse(ports);
+    /// Cancel the token of the host of `r`
+    fn stop(&self, r: &QScanTcpConnectResult) {
+        let name = r.hostname.as_ref().and_then(|name| self.names.get(name));
+        if let Some(token) = name.or_else(|| self.ips.get(&r.target.ip())) {
+            token.cancel();
+        }
     }
+}
 
-    /// Set targets. Old targets are discarded
-    ///
-    /// # Arguments
-    ///
-    /// * `addresses` - IPs string, comma separated and CIDR notation
-    /// * `ports` - ports string, comma separated and ranges
-    ///
-    pub fn set_targets(&mut self, addresses: &str, ports: &str) {
-        self.ips = addresses_parse(addresses);
-        self.ports = ports_parse(ports);
+/// Writer of the completed targets, see [QScanner::set_checkpoint_file]
+struct Checkpoint {
+    writer: std::io::BufWriter<File>,
+    flushed: Instant,
+}
+
+impl Checkpoint {
+    fn open(path: &Path) -> Option<Self> {
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+        {
+            Ok(f) => Some(Self {
+                writer: std::io::BufWriter::new(f),
+                flushed: Instant::now(),
+            }),
+            Err(e) => {
+                error!("cannot open checkpoint file {:?}: {}", path, e);
+                None
+            }
+        }
     }
 
-    /// Add targets addresses to existing targets
-    ///
-    /// # Arguments
-    ///
-    /// * `addresses` - IPs string, comma separated and CIDR notation
-    ///
-    pub fn add_targets_addr(&mut self, addresses: &str) {
-        self.ips.extend(addresses_parse(addresses));
-        self.ips = self
-            .ips
-            .clone()
-            .into_iter()
-            .unique()
-            .collect::<Vec<IpAddr>>();
+    /// Record `sock` as completed, writing the file at most every
+    /// [CHECKPOINT_INTERVAL]
+    fn record(&mut self, sock: SocketAddr) {
+        use std::io::Write;
+
+        let mut res = writeln!(self.writer, "{}", sock);
+        if res.is_ok() && self.flushed.elapsed() >= CHECKPOINT_INTERVAL {
+            res = self.writer.flush();
+            self.flushed = Instant::now();
+        }
+        if let Err(e) = res {
+            error!("cannot write checkpoint file: {}", e);
+        }
     }
+}
 
-    /// Add targets (ports) to existing targets
-    ///
-    /// # Arguments
-    ///
-    /// * `ports` - ports string, comma separated and ranges
-    ///
-    pub fn add_targets_port(&mut self, ports: &str) {
-        self.ports.extend(ports_parse(ports));
-        self.ports = self
-            .ports
-            .clone()
-            .into_iter()
-            .unique()
-            .collect::<Vec<u16>>();
+/// Target of a TCP connect scan
+#[derive(Debug, Clone, Copy)]
+enum ScanTarget<'a> {
+    Socket(SocketAddr),
+    /// Socket of a host considered down, not scanned, see
+    /// [QScanner::set_host_down_threshold]
+    Down(SocketAddr),
+    /// Socket known closed, not scanned, see [QScanner::set_closed_cache]
+    Cached(SocketAddr),
+    /// Socket of a host already found open, not scanned, see
+    /// [QScanner::set_stop_on_first_open]
+    HostOpen(SocketAddr),
+    /// Host name and port, see [QScanner::set_resolve_at_connect]
+    Name(&'a str, u16),
+}
+
+impl<'a, F> TcpScanState<'a, F> {
+    /// Maximum number of targets scanned in parallel
+    fn limit(&self) -> usize {
+        self.controller.as_ref().map_or(self.batch, |c| c.limit())
     }
 
-    /// Add targets to existing targets
-    ///
-    /// # Arguments
-    ///
-    /// * `addresses` - IPs string, comma separated and CIDR notation
+    /// Delay before connecting to the next target: the first `batch`
+    /// targets are spread over the ramp-up
+    fn next_delay(&mut self) -> Duration {
+        let delay = match self.ramp_up {
+            Some((start, ramp_up)) if self.launched < self.batch => {
+                let at = start + ramp_up.mul_f64(self.launched as f64 / self.batch as f64);
+                at.saturating_duration_since(Instant::now())
+            }
+            _ => Duration::ZERO,
+        };
+        self.launched += 1;
+        delay
+    }
+
+    /// Next target to scan, `None` once the deadline passed or enough open
+    /// targets were found
+    fn next_target(&mut self) -> Option<ScanTarget<'a>> {
+        if self.max_open_reached() {
+            return None;
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => None,
+            _ => match self.sock_it.next() {
+                Some(socket) => Some(ScanTarget::Socket(socket)),
+                None => {
+                    let name = self.names.next();
+                    self.drained = name.is_none();
+                    name.map(|(name, port)| ScanTarget::Name(name, port))
+                }
+            },
+        }
+    }
+
+    /// Whether the maximum number of open targets was found
+    fn max_open_reached(&self) -> bool {
+        self.max_open
+            .is_some_and(|max| self.progress.0.open_found >= max as u64)
+    }
+
+    /// Next target to scan with its start delay and the token stopping its
+    /// scan. Targets of hosts already found open are skipped.
+    fn next_launch(&mut self) -> Option<(ScanTarget<'a>, Duration, Option<CancellationToken>)> {
+        let target = self.next_target()?;
+        if let ScanTarget::Socket(socket) = target {
+            if self.cached_closed(socket) {
+                return Some((ScanTarget::Cached(socket), Duration::ZERO, None));
+            }
+            if self.host_down(socket.ip()) {
+                return Some((ScanTarget::Down(socket), Duration::ZERO, None));
+            }
+        }
+        let token = self.host_tokens.as_mut().and_then(|t| t.get(target));
+        if token.as_ref().is_some_and(|t| t.is_cancelled()) {
+            return match target {
+                ScanTarget::Socket(socket) => {
+                    Some((ScanTarget::HostOpen(socket), Duration::ZERO, None))
+                }
+                // Host names are still resolved, the skipped result
+                // reporting their address
+                _ => Some((target, Duration::ZERO, token)),
+            };
+        }
+        Some((target, self.next_delay(), token))
+    }
+
+    /// Whether `socket` is in the closed cache, until it expires
+    fn cached_closed(&self, socket: SocketAddr) -> bool {
+        match self.closed_cache {
+            Some((cache, until)) => Instant::now() < until && cache.contains(&socket),
+            None => false,
+        }
+    }
+
+    /// Whether the host `ip` is considered down
+    fn host_down(&self, ip: IpAddr) -> bool {
+        match &self.host_timeouts {
+            Some((threshold, hosts)) => matches!(hosts.get(&ip), Some(Some(n)) if n >= threshold),
+            None => false,
+        }
+    }
+
+    /// Account the outcome of a scanned (not skipped) target for the host
+    /// down check
+    fn record_host(&mut self, r: &QScanTcpConnectResult) {
+        if let Some((_, hosts)) = &mut self.host_timeouts {
+            let timeouts = hosts.entry(r.target.ip()).or_insert(Some(0));
+            match r.state {
+                QScanTcpConnectState::Filtered => {
+                    if let Some(n) = timeouts {
+                        *n += 1;
+                    }
+                }
+                _ => *timeouts = None,
+            }
+        }
+    }
+
+    /// Skip the remaining targets of the host of `r`
+    fn stop_host(&mut self, r: &QScanTcpConnectResult) {
+        if let Some(tokens) = &self.host_tokens {
+            tokens.stop(r);
+        }
+    }
+}
+
+/// Progress of a TCP connect scan, see [QScanner::set_progress_callback]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanProgress {
+    /// Number of targets scanned
+    pub completed: u64,
+    /// Number of targets to scan. An estimate while scanning, the targets
+    /// skipped on resume (see [QScanner::resume_from]) or left out by
+    /// sampling (see [QScanner::set_sample]) being counted, and the hosts of
+    /// the targets file (see [QScanner::set_targets_file]) not. Exact in the
+    /// last report of a scan of all the targets.
+    pub total: u64,
+    /// Number of open targets found
+    pub open_found: u64,
+}
+
+/// TCP connect scan results of a single host, see
+/// [QScanner::scan_tcp_connect_by_host]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct HostScanResult {
+    /// Open ports, in the order of the targets ports
+    pub open_ports: Vec<u16>,
+    /// Number of ports refusing the connection
+    pub closed: u64,
+    /// Number of ports not answering within the timeout
+    pub filtered: u64,
+    /// Host name, if reverse lookups are enabled (see
+    /// [QScanner::set_resolve_ptr]) and the host has open ports
+    pub hostname: Option<String>,
+}
+
+/// Summary of a TCP connect scan, see [QScanner::scan_tcp_connect_with_stats]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Number of targets scanned
+    pub attempted: u64,
+    /// Number of open targets
+    pub open: u64,
+    /// Number of targets refusing the connection
+    pub closed: u64,
+    /// Number of targets not answering within the timeout
+    pub filtered: u64,
+    /// Number of targets whose scan failed with any other error, reported
+    /// as closed in the results
+    pub errored: u64,
+    /// Number of targets reported without connecting to them (see
+    /// [QScanTcpConnectResult::skipped]), not counted in the others
+    pub skipped: u64,
+    /// The scan was aborted since none of the probed targets responded,
+    /// see [QScanner::set_require_reachable]
+    pub unreachable: bool,
+    /// Duration of the whole scan
+    pub elapsed: Duration,
+}
+
+/// Outbound connectivity by port, see [QScanner::diagnose_egress]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EgressReport {
+    /// Host the connections were attempted to
+    pub host: IpAddr,
+    /// Ports the connection succeeded on
+    pub permitted: Vec<u16>,
+    /// Ports the connection was refused on, by the host or by a firewall
+    /// rejecting it
+    pub refused: Vec<u16>,
+    /// Ports the connection timed out on, likely dropped by a firewall
+    pub blocked: Vec<u16>,
+}
+
+impl ScanStats {
+    /// Account the outcome of a single target
+    fn record(&mut self, r: &QScanTcpConnectResult, errored: bool) {
+        if r.skipped.is_some() {
+            self.skipped += 1;
+            return;
+        }
+        self.attempted += 1;
+        match r.state {
+            _ if errored => self.errored += 1,
+            QScanTcpConnectState::Open => self.open += 1,
+            QScanTcpConnectState::Close => self.closed += 1,
+            QScanTcpConnectState::Filtered => self.filtered += 1,
+        }
+    }
+}
+
+/// Callback periodically invoked with the scan progress
+struct ProgressCallback {
+    interval: Duration,
+    callback: Box<dyn Fn(ScanProgress) + Send + Sync>,
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ProgressCallback")
+            .field("interval", &self.interval)
+            .finish()
+    }
+}
+
+/// SOCKS5 proxy used by TCP connect scans, see [QScanner::set_proxy]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// Proxy address
+    pub addr: SocketAddr,
+    /// Optional username and password
+    pub credentials: Option<(String, String)>,
+}
+
+impl ProxyConfig {
+    /// Proxy without authentication
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            credentials: None,
+        }
+    }
+
+    /// Proxy with username/password authentication
+    pub fn with_credentials(addr: SocketAddr, username: &str, password: &str) -> Self {
+        Self {
+            addr,
+            credentials: Some((username.to_string(), password.to_string())),
+        }
+    }
+}
+
+/// Asynchronous network scanner
+///
+/// `QScanner` is `Send` and `Sync`: a configured scanner can be shared with
+/// an [Arc](std::sync::Arc) to drive independent scans (e.g.,
+/// [QScanner::scan_tcp_connect_stream], [QScanner::check_socket]) from
+/// several tasks at the same time. Scans caching their results (e.g.,
+/// [QScanner::scan_tcp_connect]) need `&mut self`.
+///
+/// # Examples
+///
+/// ```
+/// use qscan::qscanner::QScanner;
+/// use std::sync::Arc;
+/// use tokio::runtime::Runtime;
+/// let scanner = Arc::new(QScanner::new("127.0.0.1", "80,443").unwrap());
+/// let rt = Runtime::new().unwrap();
+/// let tasks: Vec<_> = ["127.0.0.1:80", "127.0.0.1:443"]
+///     .into_iter()
+///     .map(|target| {
+///         let scanner = scanner.clone();
+///         rt.spawn(async move { scanner.check_socket(target.parse().unwrap()).await })
+///     })
+///     .collect();
+/// for task in tasks {
+///     rt.block_on(task).unwrap();
+/// }
+/// ```
+#[derive(Debug)]
+pub struct QScanner {
+    ips: Vec<IpAddr>,
+    unresolved: Vec<String>,
+    scopes: HashMap<Ipv6Addr, u32>,
+    groups: Vec<TargetGroup>,
+    targets_file: Option<PathBuf>,
+    targets_cidr: Option<IpCidr>,
+    ports: Vec<u16>,
+    scan_type: QScanType,
+    print_mode: QSPrintMode,
+    batch: u16,
+    to: Duration,
+    tries: NonZeroU8,
+    retry_backoff: RetryBackoff,
+    port_timeouts: HashMap<u16, Duration>,
+    retry_on_refused: bool,
+    parallel_tries: bool,
+    retry_jitter: Duration,
+    jitter_rng: std::sync::Mutex<StdRng>,
+    rate_limiter: Option<ratelimit::RateLimiter>,
+    shuffle: bool,
+    shuffle_seed: Option<u64>,
+    sample: Option<Sample>,
+    sample_seed: Option<u64>,
+    iter_order: IterOrder,
+    resolve_policy: ResolvePolicy,
+    resolve_ptr: bool,
+    sort_results: bool,
+    source_addr: Option<IpAddr>,
+    adaptive_batch: bool,
+    /// See [QScanner::set_adaptive_timeout]
+    rtt: Option<std::sync::Mutex<adaptive::RttEstimator>>,
+    deadline: Option<Duration>,
+    ramp_up: Option<Duration>,
+    stop_on_first_open: bool,
+    host_down_threshold: Option<usize>,
+    /// Sockets known closed and the end of their validity, see
+    /// [QScanner::set_closed_cache]
+    closed_cache: Option<(HashSet<SocketAddr>, Instant)>,
+    max_open_results: Option<usize>,
+    resolve_at_connect: bool,
+    named_targets: Vec<String>,
+    warmup: bool,
+    require_reachable: bool,
+    checkpoint_file: Option<PathBuf>,
+    resumed: HashSet<SocketAddr>,
+    close_mode: CloseMode,
+    http_probe: bool,
+    http_ports: Vec<u16>,
+    tls_probe: Option<TlsProbe>,
+    service_probe: Option<ServiceProbeBox>,
+    emfile_events: AtomicU64,
+    local_ports: Option<RangeInclusive<u16>>,
+    /// Offset in the local ports range of the next port to bind
+    next_local_port: AtomicUsize,
+    tcp_fast_open: bool,
+    proxy: Option<ProxyConfig>,
+    ping_payload: Vec<u8>,
+    ping_interval: Duration,
+    udp_payload: Vec<u8>,
+    udp_probes: HashMap<u16, UdpProbe>,
+    max_cidr_hosts: u64,
+    max_targets: u64,
+    resolver_config: Option<(ResolverConfig, ResolverOpts)>,
+    dns_timeout: Option<Duration>,
+    dns_attempts: Option<usize>,
+    dns_concurrency: usize,
+    exclude: Excludes,
+    exclude_ports: HashSet<u16>,
+    port_priority: Vec<u16>,
+    dedup: bool,
+    on_result: Option<ResultCallback>,
+    on_result_control: Option<ResultControl>,
+    output: Option<std::sync::Mutex<OutputBuffer>>,
+    audit_log: Option<std::sync::Mutex<AuditLog>>,
+    on_progress: Option<ProgressCallback>,
+    last_results: Option<Vec<QScanResult>>,
+}
+
+/// Result of a scan for a single target
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(tag = "type"))]
+#[allow(clippy::large_enum_variant)]
+pub enum QScanResult {
+    TcpConnect(QScanTcpConnectResult),
+    Ping(QScanPingResult),
+    Udp(QScanUdpResult),
+}
+
+/// Possible states of a TCP connect target
+///
+/// * `Open`: the connection succeeded;
+/// * `Close`: the connection failed (e.g., it was refused);
+/// * `Filtered`: the connection timed out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "SCREAMING_SNAKE_CASE"))]
+pub enum QScanTcpConnectState {
+    Open,
+    Close,
+    Filtered,
+}
+
+/// Reasons for reporting a TCP connect target without connecting to it
+///
+/// * `HostDown`: its host was considered down, see
+///   [QScanner::set_host_down_threshold].
+/// * `CachedClosed`: it was known closed, see [QScanner::set_closed_cache].
+/// * `HostOpen`: another port of its host was found open, see
+///   [QScanner::set_stop_on_first_open].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "SCREAMING_SNAKE_CASE"))]
+pub enum QScanSkipReason {
+    HostDown,
+    CachedClosed,
+    HostOpen,
+}
+
+/// Result of a TCP Connect Scan for a single target
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QScanTcpConnectResult {
+    pub target: SocketAddr,
+    pub state: QScanTcpConnectState,
+    /// Time taken by the successful connect, `None` if the port is not open.
+    /// Serialized as `latency_ms`, a number of milliseconds.
+    #[cfg_attr(
+        feature = "serde",
+        serde(rename = "latency_ms", with = "latency_ms_serde")
+    )]
+    pub latency: Option<Duration>,
+    /// Host name of the target, see [QScanner::set_resolve_ptr]
+    pub hostname: Option<String>,
+    /// HTTP response of the target, see [QScanner::set_http_probe]
+    pub http: Option<QScanHttpInfo>,
+    /// TLS session with the target, see [QScanner::set_tls_probe]
+    pub tls: Option<QScanTlsInfo>,
+    /// Outcome of the service probe, see [QScanner::set_service_probe]
+    pub probe: Option<ProbeResult>,
+    /// Number of connect tries made until the conclusive one (see
+    /// [QScanner::set_ntries]), e.g. 1 if the first try was refused
+    pub attempts: u8,
+    /// Kind of the error the connection failed with, e.g.
+    /// `ConnectionRefused` or `TimedOut`, `None` if the port is open or the
+    /// target was skipped
+    #[cfg_attr(feature = "serde", serde(with = "error_kind_serde"))]
+    pub error_kind: Option<io::ErrorKind>,
+    /// Why the target was reported in `state` without connecting to it,
+    /// `None` if it was scanned
+    pub skipped: Option<QScanSkipReason>,
+    /// Time the connection to the target completed or failed at, before any
+    /// probe (see [QScanner::set_http_probe]), or the time the target was
+    /// reported at if it was skipped. Serialized as the number of
+    /// milliseconds since the Unix epoch.
+    #[cfg_attr(feature = "serde", serde(with = "unix_ms_serde"))]
+    pub scanned_at: SystemTime,
+}
+
+/// (De)serialization of [QScanTcpConnectResult::error_kind] as the name of
+/// the error kind, e.g. `"ConnectionRefused"`
+#[cfg(feature = "serde")]
+mod error_kind_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::io::ErrorKind;
+
+    const KINDS: &[ErrorKind] = &[
+        ErrorKind::NotFound,
+        ErrorKind::PermissionDenied,
+        ErrorKind::ConnectionRefused,
+        ErrorKind::ConnectionReset,
+        ErrorKind::ConnectionAborted,
+        ErrorKind::NotConnected,
+        ErrorKind::AddrInUse,
+        ErrorKind::AddrNotAvailable,
+        ErrorKind::BrokenPipe,
+        ErrorKind::InvalidInput,
+        ErrorKind::InvalidData,
+        ErrorKind::TimedOut,
+        ErrorKind::UnexpectedEof,
+        ErrorKind::Unsupported,
+        ErrorKind::OutOfMemory,
+        ErrorKind::Other,
+    ];
+
+    pub fn serialize<S: Serializer>(kind: &Option<ErrorKind>, s: S) -> Result<S::Ok, S::Error> {
+        kind.map(|k| format!("{:?}", k)).serialize(s)
+    }
+
+    /// Unknown names (e.g., of the kinds of a newer Rust) become `Other`
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<ErrorKind>, D::Error> {
+        let name: Option<String> = Option::deserialize(d)?;
+        Ok(name.map(|name| {
+            KINDS
+                .iter()
+                .copied()
+                .find(|k| format!("{:?}", k) == name)
+                .unwrap_or(ErrorKind::Other)
+        }))
+    }
+}
+
+/// (De)serialization of [QScanTcpConnectResult::latency] as a number of
+/// milliseconds, e.g. `0.152`
+#[cfg(feature = "serde")]
+mod latency_ms_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(latency: &Option<Duration>, s: S) -> Result<S::Ok, S::Error> {
+        latency.map(|l| l.as_nanos() as f64 / 1e6).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Duration>, D::Error> {
+        let ms: Option<f64> = Option::deserialize(d)?;
+        Ok(ms.map(|ms| Duration::from_nanos((ms.max(0.0) * 1e6).round() as u64)))
+    }
+}
+
+/// (De)serialization of [QScanTcpConnectResult::scanned_at] as the number of
+/// milliseconds since the Unix epoch
+#[cfg(feature = "serde")]
+mod unix_ms_serde {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, s: S) -> Result<S::Ok, S::Error> {
+        let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        (since_epoch.as_millis() as u64).serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<SystemTime, D::Error> {
+        let ms = u64::deserialize(d)?;
+        Ok(UNIX_EPOCH + Duration::from_millis(ms))
+    }
+}
+
+/// Result in the JSON format of [QScanner::get_last_results_as_json_string]
+#[cfg(feature = "serialize")]
+struct JsonResult<'a>(&'a QScanResult);
+
+#[cfg(feature = "serialize")]
+impl Serialize for JsonResult<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        use serde::ser::SerializeStruct;
+
+        let (ip, port, _) = result_fields(self.0);
+        let mut s = serializer.serialize_struct("QScanResult", 3)?;
+        s.serialize_field("IP", &ip)?;
+        if let Some(port) = port {
+            s.serialize_field("port", &port)?;
+        }
+        s.serialize_field("state", result_state(self.0))?;
+        s.end()
+    }
+}
+
+/// Outcome of the TLS probe of an open port
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QScanTlsInfo {
+    /// Negotiated protocol version, e.g. `TLSv1.3`
+    pub version: String,
+    /// Common name of the subject of the leaf certificate
+    pub subject_cn: Option<String>,
+    /// Expiry of the leaf certificate, formatted as `YYYY-MM-DDTHH:MM:SSZ`
+    pub not_after: Option<String>,
+}
+
+/// Outcome of a [ServiceProbe]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ProbeResult {
+    /// Service identified by the probe, if any, e.g. `ssh`
+    pub service: Option<String>,
+    /// Data gathered by the probe, e.g. the banner of the service
+    pub data: Option<String>,
+}
+
+/// Custom probe of the open ports of TCP connect scans, see
+/// [QScanner::set_service_probe]
+///
+/// # Examples
+///
+/// ```
+/// use futures::future::BoxFuture;
+/// use qscan::qscanner::{ProbeResult, ServiceProbe};
+/// use tokio::net::TcpStream;
+///
+/// struct Connected;
+///
+/// impl ServiceProbe for Connected {
+///     fn probe(&self, _stream: TcpStream) -> BoxFuture<'_, ProbeResult> {
+///         Box::pin(async {
+///             ProbeResult {
+///                 service: None,
+///                 data: Some("connected".to_string()),
+///             }
+///         })
+///     }
+/// }
+/// ```
+pub trait ServiceProbe: Send + Sync {
+    /// Probe the connection `stream` to an open port. The connection is
+    /// closed once `stream` is dropped.
+    fn probe(&self, stream: TcpStream) -> BoxFuture<'_, ProbeResult>;
+}
+
+/// Response to the HTTP probe of an open port
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QScanHttpInfo {
+    /// Status line, e.g. `HTTP/1.1 200 OK`
+    pub status_line: String,
+    /// Status code parsed from the status line
+    pub status_code: Option<u16>,
+    /// Value of the `Server:` header, if any
+    pub server: Option<String>,
+}
+
+/// Possible states of a Ping scan taret
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "SCREAMING_SNAKE_CASE"))]
+pub enum QScanPingState {
+    Up,
+    Down,
+}
+
+/// Result of a ping Scan for a single target
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QScanPingResult {
+    pub target: IpAddr,
+    pub state: QScanPingState,
+}
+
+/// Possible states of a UDP scan target
+///
+/// * `Open`: the target replied to the probe;
+/// * `OpenFiltered`: no reply received before the timeout expired;
+/// * `Close`: an ICMP port unreachable has been received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "SCREAMING_SNAKE_CASE"))]
+pub enum QScanUdpState {
+    Open,
+    OpenFiltered,
+    Close,
+}
+
+/// Result of a UDP Scan for a single target
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QScanUdpResult {
+    pub target: SocketAddr,
+    pub state: QScanUdpState,
+}
+
+/// Result of the check of a Unix domain socket, see
+/// [QScanner::check_unix_socket]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct QScanUnixResult {
+    pub path: PathBuf,
+    pub state: QScanTcpConnectState,
+}
+
+/// Successful TCP connect to a single target
+struct TcpOpen {
+    sock: SocketAddr,
+    latency: Duration,
+    scanned_at: SystemTime,
+    http: Option<QScanHttpInfo>,
+    tls: Option<QScanTlsInfo>,
+    probe: Option<ProbeResult>,
+    attempts: u8,
+    hostname: Option<String>,
+}
+
+/// Failed TCP connect scan of a single target
+#[derive(Debug)]
+struct TargetError {
+    error: QScanError,
+    sock: SocketAddr,
+    state: QScanTcpConnectState,
+    attempts: u8,
+    hostname: Option<String>,
+    skipped: Option<QScanSkipReason>,
+    /// `None` if no connection was attempted
+    scanned_at: Option<SystemTime>,
+}
+
+impl TargetError {
+    /// The scan failed with an error other than a refusal or a timeout
+    fn errored(&self) -> bool {
+        if self.skipped.is_some() {
+            return false;
+        }
+        match &self.error {
+            QScanError::Connect { source, .. } => {
+                self.state == QScanTcpConnectState::Close
+                    && source.kind() != io::ErrorKind::ConnectionRefused
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Errors returned by [QScanner]
+///
+/// * `InvalidPorts`: a ports string is malformed;
+/// * `InvalidTargets`: the targets which produced no address (e.g., a
+///   misspelled host name), see [QScanner::validate_inputs];
+/// * `TooManyHosts`: the CIDR `target` has more than `max` hosts,
+///   see [QScanner::validate_inputs];
+/// * `NoTargets`: the targets string has no target, see
+///   [QScanner::validate_inputs];
+/// * `Resolver`: a DNS resolver cannot be created;
+/// * `TooManyOpenFiles`: the limit of open file descriptors was reached;
+/// * `Connect`: the connection to `target` failed;
+/// * `Unreachable`: none of the `probed` targets responded to the
+///   reachability check (see [QScanner::set_require_reachable]);
+/// * `Io`: any other I/O error (e.g., a file cannot be opened).
+#[derive(Debug)]
+pub enum QScanError {
+    InvalidPorts(PortParseError),
+    InvalidTargets(Vec<String>),
+    TooManyHosts {
+        target: String,
+        max: u64,
+    },
+    NoTargets,
+    Resolver(ResolveError),
+    TooManyOpenFiles(io::Error),
+    Connect {
+        target: SocketAddr,
+        source: io::Error,
+    },
+    Unreachable {
+        probed: Vec<SocketAddr>,
+    },
+    Io(io::Error),
+}
+
+impl QScanError {
+    /// Kind of the underlying I/O error, `Other` for the errors which are
+    /// not I/O ones
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            QScanError::TooManyOpenFiles(e) | QScanError::Io(e) => e.kind(),
+            QScanError::Connect { source, .. } => source.kind(),
+            _ => io::ErrorKind::Other,
+        }
+    }
+
+    /// Error of a connection to `target` failed with `e`
+    fn connect(target: SocketAddr, e: io::Error) -> Self {
+        match QScanError::from(e) {
+            QScanError::Io(source) => QScanError::Connect { target, source },
+            e => e,
+        }
+    }
+}
+
+impl fmt::Display for QScanError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QScanError::InvalidPorts(e) => write!(f, "invalid ports: {}", e),
+            QScanError::InvalidTargets(targets) => {
+                write!(f, "invalid targets: {}", targets.join(", "))
+            }
+            QScanError::TooManyHosts { target, max } => {
+                write!(f, "{} exceeds the maximum of {} hosts", target, max)
+            }
+            QScanError::NoTargets => write!(f, "no targets"),
+            QScanError::Resolver(e) => write!(f, "resolver error: {}", e),
+            QScanError::TooManyOpenFiles(e) => write!(f, "too many open files: {}", e),
+            QScanError::Connect { target, source } => {
+                write!(f, "cannot connect to {}: {}", target, source)
+            }
+            QScanError::Unreachable { probed } => {
+                write!(f, "no response from {}", probed.iter().join(", "))
+            }
+            QScanError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for QScanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QScanError::InvalidPorts(e) => Some(e),
+            QScanError::Resolver(e) => Some(e),
+            QScanError::TooManyOpenFiles(e) | QScanError::Io(e) => Some(e),
+            QScanError::Connect { source, .. } => Some(source),
+            QScanError::InvalidTargets(_)
+            | QScanError::TooManyHosts { .. }
+            | QScanError::NoTargets
+            | QScanError::Unreachable { .. } => None,
+        }
+    }
+}
+
+impl From<PortParseError> for QScanError {
+    fn from(e: PortParseError) -> Self {
+        QScanError::InvalidPorts(e)
+    }
+}
+
+impl From<ResolveError> for QScanError {
+    fn from(e: ResolveError) -> Self {
+        QScanError::Resolver(e)
+    }
+}
+
+impl From<io::Error> for QScanError {
+    fn from(e: io::Error) -> Self {
+        if is_too_many_open_files(&e) {
+            QScanError::TooManyOpenFiles(e)
+        } else {
+            QScanError::Io(e)
+        }
+    }
+}
+
+/// Errors returned while parsing a ports string
+///
+/// * `EmptyToken`: a range with a missing bound (e.g., "80-", "-80");
+/// * `InvalidNumber`: a value that is not a valid port number (e.g., "70000", "8o");
+/// * `UnknownService`: a service name not in the built-in table (e.g., "foo");
+/// * `InvalidRange`: a range with more than two components (e.g., "1-2-3");
+/// * `ReversedRange`: a range whose start is greater than its end (e.g., "200-100");
+/// * `UnreadableFile`: a ports file that cannot be read;
+/// * `TooManyPorts`: the ports, counting the duplicates, exceed the given
+///   maximum (e.g., "1-65535" repeated many times).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PortParseError {
+    EmptyToken(String),
+    InvalidNumber(String),
+    UnknownService(String),
+    InvalidRange(String),
+    ReversedRange(u16, u16),
+    UnreadableFile(String),
+    TooManyPorts(usize),
+}
+
+impl fmt::Display for PortParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PortParseError::EmptyToken(t) => write!(f, "empty port in {:?}", t),
+            PortParseError::InvalidNumber(t) => write!(f, "invalid port number {:?}", t),
+            PortParseError::UnknownService(t) => write!(f, "unknown service {:?}", t),
+            PortParseError::InvalidRange(t) => write!(f, "invalid port range {:?}", t),
+            PortParseError::ReversedRange(start, end) => {
+                write!(f, "reversed port range {}-{}", start, end)
+            }
+            PortParseError::UnreadableFile(t) => write!(f, "cannot read ports file {:?}", t),
+            PortParseError::TooManyPorts(max) => write!(f, "more than {} ports", max),
+        }
+    }
+}
+
+impl std::error::Error for PortParseError {}
+
+/// Errors returned by [QScannerBuilder::build]
+///
+/// * `InvalidPorts`: the ports string is malformed;
+/// * `ZeroBatch`: the batch size is 0;
+/// * `ZeroTimeout`: the timeout is 0;
+/// * `ZeroTries`: the number of tries is 0;
+/// * `Scanner`: any other error creating the scanner (see [QScanner::new]).
+#[derive(Debug)]
+pub enum BuildError {
+    InvalidPorts(PortParseError),
+    ZeroBatch,
+    ZeroTimeout,
+    ZeroTries,
+    Scanner(QScanError),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildError::InvalidPorts(e) => write!(f, "invalid ports: {}", e),
+            BuildError::ZeroBatch => write!(f, "batch size must be greater than 0"),
+            BuildError::ZeroTimeout => write!(f, "timeout must be greater than 0"),
+            BuildError::ZeroTries => write!(f, "number of tries must be greater than 0"),
+            BuildError::Scanner(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BuildError::InvalidPorts(e) => Some(e),
+            BuildError::Scanner(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PortParseError> for BuildError {
+    fn from(e: PortParseError) -> Self {
+        BuildError::InvalidPorts(e)
+    }
+}
+
+impl From<QScanError> for BuildError {
+    fn from(e: QScanError) -> Self {
+        match e {
+            QScanError::InvalidPorts(e) => BuildError::InvalidPorts(e),
+            e => BuildError::Scanner(e),
+        }
+    }
+}
+
+/// Builder of a configured [QScanner]
+///
+/// # Examples
+///
+/// ```
+/// use qscan::qscanner::{QScanType, QScannerBuilder};
+/// let scanner = QScannerBuilder::new()
+///     .targets("127.0.0.1")
+///     .ports("80,443")
+///     .batch(100)
+///     .timeout_ms(500)
+///     .tries(2)
+///     .scan_type(QScanType::TcpConnect)
+///     .build()
+///     .unwrap();
+/// assert_eq!(scanner.get_tagets_ports(), &vec![80, 443]);
+/// ```
+#[derive(Debug)]
+pub struct QScannerBuilder {
+    targets: String,
+    ports: String,
+    batch: u16,
+    timeout_ms: u64,
+    tries: u8,
+    scan_type: QScanType,
+    print_mode: QSPrintMode,
+}
+
+impl Default for QScannerBuilder {
+    fn default() -> Self {
+        Self {
+            targets: String::new(),
+            ports: String::new(),
+            batch: BATCH_DEF,
+            timeout_ms: TIMEOUT_DEF,
+            tries: TRIES_DEF,
+            scan_type: SCAN_TYPE,
+            print_mode: PRINT_MODE,
+        }
+    }
+}
+
+impl QScannerBuilder {
+    /// Create a builder with the default settings
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the targets addresses string, see [QScanner::new]
+    pub fn targets(mut self, addresses: &str) -> Self {
+        self.targets = addresses.to_string();
+        self
+    }
+
+    /// Set the targets ports string, see [QScanner::new]
+    pub fn ports(mut self, ports: &str) -> Self {
+        self.ports = ports.to_string();
+        self
+    }
+
+    /// Set the number of parallel scans
+    pub fn batch(mut self, batch: u16) -> Self {
+        self.batch = batch;
+        self
+    }
+
+    /// Set the scan timeout for each target
+    pub fn timeout_ms(mut self, to_ms: u64) -> Self {
+        self.timeout_ms = to_ms;
+        self
+    }
+
+    /// Set how many tries for each target
+    pub fn tries(mut self, tries: u8) -> Self {
+        self.tries = tries;
+        self
+    }
+
+    /// Set the scanner type
+    pub fn scan_type(mut self, scan_type: QScanType) -> Self {
+        self.scan_type = scan_type;
+        self
+    }
+
+    /// Set the results printing mode
+    pub fn print_mode(mut self, print_mode: QSPrintMode) -> Self {
+        self.print_mode = print_mode;
+        self
+    }
+
+    /// Validate the settings and build the scanner
+    ///
+    /// # Errors
+    ///
+    /// Returns a [BuildError] if the ports string is malformed, a setting
+    /// is 0 or the scanner cannot be created.
+    pub fn build(self) -> Result<QScanner, BuildError> {
+        if self.batch == 0 {
+            return Err(BuildError::ZeroBatch);
+        }
+        if self.timeout_ms == 0 {
+            return Err(BuildError::ZeroTimeout);
+        }
+        if self.tries == 0 {
+            return Err(BuildError::ZeroTries);
+        }
+
+        let mut scanner = QScanner::new(&self.targets, &self.ports)?;
+        scanner.set_batch(self.batch);
+        scanner.set_timeout_ms(self.timeout_ms);
+        scanner.set_ntries(self.tries);
+        scanner.set_scan_type(self.scan_type);
+        scanner.set_print_mode(self.print_mode);
+
+        Ok(scanner)
+    }
+}
+
+/// Defaults
+const SCAN_TYPE: QScanType = QScanType::TcpConnect;
+const PRINT_MODE: QSPrintMode = QSPrintMode::NonRealTime;
+const BATCH_DEF: u16 = 2500;
+const TIMEOUT_DEF: u64 = 1000;
+const TRIES_DEF: u8 = 1;
+const PING_INTERVAL_DEF: u64 = 1000;
+const MAX_CIDR_HOSTS_DEF: u64 = 1 << 24;
+const MAX_TARGETS_DEF: u64 = 1 << 24;
+const DNS_CONCURRENCY_DEF: usize = 16;
+/// Target read from the standard input
+const STDIN_TARGETS: &str = "-";
+/// Hosts of the local IPv6 /64 returned by [QScanner::local_subnet_targets]
+const LOCAL_SUBNET_V6_HOSTS: u16 = u16::MAX;
+/// Maximum number of ports of a ports string, counting the duplicates
+const PORTS_MAX: usize = 1 << 20;
+const EMFILE_BACKOFF: Duration = Duration::from_millis(100);
+/// Interval between the writes of the checkpoint file
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(1);
+/// Number of lines of the real-time output buffered before flushing
+const OUTPUT_FLUSH_LINES: usize = 1024;
+/// Maximum number of ports of the local port range tried for a connection
+const LOCAL_PORT_TRIES: usize = 64;
+/// Maximum duration of the reachability check before TCP connect scans
+const WARMUP_TIMEOUT: Duration = Duration::from_secs(1);
+/// Number of targets probed by the reachability check
+const WARMUP_TARGETS: usize = 2;
+/// Number of targets looked through to find distinct hosts to probe
+const WARMUP_SEARCH: usize = 1024;
+/// Maximum number of bytes of the HTTP response read by the HTTP probe
+const HTTP_PROBE_MAX: usize = 4096;
+
+/// Ports commonly serving HTTP, probed by default when the HTTP probe is
+/// enabled
+const HTTP_PORTS: &[u16] = &[80, 81, 591, 3000, 5000, 8000, 8008, 8080, 8081, 8888];
+
+/// Most common open TCP ports, most frequent first (from nmap's services
+/// frequencies)
+const TOP_PORTS: &[u16] = &[
+    80, 23, 443, 21, 22, 25, 3389, 110, 445, 139, 143, 53, 135, 3306, 8080, 1723, 111, 995, 993,
+    5900, 1025, 587, 8888, 199, 1720, 465, 548, 113, 81, 6001, 10000, 514, 5060, 179, 1026, 2000,
+    8443, 8000, 32768, 554, 26, 1433, 49152, 2001, 515, 8008, 49154, 1027, 5666, 646, 5000, 5631,
+    631, 49153, 8081, 2049, 88, 79, 5800, 106, 2121, 1110, 49155, 6000, 513, 990, 5357, 427, 49156,
+    543, 544, 5101, 144, 7, 389, 8009, 3128, 444, 9999, 5009, 7070, 5190, 3000, 5432, 1900, 3986,
+    13, 1029, 9, 5051, 6646, 49157, 1028, 873, 1755, 2717, 4899, 9100, 119, 37,
+];
+
+/// Service names accepted in ports strings
+const SERVICE_PORTS: &[(&str, u16)] = &[
+    ("ftp", 21),
+    ("ssh", 22),
+    ("telnet", 23),
+    ("smtp", 25),
+    ("domain", 53),
+    ("dns", 53),
+    ("http", 80),
+    ("pop3", 110),
+    ("ntp", 123),
+    ("imap", 143),
+    ("snmp", 161),
+    ("ldap", 389),
+    ("https", 443),
+    ("smb", 445),
+    ("smtps", 465),
+    ("submission", 587),
+    ("ldaps", 636),
+    ("imaps", 993),
+    ("pop3s", 995),
+    ("mssql", 1433),
+    ("mysql", 3306),
+    ("rdp", 3389),
+    ("postgresql", 5432),
+    ("vnc", 5900),
+    ("redis", 6379),
+    ("http-alt", 8080),
+];
+
+impl QScanner {
+    /// Create a new QScanner
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - IPs string, comma separated and CIDR notation (IPv4 or IPv6)
+    ///   or IPv4 ranges (e.g., "192.168.1.1-192.168.1.50", "192.168.1.1-50").
+    ///   Spaces and new lines separate targets too (e.g., "8.8.8.8 8.8.4.4").
+    ///   A target can also be a file of targets, one for each line, or "-"
+    ///   to read them from the standard input until EOF
+    /// * `ports` - ports string, comma separated and ranges
+    ///
+    /// CIDRs and ranges larger than 2^24 hosts are skipped, see
+    /// [QScanner::set_max_cidr_hosts].
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let scanner1 = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let scanner2 = QScanner::new("127.0.0.1,127.0.1.0/24", "80,443,1024-2048").unwrap();
+    /// let scanner3 = QScanner::new("::1,[2001:db8::1]:80,2001:db8::/120", "80").unwrap();
+    /// assert!(QScanner::new("127.0.0.1", "80-").is_err());
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed.
+    ///
+    pub fn new(addresses: &str, ports: &str) -> Result<Self, QScanError> {
+        let parsed = addresses_parse(addresses, MAX_CIDR_HOSTS_DEF, MAX_TARGETS_DEF, None);
+        Ok(Self::from_parsed(parsed, ports_parse(ports)?))
+    }
+
+    /// Parse and validate the `addresses` and `ports` strings (see
+    /// [QScanner::new]) without creating a scanner nor connecting to any
+    /// target, e.g. to report the typos of user input before scanning.
+    /// Domain names are resolved. Returns the number of target addresses
+    /// and the number of ports.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::{QScanError, QScanner};
+    /// assert_eq!(
+    ///     QScanner::validate_inputs("127.0.0.1,10.0.0.0/30", "22,80-82").unwrap(),
+    ///     (5, 4)
+    /// );
+    /// assert!(matches!(
+    ///     QScanner::validate_inputs("127.0.0.1", "80-"),
+    ///     Err(QScanError::InvalidPorts(_))
+    /// ));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed,
+    /// [QScanError::TooManyHosts] for the first CIDR with more
+    /// hosts than the default maximum (see [QScanner::set_max_cidr_hosts]),
+    /// [QScanError::InvalidTargets] with the targets which produced no
+    /// address (e.g., "10.0.0.256" or unresolvable names) and
+    /// [QScanError::NoTargets] if `addresses` has no target.
+    pub fn validate_inputs(addresses: &str, ports: &str) -> Result<(usize, usize), QScanError> {
+        let ports = ports_parse(ports)?;
+
+        for target in targets_split(addresses) {
+            let too_many = IpCidr::from_str(&target)
+                .is_ok_and(|cidr| cidr_hosts(&cidr) > MAX_CIDR_HOSTS_DEF as u128);
+            if too_many {
+                return Err(QScanError::TooManyHosts {
+                    target,
+                    max: MAX_CIDR_HOSTS_DEF,
+                });
+            }
+        }
+
+        let parsed = addresses_parse(addresses, MAX_CIDR_HOSTS_DEF, MAX_TARGETS_DEF, None);
+        if !parsed.unresolved.is_empty() {
+            return Err(QScanError::InvalidTargets(parsed.unresolved));
+        }
+        if parsed.ips.is_empty() {
+            return Err(QScanError::NoTargets);
+        }
+
+        Ok((parsed.ips.len(), ports.len()))
+    }
+
+    /// Targets of the local subnet of the host primary interface, i.e. the
+    /// one of the default route: the /24 for an IPv4 address or, as a whole
+    /// /64 cannot be scanned, the hosts with the lowest 2^16 interface ids
+    /// (e.g. statically or DHCPv6 assigned addresses) of the /64 for an IPv6
+    /// one. IPv4 is preferred. The network and broadcast addresses and the
+    /// host itself are excluded. No packet is sent.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use qscan::qscanner::QScanner;
+    /// let ips = QScanner::local_subnet_targets().unwrap();
+    /// let scanner = QScanner::new_from_vecs(ips, vec![22, 80, 443]);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::Io] if the host has no route to either family.
+    pub fn local_subnet_targets() -> Result<Vec<IpAddr>, QScanError> {
+        let local = primary_ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .or_else(|_| primary_ip(IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1))))?;
+        Ok(subnet_targets(local))
+    }
+
+    /// Create a new QScanner from already parsed IPs and ports
+    pub fn new_from_vecs(ips: Vec<IpAddr>, ports: Vec<u16>) -> Self {
+        let parsed = ParsedAddresses {
+            ips,
+            ..Default::default()
+        };
+        Self::from_parsed(parsed, ports)
+    }
+
+    /// Scanner with the default settings scanning the `parsed` addresses and
+    /// `ports`, on which all the constructors are based
+    fn from_parsed(parsed: ParsedAddresses, ports: Vec<u16>) -> Self {
+        let mut qs = Self {
+            ips: parsed.ips,
+            unresolved: parsed.unresolved,
+            scopes: parsed.scopes,
+            groups: Vec::new(),
+            targets_file: None,
+            targets_cidr: None,
+            ports,
+            scan_type: SCAN_TYPE,
+            print_mode: PRINT_MODE,
+            batch: BATCH_DEF,
+            to: Duration::from_millis(TIMEOUT_DEF),
+            tries: NonZeroU8::new(std::cmp::max(TRIES_DEF, 1)).unwrap(),
+            retry_backoff: RetryBackoff::Fixed,
+            port_timeouts: HashMap::new(),
+            retry_on_refused: false,
+            parallel_tries: false,
+            retry_jitter: Duration::ZERO,
+            jitter_rng: std::sync::Mutex::new(StdRng::from_entropy()),
+            rate_limiter: None,
+            shuffle: false,
+            shuffle_seed: None,
+            sample: None,
+            sample_seed: None,
+            iter_order: IterOrder::PortMajor,
+            resolve_policy: ResolvePolicy::All,
+            resolve_ptr: false,
+            sort_results: false,
+            source_addr: None,
+            adaptive_batch: false,
+            rtt: None,
+            deadline: None,
+            ramp_up: None,
+            stop_on_first_open: false,
+            host_down_threshold: None,
+            closed_cache: None,
+            max_open_results: None,
+            resolve_at_connect: false,
+            named_targets: parsed.names,
+            warmup: false,
+            require_reachable: false,
+            checkpoint_file: None,
+            resumed: HashSet::new(),
+            close_mode: CloseMode::Graceful,
+            http_probe: false,
+            http_ports: HTTP_PORTS.to_vec(),
+            tls_probe: None,
+            service_probe: None,
+            emfile_events: AtomicU64::new(0),
+            local_ports: None,
+            next_local_port: AtomicUsize::new(0),
+            tcp_fast_open: false,
+            proxy: None,
+            ping_payload: vec![0; 56],
+            ping_interval: Duration::from_millis(PING_INTERVAL_DEF),
+            udp_payload: Vec::new(),
+            udp_probes: HashMap::new(),
+            max_cidr_hosts: MAX_CIDR_HOSTS_DEF,
+            max_targets: MAX_TARGETS_DEF,
+            resolver_config: None,
+            dns_timeout: None,
+            dns_attempts: None,
+            dns_concurrency: DNS_CONCURRENCY_DEF,
+            exclude: Excludes::default(),
+            exclude_ports: HashSet::new(),
+            port_priority: Vec::new(),
+            dedup: true,
+            on_result: None,
+            on_result_control: None,
+            output: None,
+            audit_log: None,
+            on_progress: None,
+            last_results: None,
+        };
+        qs.normalize_targets();
+        qs
+    }
+
+    /// Set the scanner type
+    pub fn set_scan_type(&mut self, scan_type: QScanType) {
+        self.scan_type = scan_type;
+    }
+
+    /// Set the results printing mode
+    pub fn set_print_mode(&mut self, print_mode: QSPrintMode) {
+        self.print_mode = print_mode;
+    }
+
+    /// Set the number of parallel scans. Zero is corrected to one, as no
+    /// target would ever be scanned.
+    pub fn set_batch(&mut self, batch: u16) {
+        self.batch = batch.max(1);
+    }
+
+    /// Set the scan timeout for each target
+    pub fn set_timeout_ms(&mut self, to_ms: u64) {
+        self.to = Duration::from_millis(to_ms);
+    }
+
+    /// Set, for some ports, a TCP connect timeout different from the scan
+    /// timeout (see [QScanner::set_timeout_ms]), which is still used for the
+    /// ports not in `timeouts`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use std::collections::HashMap;
+    /// use std::time::Duration;
+    /// let mut scanner = QScanner::new("127.0.0.1", "22,80").unwrap();
+    /// scanner.set_port_timeouts(HashMap::from([(22, Duration::from_millis(200))]));
+    /// ```
+    pub fn set_port_timeouts(&mut self, timeouts: HashMap<u16, Duration>) {
+        self.port_timeouts = timeouts;
+    }
+
+    /// Base TCP connect timeout of `port`
+    fn port_timeout(&self, port: u16) -> Duration {
+        self.port_timeouts.get(&port).copied().unwrap_or(self.to)
+    }
+
+    /// Base TCP connect timeout of `socket`: the timeout of its port, or
+    /// less once the RTT of its host is estimated (see
+    /// [QScanner::set_adaptive_timeout])
+    fn socket_timeout(&self, socket: SocketAddr) -> Duration {
+        let to = self.port_timeout(socket.port());
+        match &self.rtt {
+            Some(rtt) => rtt.lock().unwrap().timeout(socket.ip(), to),
+            None => to,
+        }
+    }
+
+    /// Enable or disable the adaptive timeout for TCP connect scans. The RTT
+    /// of each host is estimated from the connects it answers (accepted or
+    /// refused) and, after the first few of them, the timeout of its other
+    /// ports is scaled to the RTT, as TCP does for retransmissions. The
+    /// timeout (see [QScanner::set_timeout_ms] and
+    /// [QScanner::set_port_timeouts]) is kept as the ceiling. Faster for
+    /// targets with mixed latencies, e.g. nearby and distant hosts.
+    pub fn set_adaptive_timeout(&mut self, adaptive: bool) {
+        self.rtt = adaptive.then(|| std::sync::Mutex::new(adaptive::RttEstimator::default()));
+    }
+
+    /// Enable or disable the adaptive batch size for TCP connect scans. The
+    /// scan starts with a few targets in parallel and scans more of them
+    /// (up to the batch size) while timeouts are rare, and fewer when
+    /// timeouts or open files errors increase.
+    pub fn set_adaptive_batch(&mut self, adaptive: bool) {
+        self.adaptive_batch = adaptive;
+    }
+
+    /// Set how many retries for each target
+    /// If `ntries` is 0, it is converted to 1
+    pub fn set_ntries(&mut self, ntries: u8) {
+        self.tries = NonZeroU8::new(std::cmp::max(ntries, 1)).unwrap();
+    }
+
+    /// Retry the TCP connect of a target when the connection is refused.
+    /// Default: false, a refused connection is a definitive answer and only
+    /// timeouts and transient errors (e.g., connection reset) are retried.
+    pub fn set_retry_on_refused(&mut self, retry: bool) {
+        self.retry_on_refused = retry;
+    }
+
+    /// Start all the TCP connect tries of a target at the same time, instead
+    /// of one after the other, so that a target is reported as soon as a
+    /// try succeeds or once all of them failed. Each target uses up to
+    /// `ntries` connections. Default: false.
+    pub fn set_parallel_tries(&mut self, parallel: bool) {
+        self.parallel_tries = parallel;
+    }
+
+    /// Wait a random duration between 0 and `max` before each retry of a
+    /// TCP connect, spreading the retries of targets failing at the same
+    /// time. Default: no jitter.
+    pub fn set_retry_jitter(&mut self, max: Duration) {
+        self.retry_jitter = max;
+    }
+
+    /// Set the seed of the retry jitter (see [QScanner::set_retry_jitter]),
+    /// so that the same delays are reproduced across scans
+    pub fn set_retry_jitter_seed(&mut self, seed: u64) {
+        self.jitter_rng = std::sync::Mutex::new(StdRng::seed_from_u64(seed));
+    }
+
+    /// Set how the timeout grows across the retries of a single target
+    /// (TCP connect scan). Default is [RetryBackoff::Fixed].
+    pub fn set_retry_backoff(&mut self, backoff: RetryBackoff) {
+        self.retry_backoff = backoff;
+    }
+
+    /// Limit the number of TCP connect attempts initiated per second.
+    /// If `pps` is 0, the rate is not limited (default).
+    pub fn set_max_rate(&mut self, pps: u32) {
+        self.rate_limiter = match pps {
+            0 => None,
+            pps => Some(ratelimit::RateLimiter::new(pps)),
+        };
+    }
+
+    /// Scan the targets (all the ip:port pairs) in random order
+    pub fn set_shuffle(&mut self, shuffle: bool) {
+        self.shuffle = shuffle;
+    }
+
+    /// Set the seed used to shuffle the targets (see [QScanner::set_shuffle]),
+    /// so that the same scan order is reproduced across scans
+    pub fn set_shuffle_seed(&mut self, seed: u64) {
+        self.shuffle_seed = Some(seed);
+    }
+
+    /// Scan only a random subset of the targets of TCP connect and UDP
+    /// scans, e.g. for measurements on address spaces too big to scan
+    /// whole. Each target is picked while iterating the targets, so the
+    /// skipped ones are never collected. For [Sample::Count] the targets
+    /// file addresses (see [QScanner::set_targets_file]) are not counted,
+    /// like in [QScanner::targets_count], which ignores the sampling.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::{QScanner, Sample};
+    /// let mut qs = QScanner::new("10.0.0.0/16", "80").unwrap();
+    /// qs.set_sample(Sample::Count(100));
+    /// qs.set_sample_seed(1);
+    /// let picked = qs.enumerate_targets().len();
+    /// assert!(picked > 50 && picked < 150);
+    /// ```
+    pub fn set_sample(&mut self, sample: Sample) {
+        self.sample = Some(sample);
+    }
+
+    /// Set the seed used to pick the targets (see [QScanner::set_sample]),
+    /// so that the same subset is picked across scans
+    pub fn set_sample_seed(&mut self, seed: u64) {
+        self.sample_seed = Some(seed);
+    }
+
+    /// Probability of each target of being scanned, see
+    /// [QScanner::set_sample]
+    fn sample_probability(&self) -> Option<f64> {
+        let p = match self.sample? {
+            Sample::Fraction(f) => f,
+            Sample::Count(n) => match self.targets_count() {
+                0 => 1.0,
+                total => n as f64 / total as f64,
+            },
+        };
+        Some(if p.is_nan() { 0.0 } else { p.clamp(0.0, 1.0) })
+    }
+
+    /// Set the order the targets are scanned in, see [IterOrder]. Default:
+    /// [IterOrder::PortMajor]. Ignored when shuffling (see
+    /// [QScanner::set_shuffle]); the addresses of a targets file (see
+    /// [QScanner::set_targets_file]) are always scanned host by host.
+    pub fn set_iteration_order(&mut self, order: IterOrder) {
+        self.iter_order = order;
+    }
+
+    /// Set which of the addresses a domain name resolves to become targets,
+    /// see [ResolvePolicy]. Default: [ResolvePolicy::All]. Only affects
+    /// targets set after this call (and the targets file lines).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::{QScanner, ResolvePolicy};
+    /// let mut qs = QScanner::new("", "80").unwrap();
+    /// qs.set_resolve_policy(ResolvePolicy::AllV4);
+    /// qs.set_targets_addr("localhost");
+    /// assert!(qs.get_tagets_ips().iter().all(|ip| ip.is_ipv4()));
+    /// ```
+    pub fn set_resolve_policy(&mut self, policy: ResolvePolicy) {
+        self.resolve_policy = policy;
+    }
+
+    /// Set the local address TCP connect scans connect from. Targets whose
+    /// address family differs from `addr` are reported as closed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `addr` cannot be bound (e.g., it is not an address
+    /// of one of the host interfaces). The previous source address is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// scanner.set_source_addr(IpAddr::V4(Ipv4Addr::LOCALHOST)).unwrap();
+    /// ```
+    pub fn set_source_addr(&mut self, addr: IpAddr) -> Result<(), QScanError> {
+        source_tcp_socket(addr)?;
+        self.source_addr = Some(addr);
+        Ok(())
+    }
+
+    /// Bind the connections of TCP connect scans to the local ports in
+    /// `range`, in turn, with `SO_REUSEADDR`, instead of the ephemeral
+    /// ports chosen by the system. A wider range than the system ephemeral
+    /// one sustains more connections from a single source address. Ports
+    /// already in use are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `range` is empty. The previous range is kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// scanner.set_local_port_range(20000..=60999).unwrap();
+    /// assert!(scanner.set_local_port_range(2000..=1000).is_err());
+    /// ```
+    pub fn set_local_port_range(&mut self, range: RangeInclusive<u16>) -> Result<(), QScanError> {
+        if range.is_empty() {
+            return Err(QScanError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("empty local port range {:?}", range),
+            )));
+        }
+        self.local_ports = Some(range);
+        Ok(())
+    }
+
+    /// Enable TCP Fast Open (`TCP_FASTOPEN_CONNECT`) for the connections of
+    /// TCP connect scans, saving a round trip when reconnecting to hosts
+    /// that handed out a TFO cookie, e.g. for banner grabbing. It is
+    /// silently ignored on platforms (or kernels) without TFO support.
+    ///
+    /// Note that with a cached cookie the SYN is deferred to the first write
+    /// of the connection, so the connect to such hosts succeeds at once and
+    /// the state of their ports is only meaningful when data is exchanged
+    /// (see [QScanner::set_service_probe]).
+    ///
+    /// Default: false
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// scanner.set_tcp_fast_open(true);
+    /// ```
+    pub fn set_tcp_fast_open(&mut self, tfo: bool) {
+        self.tcp_fast_open = tfo;
+    }
+
+    /// Connect to the targets of TCP connect scans through a SOCKS5 proxy. A
+    /// target is open if the proxy CONNECT succeeds. Timeout and retries
+    /// apply to the whole proxy handshake. If a source address is set, it is
+    /// used to connect to the proxy.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::{ProxyConfig, QScanner};
+    /// let mut scanner = QScanner::new("10.0.0.1", "22").unwrap();
+    /// scanner.set_proxy(ProxyConfig::new("127.0.0.1:1080".parse().unwrap()));
+    /// ```
+    pub fn set_proxy(&mut self, proxy: ProxyConfig) {
+        self.proxy = Some(proxy);
+    }
+
+    /// Enable or disable the reverse DNS lookup of the targets with open
+    /// ports at the end of a TCP connect scan. The host name is stored in
+    /// [QScanTcpConnectResult::hostname].
+    pub fn set_resolve_ptr(&mut self, resolve_ptr: bool) {
+        self.resolve_ptr = resolve_ptr;
+    }
+
+    /// Sort the results of TCP connect, ping and UDP scans by IP, then by
+    /// port, instead of returning them in completion order. Real-time print
+    /// modes still print the results as soon as they are found.
+    pub fn set_sort_results(&mut self, sort: bool) {
+        self.sort_results = sort;
+    }
+
+    /// Spread the start of the first `batch` TCP connect attempts over
+    /// `ramp_up`, instead of starting them all at once, reducing the burst of
+    /// connections at the start of the scan. Default: no ramp-up.
+    pub fn set_ramp_up(&mut self, ramp_up: Duration) {
+        self.ramp_up = (!ramp_up.is_zero()).then_some(ramp_up);
+    }
+
+    /// Stop the TCP connect scan of a host as soon as one of its ports is
+    /// found open: its remaining ports are not scanned and the ports being
+    /// scanned are dropped. They are reported closed and skipped
+    /// ([QScanSkipReason::HostOpen]), counted in [ScanStats::skipped]. Useful
+    /// to find out quickly which hosts are up. Default: false.
+    pub fn set_stop_on_first_open(&mut self, stop: bool) {
+        self.stop_on_first_open = stop;
+    }
+
+    /// Consider a host down once `n` of its ports timed out while none of
+    /// them responded (i.e., was open or refused the connection) during a
+    /// TCP connect scan: its remaining ports are reported filtered and
+    /// skipped ([QScanSkipReason::HostDown]), with no attempts, without
+    /// connecting to them. Much faster over sparse address
+    /// spaces. The ports being scanned when the host is considered down are
+    /// scanned to the end. Zero disables the check (the default).
+    pub fn set_host_down_threshold(&mut self, n: usize) {
+        self.host_down_threshold = (n > 0).then_some(n);
+    }
+
+    /// Report the TCP connect targets in `cache` closed and skipped
+    /// ([QScanSkipReason::CachedClosed]), with no attempts, without
+    /// connecting to them, for `ttl` from this call. Useful to skip
+    /// the ports refused by a previous scan (see
+    /// [QScanner::get_last_results]) in incremental scans. The cache
+    /// replaces the previous one.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use std::collections::HashSet;
+    /// use std::time::Duration;
+    /// let mut qs = QScanner::new("127.0.0.1", "80,443").unwrap();
+    /// let cache = HashSet::from(["127.0.0.1:80".parse().unwrap()]);
+    /// qs.set_closed_cache(cache, Duration::from_secs(3600));
+    /// ```
+    pub fn set_closed_cache(&mut self, cache: HashSet<SocketAddr>, ttl: Duration) {
+        let until = Instant::now()
+            .checked_add(ttl)
+            .unwrap_or_else(|| Instant::now() + Duration::from_secs(u32::MAX as u64));
+        self.closed_cache = Some((cache, until));
+    }
+
+    /// Keep the host names of the targets set after this call as names,
+    /// instead of resolving them when parsing the targets: TCP connect scans
+    /// resolve them with the system resolver right before connecting to
+    /// each of their ports, following the DNS changes (e.g., round-robin)
+    /// during the scan. The first address selected by the resolve policy
+    /// (see [QScanner::set_resolve_policy]) is scanned, and the host name is
+    /// reported in the results. Host names failing to resolve are reported
+    /// as filtered, with the unspecified address. Host names are scanned
+    /// after the other targets, and are ignored by ping and UDP scans and by
+    /// target groups. Default: false.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("", "").unwrap();
+    /// qs.set_resolve_at_connect(true);
+    /// qs.set_targets("localhost,127.0.0.2", "80").unwrap();
+    /// assert_eq!(qs.get_tagets_ips().len(), 1);
+    /// assert_eq!(qs.targets_count(), 2);
+    /// ```
+    pub fn set_resolve_at_connect(&mut self, resolve_at_connect: bool) {
+        self.resolve_at_connect = resolve_at_connect;
+    }
+
+    /// Stop TCP connect scans once `max` open targets are found across all
+    /// the hosts: no new target is scanned, the targets being scanned are
+    /// dropped and the results gathered so far are returned. Useful to
+    /// quickly sample a few open services. Default: no limit.
+    pub fn set_max_open_results(&mut self, max: usize) {
+        self.max_open_results = Some(max);
+    }
+
+    /// Check that the network path is up before TCP connect scans (see
+    /// [QScanner::check_reachable]), warning when none of the probed
+    /// targets responds, since the scan is then likely to time out on every
+    /// target. Default: false.
+    pub fn set_warmup(&mut self, warmup: bool) {
+        self.warmup = warmup;
+    }
+
+    /// Like [QScanner::set_warmup], but abort TCP connect scans when none
+    /// of the probed targets responds: an error is logged and no result is
+    /// returned. [QScanner::scan_tcp_connect_checked] returns the
+    /// [QScanError::Unreachable] error and [ScanStats::unreachable] is set.
+    /// Default: false.
+    pub fn set_require_reachable(&mut self, require: bool) {
+        self.require_reachable = require;
+    }
+
+    /// Set the maximum duration of TCP connect scans. Once `deadline` passes
+    /// since the start of the scan no new target is scanned, the targets
+    /// being scanned are completed and the partial results are returned. The
+    /// targets not scanned are omitted from the results.
+    pub fn set_deadline(&mut self, deadline: Duration) {
+        self.deadline = Some(deadline);
+    }
+
+    /// Set how the connections to open ports are closed, see [CloseMode].
+    /// Default: [CloseMode::Graceful].
+    pub fn set_close_mode(&mut self, mode: CloseMode) {
+        self.close_mode = mode;
+    }
+
+    /// Enable or disable the HTTP probe of the open ports commonly serving
+    /// HTTP (see [QScanner::set_http_ports]). A `GET / HTTP/1.0` request is
+    /// sent after the connection and the response status line and `Server:`
+    /// header are stored in [QScanTcpConnectResult::http]. Responses which
+    /// are not HTTP, or not received within the timeout, are ignored.
+    pub fn set_http_probe(&mut self, http_probe: bool) {
+        self.http_probe = http_probe;
+    }
+
+    /// Set the ports probed when the HTTP probe is enabled. Default: 80, 81,
+    /// 591, 3000, 5000, 8000, 8008, 8080, 8081, 8888.
+    pub fn set_http_ports(&mut self, ports: &[u16]) {
+        self.http_ports = ports.to_vec();
+    }
+
+    /// Enable or disable the TLS probe of the open ports. A TLS handshake is
+    /// attempted after the connection, without verifying the server
+    /// certificate, and the negotiated protocol version and the subject
+    /// common name and expiry of the leaf certificate are stored in
+    /// [QScanTcpConnectResult::tls]. Ports not speaking TLS are reported
+    /// without TLS information. The ports probed for HTTP (see
+    /// [QScanner::set_http_probe]) are not probed for TLS.
+    ///
+    /// The host name of the target (see [QScanner::set_resolve_at_connect])
+    /// is sent as SNI, so that virtual hosts present their own certificate;
+    /// no SNI is sent for the targets which are IP addresses.
+    pub fn set_tls_probe(&mut self, tls_probe: bool) {
+        self.tls_probe = if tls_probe {
+            Some(TlsProbe(tls::connector()))
+        } else {
+            None
+        };
+    }
+
+    /// Set a custom probe of the open ports of TCP connect scans (e.g., a
+    /// banner grab or a protocol handshake), invoked after the HTTP and TLS
+    /// probes, if any. The probe takes over the connection instead of the
+    /// close mode, and its outcome is stored in
+    /// [QScanTcpConnectResult::probe]. Probes lasting more than the timeout
+    /// are dropped.
+    pub fn set_service_probe(&mut self, probe: Box<dyn ServiceProbe>) {
+        self.service_probe = Some(ServiceProbeBox(probe));
+    }
+
+    /// Buffer the output of the real-time print modes, flushing it once
+    /// `interval` elapsed since the last flush or enough lines are buffered,
+    /// and at the end of the scans. Faster than printing each line as soon
+    /// as it is found when many targets are printed, e.g. when the output is
+    /// redirected to a file. By default each line is printed immediately.
+    pub fn set_output_flush_interval(&mut self, interval: Duration) {
+        self.output = Some(std::sync::Mutex::new(OutputBuffer::new(
+            Box::new(std::io::stdout()),
+            interval,
+        )));
+    }
+
+    /// Append to the file at `path` a line for every target scanned by TCP
+    /// connect and UDP scans, whatever the print mode, with the unix time
+    /// (in seconds, with milliseconds), the IP, the port and the state,
+    /// e.g. `1760443200.125 127.0.0.1 80 OPEN`. The lines are buffered
+    /// and written at the end of the scans.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened. The previous log, if
+    /// any, is kept.
+    pub fn set_audit_log(&mut self, path: &Path) -> Result<(), QScanError> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+        self.audit_log = Some(std::sync::Mutex::new(AuditLog {
+            writer: std::io::BufWriter::new(file),
+        }));
+        Ok(())
+    }
+
+    /// Record `res` in the audit log, if any
+    fn audit(&self, res: &QScanResult) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.lock().unwrap().record(res);
+        }
+    }
+
+    /// Print a line of the real-time print modes
+    fn print_line(&self, line: fmt::Arguments) {
+        match &self.output {
+            Some(output) => output.lock().unwrap().write_line(line),
+            None => println!("{}", line),
+        }
+    }
+
+    /// Flush the buffered output of the real-time print modes and the audit
+    /// log, if any
+    fn flush_output(&self) {
+        if let Some(output) = &self.output {
+            output.lock().unwrap().flush();
+        }
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.lock().unwrap().flush();
+        }
+    }
+
+    /// Set ping payload
+    pub fn set_ping_payload(&mut self, payload: &[u8]) {
+        self.ping_payload = Vec::from(payload);
+    }
+
+    /// Set ping interval in ms
+    pub fn set_ping_interval_ms(&mut self, ping_int_ms: u64) {
+        self.ping_interval = Duration::from_millis(ping_int_ms);
+    }
+
+    /// Set the payload of the datagram sent to each target during UDP scans
+    pub fn set_udp_payload(&mut self, payload: &[u8]) {
+        self.udp_payload = Vec::from(payload);
+    }
+
+    /// Set protocol specific probes for the UDP scans, by port. On the ports
+    /// with a probe its payload is sent instead of the generic one and the
+    /// port is considered open only if a reply accepted by the probe matcher
+    /// is received; the other replies are ignored.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::{QScanner, UdpProbe};
+    /// use std::collections::HashMap;
+    /// let mut scanner = QScanner::new("127.0.0.1", "53,123").unwrap();
+    /// scanner.set_udp_probes(HashMap::from([(53, UdpProbe::dns()), (123, UdpProbe::ntp())]));
+    /// ```
+    pub fn set_udp_probes(&mut self, probes: HashMap<u16, UdpProbe>) {
+        self.udp_probes = probes;
+    }
+
+    /// Set a callback invoked, during TCP connect scans, on each result as
+    /// soon as the scan for a target ends. The callback is invoked in
+    /// addition to the printing of the print mode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// scanner.set_on_result(Box::new(|r| println!("{:?}", r)));
+    /// ```
+    pub fn set_on_result(&mut self, callback: Box<dyn Fn(&QScanResult) + Send + Sync>) {
+        self.on_result = Some(ResultCallback(callback));
+    }
+
+    /// Set a callback invoked, during TCP connect scans, on each result as
+    /// soon as the scan for a target ends, after the one set with
+    /// [QScanner::set_on_result], deciding whether the scan goes on.
+    /// Returning [ControlFlow::Break] stops the scan, as if cancelled (see
+    /// [QScanner::scan_tcp_connect_with_cancel]): the results so far,
+    /// including the current one, are returned.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::{QScanResult, QScanTcpConnectState, QScanner};
+    /// use std::ops::ControlFlow;
+    /// let mut scanner = QScanner::new("127.0.0.1", "1-1024").unwrap();
+    /// // Stop at the first open port
+    /// scanner.set_on_result_control(Box::new(|r| match r {
+    ///     QScanResult::TcpConnect(r) if r.state == QScanTcpConnectState::Open => {
+    ///         ControlFlow::Break(())
+    ///     }
+    ///     _ => ControlFlow::Continue(()),
+    /// }));
+    /// ```
+    pub fn set_on_result_control(&mut self, callback: Box<ResultControlFn>) {
+        self.on_result_control = Some(ResultControl(callback));
+    }
+
+    /// Set a callback invoked, during TCP connect scans, about every
+    /// `interval` with the progress of the scan, and once when the scan ends,
+    /// whether all the targets were scanned or the scan was cut short (e.g.,
+    /// cancelled or past the deadline). See [ScanProgress::total] for the
+    /// accuracy of the number of targets to scan.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use std::time::Duration;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// scanner.set_progress_callback(
+    ///     Duration::from_secs(1),
+    ///     Box::new(|p| println!("{}/{}", p.completed, p.total)),
+    /// );
+    /// ```
+    pub fn set_progress_callback(
+        &mut self,
+        interval: Duration,
+        callback: Box<dyn Fn(ScanProgress) + Send + Sync>,
+    ) {
+        self.on_progress = Some(ProgressCallback { interval, callback });
+    }
+
+    /// Set addresses excluded from the scan. Matching targets are removed from
+    /// existing and future targets. Old exclusions are discarded. Excluded
+    /// CIDRs are not expanded: they are subtracted from the CIDRs of future
+    /// targets, so that only the remaining addresses are kept in memory.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - IPs string, same syntax of targets addresses
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("192.168.1.0/24", "80").unwrap();
+    /// qs.set_exclude_addresses("192.168.1.1");
+    /// assert_eq!(qs.get_tagets_ips().len(), 255);
+    /// ```
+    pub fn set_exclude_addresses(&mut self, addresses: &str) {
+        let mut exclude = Excludes::default();
+
+        // CIDRs are kept as blocks, see [cidr_subtract]
+        for addr in targets_split(addresses) {
+            match IpCidr::from_str(&addr) {
+                Ok(cidr) if addr.contains('/') => exclude.cidrs.push(cidr),
+                _ => exclude
+                    .ips
+                    .extend(self.parse_addresses(&addr, self.max_targets).ips),
+            }
+        }
+
+        self.exclude = exclude;
+        self.normalize_targets();
+    }
+
+    /// Set ports excluded from the scan. Matching ports are removed from
+    /// existing and future targets. Old exclusions are discarded.
+    ///
+    /// # Arguments
+    ///
+    /// * `ports` - ports string, comma separated and ranges
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed. Old exclusions are kept.
+    ///
+    pub fn set_exclude_ports(&mut self, ports: &str) -> Result<(), QScanError> {
+        self.exclude_ports = ports_parse(ports)?.into_iter().collect();
+        self.normalize_targets();
+        Ok(())
+    }
+
+    /// Enable or disable the removal of duplicated targets addresses and
+    /// ports (e.g., "192.168.1.5" in "192.168.1.0/24,192.168.1.5"), keeping
+    /// the first occurrence of each one. Default: enabled. Only affects
+    /// targets set after this call.
+    pub fn set_dedup(&mut self, dedup: bool) {
+        self.dedup = dedup;
+    }
+
+    /// Scan the `ports` first, in the given order, across all the targets,
+    /// and then the remaining ports. Ports which are not targets are ignored.
+    /// Unlike [QScanner::set_shuffle], the order is deterministic. Ignored
+    /// when shuffling; with [IterOrder::HostMajor] or a targets file (see
+    /// [QScanner::set_targets_file]) the ports are prioritized for each
+    /// address.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("127.0.0.1,127.0.0.2", "21,22,80,443").unwrap();
+    /// qs.set_port_priority(vec![443, 22]);
+    /// assert_eq!(qs.enumerate_targets()[0], "127.0.0.1:443".parse().unwrap());
+    /// ```
+    pub fn set_port_priority(&mut self, ports: Vec<u16>) {
+        self.port_priority = ports;
+        self.normalize_targets();
+    }
+
+    /// Remove duplicated (see [QScanner::set_dedup]) and excluded addresses
+    /// and ports from targets, and move the priority ports first
+    fn normalize_targets(&mut self) {
+        if self.dedup {
+            self.ips = std::mem::take(&mut self.ips).into_iter().unique().collect();
+            self.named_targets = std::mem::take(&mut self.named_targets)
+                .into_iter()
+                .unique()
+                .collect();
+            self.ports = std::mem::take(&mut self.ports)
+                .into_iter()
+                .unique()
+                .collect();
+        }
+
+        if !self.exclude.is_empty() {
+            self.ips.retain(|ip| !self.exclude.contains(ip));
+        }
+
+        if !self.exclude_ports.is_empty() {
+            self.ports.retain(|port| !self.exclude_ports.contains(port));
+        }
+
+        if !self.port_priority.is_empty() {
+            let priority = &self.port_priority;
+            self.ports.sort_by_key(|port| {
+                priority
+                    .iter()
+                    .position(|p| p == port)
+                    .unwrap_or(priority.len())
+            });
+        }
+    }
+
+    /// Set the maximum number of hosts a single CIDR can expand to.
+    /// Bigger CIDRs (e.g., "2001:db8::/64") are skipped when parsing targets
+    /// addresses. Only affects targets set after this call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("", "80").unwrap();
+    /// qs.set_max_cidr_hosts(256);
+    /// qs.set_targets_addr("2001:db8::/120,2001:db8::/119");
+    /// assert_eq!(qs.get_tagets_ips().len(), 256);
+    /// ```
+    pub fn set_max_cidr_hosts(&mut self, max_hosts: u64) {
+        self.max_cidr_hosts = max_hosts;
+    }
+
+    /// Set the maximum number of target addresses (default 2^24). Addresses
+    /// (e.g., CIDRs) that would exceed the maximum are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("", "80").unwrap();
+    /// qs.set_max_targets(300);
+    /// qs.set_targets_addr("10.0.0.0/24,10.0.1.0/24,10.0.2.1");
+    /// assert_eq!(qs.get_tagets_ips().len(), 257);
+    /// ```
+    pub fn set_max_targets(&mut self, max_targets: u64) {
+        self.max_targets = max_targets;
+    }
+
+    /// Parse `addresses` with the scanner settings, expanding at most
+    /// `max_targets` addresses
+    fn parse_addresses(&self, addresses: &str, max_targets: u64) -> ParsedAddresses {
+        addresses_parse_with_duplicates(
+            addresses,
+            self.max_cidr_hosts,
+            max_targets,
+            self.fallback_resolver().as_ref(),
+            self.resolve_policy,
+            &self.exclude.cidrs,
+            self.dns_concurrency,
+        )
+    }
+
+    /// Parse the targets `addresses`, keeping the host names as names in
+    /// the resolve-at-connect mode (see [QScanner::set_resolve_at_connect])
+    fn parse_targets(&self, addresses: &str, max_targets: u64) -> ParsedAddresses {
+        if !self.resolve_at_connect {
+            return self.parse_addresses(addresses, max_targets);
+        }
+
+        let (names, others): (Vec<String>, Vec<String>) = targets_split(addresses)
+            .into_iter()
+            .partition(|addr| is_hostname(addr));
+        let mut parsed = self.parse_addresses(&others.join(","), max_targets);
+        parsed.names = names;
+        parsed
+    }
+
+    /// Replace the targets addresses with `parsed`
+    fn set_parsed_addresses(&mut self, parsed: ParsedAddresses) {
+        self.ips = parsed.ips;
+        self.unresolved = parsed.unresolved;
+        self.scopes = parsed.scopes;
+        self.named_targets = parsed.names;
+    }
+
+    /// Add the targets addresses `parsed` to the existing ones
+    fn add_parsed_addresses(&mut self, parsed: ParsedAddresses) {
+        self.ips.extend(parsed.ips);
+        self.unresolved.extend(parsed.unresolved);
+        self.scopes.extend(parsed.scopes);
+        self.named_targets.extend(parsed.names);
+    }
+
+    /// Set the DNS resolver used when the system resolver fails to resolve a
+    /// domain name, and for reverse lookups (see [QScanner::set_resolve_ptr]).
+    /// By default only the system resolver is used. The resolver is used by
+    /// the targets set after this call.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+    /// let mut qs = QScanner::new("", "80").unwrap();
+    /// qs.set_resolver_config(ResolverConfig::google(), ResolverOpts::default())
+    ///     .unwrap();
+    /// qs.set_targets_addr("127.0.0.1");
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::Resolver] if the resolver cannot be created. The
+    /// previous resolver is kept.
+    pub fn set_resolver_config(
+        &mut self,
+        config: ResolverConfig,
+        opts: ResolverOpts,
+    ) -> Result<(), QScanError> {
+        Resolver::new(config.clone(), opts).map_err(ResolveError::from)?;
+        self.resolver_config = Some((config, opts));
+        Ok(())
+    }
+
+    /// Use Cloudflare DNS over TLS when the system resolver fails, see
+    /// [QScanner::set_resolver_config].
+    pub fn set_cloudflare_resolver(&mut self) -> Result<(), QScanError> {
+        self.set_resolver_config(ResolverConfig::cloudflare_tls(), ResolverOpts::default())
+    }
+
+    /// Set the timeout of each DNS query made with the resolver set with
+    /// [QScanner::set_resolver_config], overriding the one of its options,
+    /// so that a slow or dead DNS server fails fast. The system resolver
+    /// keeps the system settings.
+    pub fn set_dns_timeout(&mut self, timeout: Duration) {
+        self.dns_timeout = Some(timeout);
+    }
+
+    /// Set how many times a DNS query made with the resolver set with
+    /// [QScanner::set_resolver_config] is tried, overriding the attempts of
+    /// its options. See [QScanner::set_dns_timeout].
+    pub fn set_dns_attempts(&mut self, attempts: usize) {
+        self.dns_attempts = Some(attempts);
+    }
+
+    /// Set how many host names of the targets (including the ones of
+    /// targets files) are resolved in parallel by the targets set after
+    /// this call, so that files with many host names are parsed quickly.
+    /// The order of the targets is kept.
+    ///
+    /// The targets are parsed synchronously, so the lookups are blocking
+    /// ones (the system resolver, then the resolver set with
+    /// [QScanner::set_resolver_config]) made by up to `concurrency` OS
+    /// threads spawned for each parsing and joined before it returns. Each
+    /// thread costs a spawn and its stack, which is only worth it for many
+    /// (or slow) host names; 1 does the lookups in the calling thread. The
+    /// calling thread is blocked until all the names are resolved, also in
+    /// an async context, where the targets are better set from e.g.
+    /// `tokio::task::spawn_blocking`.
+    ///
+    /// Default: 16
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut scanner = QScanner::new("", "80").unwrap();
+    /// scanner.set_dns_concurrency(64);
+    /// scanner.set_targets_addr("localhost,127.0.0.2");
+    /// ```
+    pub fn set_dns_concurrency(&mut self, concurrency: usize) {
+        self.dns_concurrency = concurrency.max(1);
+    }
+
+    /// Resolver configuration set with [QScanner::set_resolver_config],
+    /// with the DNS timeout and attempts applied
+    fn resolver_config(&self) -> Option<(ResolverConfig, ResolverOpts)> {
+        let (config, mut opts) = self.resolver_config.clone()?;
+        if let Some(timeout) = self.dns_timeout {
+            opts.timeout = timeout;
+        }
+        if let Some(attempts) = self.dns_attempts {
+            opts.attempts = attempts;
+        }
+        Some((config, opts))
+    }
+
+    /// Resolver built from the configuration set with
+    /// [QScanner::set_resolver_config]
+    fn fallback_resolver(&self) -> Option<Resolver> {
+        let (config, opts) = self.resolver_config()?;
+
+        Resolver::new(config, opts)
+            .map_err(|e| warn!("cannot create resolver: {}", e))
+            .ok()
+    }
+
+    pub fn get_last_results(&self) -> Option<&Vec<QScanResult>> {
+        match &self.last_results {
+            Some(res) => Some(res),
+            None => None,
+        }
+    }
+
+    /// QScanner caches the results of the latest scan. This function clear the cache.
+    pub fn reset_last_results(&mut self) {
+        if let Some(last_res) = &mut self.last_results {
+            last_res.clear();
+            self.last_results = None;
+        }
+    }
+
+    /// Return the vector of target IP addresses
+    pub fn get_tagets_ips(&self) -> &Vec<IpAddr> {
+        &self.ips
+    }
+
+    /// Return the targets (or the lines of targets files) which produced no
+    /// address when set, e.g. domain names which could not be resolved. The
+    /// scan goes on with the other targets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let qs = QScanner::new("127.0.0.1,not-a-host.invalid", "80").unwrap();
+    /// assert_eq!(qs.unresolved_targets(), ["not-a-host.invalid"]);
+    /// ```
+    pub fn unresolved_targets(&self) -> &[String] {
+        &self.unresolved
+    }
+
+    /// Return the vector of target ports
+    pub fn get_tagets_ports(&self) -> &Vec<u16> {
+        &self.ports
+    }
+
+    /// Return all the targets (ip:port) in scan order, without scanning them.
+    /// See [QScanner::targets_count] for large scans.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let qs = QScanner::new("127.0.0.1,127.0.0.2", "80,443").unwrap();
+    /// assert_eq!(qs.enumerate_targets().len(), 4);
+    /// ```
+    pub fn enumerate_targets(&self) -> Vec<SocketAddr> {
+        self.sock_iter().collect()
+    }
+
+    /// Return the number of targets (ip:port) of a TCP connect or UDP scan.
+    /// The addresses of the targets file (see [QScanner::set_targets_file])
+    /// are not counted.
+    pub fn targets_count(&self) -> u64 {
+        let product = |ips: usize, ports: usize| (ips as u64).saturating_mul(ports as u64);
+        let cidr_hosts = self
+            .targets_cidr
+            .as_ref()
+            .map_or(0, |c| cidr_hosts(c).min(u64::MAX as u128) as u64);
+        self.groups.iter().fold(
+            product(self.ips.len() + self.named_targets.len(), self.ports.len())
+                .saturating_add(cidr_hosts.saturating_mul(self.ports.len() as u64)),
+            |count, g| count.saturating_add(product(g.ips.len(), g.ports.len())),
+        )
+    }
+
+    /// Set targets addresses. Old targets are discarded
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - IPs string, comma separated and CIDR notation
+    ///
+    pub fn set_targets_addr(&mut self, addresses: &str) {
+        self.set_parsed_addresses(self.parse_targets(addresses, self.max_targets));
+        self.targets_file = None;
+        self.targets_cidr = None;
+        self.normalize_targets();
+    }
+
+    /// Set targets port. Old targets are discarded
+    ///
+    /// # Arguments
+    ///
+    /// * `ports` - ports string, comma separated and ranges
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed. Old targets are kept.
+    ///
+    pub fn set_targets_port(&mut self, ports: &str) -> Result<(), QScanError> {
+        self.ports = ports_parse_with_duplicates(ports)?;
+        self.normalize_targets();
+        Ok(())
+    }
+
+    /// Set targets. Old targets are discarded
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - IPs string, comma separated and CIDR notation
+    /// * `ports` - ports string, comma separated and ranges
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed. Old targets are kept.
+    ///
+    pub fn set_targets(&mut self, addresses: &str, ports: &str) -> Result<(), QScanError> {
+        let ports = ports_parse_with_duplicates(ports)?;
+        self.set_parsed_addresses(self.parse_targets(addresses, self.max_targets));
+        self.targets_file = None;
+        self.targets_cidr = None;
+        self.ports = ports;
+        self.normalize_targets();
+        Ok(())
+    }
+
+    /// Set a file of targets addresses (one IP, CIDR or domain name for each
+    /// line) read while scanning, so that only the addresses being scanned
+    /// are kept in memory. Old targets addresses are discarded, targets
+    /// added later are scanned before the file ones.
+    ///
+    /// Only TCP connect and UDP scans use the file, which is scanned one
+    /// address at a time (i.e., all the ports of an address, then the next
+    /// address). Addresses are not deduplicated, nor shuffled, and domain
+    /// names are resolved only with the system resolver.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be opened. Old targets are kept.
+    pub fn set_targets_file(&mut self, path: &Path) -> Result<(), QScanError> {
+        File::open(path)?;
+        self.targets_file = Some(path.to_path_buf());
+        self.targets_cidr = None;
+        self.ips.clear();
+        self.unresolved.clear();
+        self.scopes.clear();
+        self.named_targets.clear();
+        Ok(())
+    }
+
+    /// Set a CIDR of targets addresses generated while scanning, so that
+    /// memory does not grow with the size of the range, e.g. to scan a
+    /// single port of a /12. Old targets addresses are discarded, targets
+    /// added later are scanned before the CIDR ones.
+    ///
+    /// Like for [QScanner::set_targets_file], only TCP connect and UDP
+    /// scans use the CIDR, which is scanned one address at a time and not
+    /// shuffled. [QScanner::set_max_cidr_hosts] does not apply.
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidTargets] if `cidr` is not a valid CIDR.
+    /// Old targets are kept.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("", "443").unwrap();
+    /// qs.set_targets_cidr("10.0.0.0/12").unwrap();
+    /// assert_eq!(qs.targets_count(), 1 << 20);
+    /// ```
+    pub fn set_targets_cidr(&mut self, cidr: &str) -> Result<(), QScanError> {
+        let cidr = IpCidr::from_str(cidr)
+            .map_err(|_| QScanError::InvalidTargets(vec![cidr.to_string()]))?;
+        self.targets_cidr = Some(cidr);
+        self.targets_file = None;
+        self.ips.clear();
+        self.unresolved.clear();
+        self.scopes.clear();
+        self.named_targets.clear();
+        Ok(())
+    }
+
+    /// Set a file where TCP connect scans append the completed targets
+    /// (one ip:port for each line), written about every second, so that an
+    /// interrupted scan can be resumed with [QScanner::resume_from].
+    pub fn set_checkpoint_file(&mut self, path: &Path) {
+        self.checkpoint_file = Some(path.to_path_buf());
+    }
+
+    /// Skip the targets recorded in the checkpoint file at `path` (see
+    /// [QScanner::set_checkpoint_file]) in the next TCP connect and UDP
+    /// scans. The skipped targets are still counted by
+    /// [QScanner::targets_count].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read. Invalid lines are
+    /// skipped with a warning.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let path = std::env::temp_dir().join("qscan_resume_doc.txt");
+    /// std::fs::write(&path, "127.0.0.1:80\n").unwrap();
+    /// let mut qs = QScanner::new("127.0.0.1", "80,443").unwrap();
+    /// qs.resume_from(&path).unwrap();
+    /// assert_eq!(qs.enumerate_targets(), vec!["127.0.0.1:443".parse().unwrap()]);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub fn resume_from(&mut self, path: &Path) -> Result<(), QScanError> {
+        self.resumed
+            .extend(read_sockets_from_file(path, "checkpoint")?);
+        Ok(())
+    }
+
+    /// Add targets addresses to existing targets
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - IPs string, comma separated and CIDR notation
+    ///
+    pub fn add_targets_addr(&mut self, addresses: &str) {
+        let remaining = self.max_targets.saturating_sub(self.ips.len() as u64);
+        self.add_parsed_addresses(self.parse_targets(addresses, remaining));
+        self.normalize_targets();
+    }
+
+    /// Add targets (ports) to existing targets
+    ///
+    /// # Arguments
+    ///
+    /// * `ports` - ports string, comma separated and ranges
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed. Existing targets are kept.
+    ///
+    pub fn add_targets_port(&mut self, ports: &str) -> Result<(), QScanError> {
+        self.ports.extend(ports_parse_with_duplicates(ports)?);
+        self.normalize_targets();
+        Ok(())
+    }
+
+    /// Add targets to existing targets
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - IPs string, comma separated and CIDR notation
+    /// * `ports` - ports string, comma separated and ranges
+    ///
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed. Existing targets are kept.
+    ///
+    pub fn add_targets(&mut self, addresses: &str, ports: &str) -> Result<(), QScanError> {
+        let ports = ports_parse_with_duplicates(ports)?;
+        let remaining = self.max_targets.saturating_sub(self.ips.len() as u64);
+        self.add_parsed_addresses(self.parse_targets(addresses, remaining));
+        self.ports.extend(ports);
+        self.normalize_targets();
+        Ok(())
+    }
+
+    /// Add a group of targets scanned only on their own `ports`, instead of
+    /// the ports of the other targets. E.g., a subnet for the web ports and
+    /// another one for SSH. The groups are scanned, in the order they were
+    /// added, by TCP connect and UDP scans after the other targets.
+    ///
+    /// # Arguments
+    ///
+    /// * `addresses` - IPs string, comma separated and CIDR notation
     /// * `ports` - ports string, comma separated and ranges
     ///
-    pub fn add_targets(&mut self, addresses: &str, ports: &str) {
-        self.ips.extend(addresses_parse(addresses));
-        self.ips = self
+    /// # Errors
+    ///
+    /// Returns [QScanError::InvalidPorts] if `ports` is malformed.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("", "").unwrap();
+    /// qs.add_target_group("10.0.0.0/30", "80,443").unwrap();
+    /// qs.add_target_group("10.0.1.1", "22").unwrap();
+    /// assert_eq!(qs.targets_count(), 9);
+    /// ```
+    pub fn add_target_group(&mut self, addresses: &str, ports: &str) -> Result<(), QScanError> {
+        let mut ports = ports_parse_with_duplicates(ports)?;
+        let parsed = self.parse_addresses(addresses, self.max_targets);
+        let mut ips = parsed.ips;
+        self.unresolved.extend(parsed.unresolved);
+        self.scopes.extend(parsed.scopes);
+
+        if self.dedup {
+            ips = ips.into_iter().unique().collect();
+            ports = ports.into_iter().unique().collect();
+        }
+        ips.retain(|ip| !self.exclude.contains(ip));
+        ports.retain(|port| !self.exclude_ports.contains(port));
+
+        self.groups.push(TargetGroup { ips, ports });
+        Ok(())
+    }
+
+    /// Set targets addresses. Old targets are discarded
+    ///
+    /// # Arguments
+    ///
+    /// * `ips` - Target IPs
+    ///
+    /// # Examples
+    ///
+    ///```
+    /// use qscan::qscanner::QScanner;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// let mut qs = QScanner::new("", "").unwrap();
+    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))];
+    /// qs.set_vec_targets_addr(target_ips);
+    /// ```
+    pub fn set_vec_targets_addr(&mut self, ips: Vec<IpAddr>) {
+        self.ips = ips;
+        self.unresolved.clear();
+        self.scopes.clear();
+        self.named_targets.clear();
+        self.targets_file = None;
+        self.targets_cidr = None;
+        self.normalize_targets();
+    }
+    /// Set targets port. Old targets are discarded
+    ///
+    /// # Arguments
+    ///
+    /// * `ports` - Target ports
+    ///
+    /// # Examples
+    ///
+    ///```
+    /// use qscan::qscanner::QScanner;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// let mut qs = QScanner::new("", "").unwrap();
+    /// let target_ports = vec![80];
+    /// qs.set_vec_targets_port(target_ports);
+    /// ```
+    pub fn set_vec_targets_port(&mut self, ports: Vec<u16>) {
+        self.ports = ports;
+        self.normalize_targets();
+    }
+
+    /// Set targets ports to the `n` most common ports (at most 100). Old
+    /// targets are discarded
+    ///
+    ///```
+    /// use qscan::qscanner::QScanner;
+    /// let mut qs = QScanner::new("127.0.0.1", "").unwrap();
+    /// qs.set_top_ports(3);
+    /// assert_eq!(qs.get_tagets_ports(), &vec![80, 23, 443]);
+    /// ```
+    pub fn set_top_ports(&mut self, n: usize) {
+        self.set_vec_targets_port(TOP_PORTS.iter().take(n).copied().collect());
+    }
+
+    /// Set targets. Old targets are discarded
+    ///
+    /// # Arguments
+    ///
+    /// * `ips` - Target IPs
+    /// * `ports` - Target ports
+    ///
+    /// # Examples
+    ///
+    ///```
+    /// use qscan::qscanner::QScanner;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// let mut qs = QScanner::new("", "").unwrap();
+    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))];
+    /// let target_ports = vec![80];
+    /// qs.set_vec_targets(target_ips, target_ports);
+    /// ```
+    pub fn set_vec_targets(&mut self, ips: Vec<IpAddr>, ports: Vec<u16>) {
+        self.ips = ips;
+        self.unresolved.clear();
+        self.scopes.clear();
+        self.named_targets.clear();
+        self.targets_file = None;
+        self.targets_cidr = None;
+        self.ports = ports;
+        self.normalize_targets();
+    }
+
+    /// Add new targets (addresses)
+    ///
+    /// # Arguments
+    ///
+    /// * `ips` - Target IPs
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// let mut qs = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))];
+    /// qs.add_vec_targets_addr(target_ips);
+    /// ```
+    pub fn add_vec_targets_addr(&mut self, ips: Vec<IpAddr>) {
+        self.ips.extend(ips);
+        self.normalize_targets();
+    }
+
+    /// Add new targets (port)
+    ///
+    /// # Arguments
+    ///
+    /// * `ports` - Target ports
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// let mut qs = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let target_ports = vec![443];
+    /// qs.add_vec_targets_port(target_ports);
+    /// ```
+    pub fn add_vec_targets_port(&mut self, ports: Vec<u16>) {
+        self.ports.extend(ports);
+        self.normalize_targets();
+    }
+
+    /// Add new targets
+    ///
+    /// # Arguments
+    ///
+    /// * `ips` - Target IPs
+    /// * `ports` - Target ports
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use std::net::{IpAddr, Ipv4Addr};
+    /// let mut qs = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))];
+    /// let target_ports = vec![443];
+    /// qs.add_vec_targets(target_ips, target_ports);
+    /// ```
+    pub fn add_vec_targets(&mut self, ips: Vec<IpAddr>, ports: Vec<u16>) {
+        self.ips.extend(ips);
+        self.ports.extend(ports);
+        self.normalize_targets();
+    }
+
+    /// Last results as a JSON array of `{"IP":..,"port":..,"state":..}`
+    /// objects (the port is omitted for ping results), `null` if no scan
+    /// ran. This format is kept as is across releases; serialize the
+    /// results themselves (`serde` feature) for all their fields.
+    #[cfg(feature = "serialize")]
+    pub fn get_last_results_as_json_string(&self) -> serde_json::Result<String> {
+        let results: Option<Vec<JsonResult>> = self
+            .last_results
+            .as_ref()
+            .map(|results| results.iter().map(JsonResult).collect());
+        serde_json::to_string(&results)
+    }
+
+    /// Async TCP connect scan
+    ///
+    /// # Return
+    ///
+    /// A vector of [SocketAddr] for each open port found.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+    /// ```
+    ///
+    pub async fn scan_tcp_connect(&mut self) -> &Vec<QScanResult> {
+        self.scan_tcp_connect_with_cancel(CancellationToken::new())
+            .await
+    }
+
+    /// Async TCP connect scan that can be aborted with a [CancellationToken].
+    ///
+    /// When `token` is cancelled no new target is scanned and the targets
+    /// being scanned are dropped.
+    ///
+    /// # Return
+    ///
+    /// The results gathered until the end of the scan or its cancellation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// use tokio_util::sync::CancellationToken;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let token = CancellationToken::new();
+    /// let res = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_tcp_connect_with_cancel(token.clone()));
+    /// ```
+    ///
+    pub async fn scan_tcp_connect_with_cancel(
+        &mut self,
+        token: CancellationToken,
+    ) -> &Vec<QScanResult> {
+        let _ = self.tcp_connect_collect(token).await;
+        self.last_results.as_ref().unwrap()
+    }
+
+    /// Async TCP connect scan, like [QScanner::scan_tcp_connect], returning
+    /// an error instead of no result when the scan is aborted by the
+    /// reachability check (see [QScanner::set_require_reachable]).
+    ///
+    /// # Errors
+    ///
+    /// [QScanError::Unreachable] if none of the probed targets responds.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// scanner.set_require_reachable(true);
+    /// let res = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_tcp_connect_checked());
+    /// assert!(res.is_ok());
+    /// ```
+    ///
+    pub async fn scan_tcp_connect_checked(&mut self) -> Result<&Vec<QScanResult>, QScanError> {
+        self.tcp_connect_collect(CancellationToken::new()).await?;
+        Ok(self.last_results.as_ref().unwrap())
+    }
+
+    /// Async TCP connect scan of exactly the targets listed in the baseline
+    /// file at `path` (one ip:port for each line, e.g. the open targets of a
+    /// previous scan), instead of the ips x ports product, to find out
+    /// quickly which of them are still open. The results are stored in the
+    /// last results: targets not open any more are reported closed or
+    /// filtered. The scan settings apply as for
+    /// [QScanner::scan_tcp_connect], e.g. the excluded addresses, the
+    /// progress and result callbacks and the deadline.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read. Invalid lines are
+    /// skipped with a warning. [QScanError::Unreachable] if the scan is
+    /// aborted by the reachability check (see
+    /// [QScanner::set_require_reachable]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let path = std::env::temp_dir().join("qscan_baseline_doc.txt");
+    /// std::fs::write(&path, "127.0.0.1:80\n").unwrap();
+    /// let mut scanner = QScanner::new("", "").unwrap();
+    /// let res = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_from_baseline(&path))
+    ///     .unwrap();
+    /// assert_eq!(res.len(), 1);
+    /// # std::fs::remove_file(&path).unwrap();
+    /// ```
+    pub async fn scan_from_baseline(
+        &mut self,
+        path: &Path,
+    ) -> Result<&Vec<QScanResult>, QScanError> {
+        let baseline = read_sockets_from_file(path, "baseline")?;
+
+        let run = self
+            .tcp_connect_run(Some(baseline), CancellationToken::new(), true)
+            .await;
+        let (sock_res, _) = run.inspect_err(|_| self.last_results = Some(Vec::new()))?;
+        self.last_results = Some(sock_res);
+        Ok(self.last_results.as_ref().unwrap())
+    }
+
+    /// Async TCP connect scan returning, along with the results, a summary
+    /// of the scan.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let (res, stats) = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_tcp_connect_with_stats());
+    /// assert_eq!(stats.attempted, res.len() as u64);
+    /// ```
+    ///
+    pub async fn scan_tcp_connect_with_stats(&mut self) -> (&Vec<QScanResult>, ScanStats) {
+        let stats = self
+            .tcp_connect_collect(CancellationToken::new())
+            .await
+            .unwrap_or(ScanStats {
+                unreachable: true,
+                ..Default::default()
+            });
+        (self.last_results.as_ref().unwrap(), stats)
+    }
+
+    /// Async TCP connect scan printing nothing, whatever the print mode,
+    /// returning the results along with a human-readable report: the open
+    /// ports of each host (see [format_results_ranges]) and a summary of the
+    /// scan, counting the targets scanned by state and the skipped ones (see
+    /// [ScanStats::skipped]). Results are not cached (see
+    /// [QScanner::get_last_results]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let (res, report) = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_tcp_connect_report());
+    /// println!("{}", report);
+    /// ```
+    ///
+    pub async fn scan_tcp_connect_report(&self) -> (Vec<QScanResult>, String) {
+        let (sock_res, stats) = match self
+            .tcp_connect_run(None, CancellationToken::new(), false)
+            .await
+        {
+            Ok(run) => run,
+            Err(e) => return (Vec::new(), format!("scan aborted: {}", e)),
+        };
+        let mut report = format_results_ranges(&sock_res);
+        if !report.is_empty() {
+            report.push('\n');
+        }
+        report.push_str(&format!(
+            "{} targets in {:.2}s: {} open, {} closed, {} filtered, {} errored, {} skipped",
+            stats.attempted + stats.skipped,
+            stats.elapsed.as_secs_f64(),
+            stats.open,
+            stats.closed,
+            stats.filtered,
+            stats.errored,
+            stats.skipped
+        ));
+
+        (sock_res, report)
+    }
+
+    /// Probe a couple of targets on distinct hosts, if any, with a single
+    /// TCP connect attempt lasting at most one second. A target responds
+    /// when it is open or actively refuses the connection.
+    ///
+    /// # Errors
+    ///
+    /// [QScanError::Unreachable] if none of the probed targets responds.
+    /// No error is returned when there are no targets.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let res = Runtime::new().unwrap().block_on(scanner.check_reachable());
+    /// assert!(res.is_ok());
+    /// ```
+    pub async fn check_reachable(&self) -> Result<(), QScanError> {
+        self.check_reachable_of(self.sock_iter()).await
+    }
+
+    /// See [QScanner::check_reachable], probing the hosts of `socks`
+    async fn check_reachable_of(
+        &self,
+        socks: impl Iterator<Item = SocketAddr>,
+    ) -> Result<(), QScanError> {
+        let mut probed: Vec<SocketAddr> = Vec::new();
+        for sock in socks.take(WARMUP_SEARCH) {
+            if probed.len() == WARMUP_TARGETS {
+                break;
+            }
+            if probed.iter().all(|p| p.ip() != sock.ip()) {
+                probed.push(sock);
+            }
+        }
+        if probed.is_empty() {
+            return Ok(());
+        }
+
+        let to = self.to.min(WARMUP_TIMEOUT);
+        let mut probes: FuturesUnordered<_> = probed
+            .iter()
+            .map(|&sock| timeout(to, self.tcp_connect(sock, 0)))
+            .collect();
+        while let Some(res) = probes.next().await {
+            match res {
+                Ok(Ok(Ok(_))) => return Ok(()),
+                Ok(Ok(Err(e))) if e.kind() == io::ErrorKind::ConnectionRefused => return Ok(()),
+                _ => {}
+            }
+        }
+
+        Err(QScanError::Unreachable { probed })
+    }
+
+    /// Scan all the targets, storing the results in the last results, none
+    /// if the scan is aborted by the reachability check
+    async fn tcp_connect_collect(
+        &mut self,
+        token: CancellationToken,
+    ) -> Result<ScanStats, QScanError> {
+        let run = self.tcp_connect_run(None, token, true).await;
+        let (sock_res, stats) = run.inspect_err(|_| self.last_results = Some(Vec::new()))?;
+        self.last_results = Some(sock_res);
+        Ok(stats)
+    }
+
+    /// Scan all the targets, or only the `baseline` sockets if any,
+    /// printing the results if `print` is set. Returns the results, with the
+    /// host names and sorted according to the settings, and their
+    /// statistics, or the error of the reachability check aborting the scan
+    /// (see [QScanner::set_require_reachable]).
+    async fn tcp_connect_run(
+        &self,
+        baseline: Option<Vec<SocketAddr>>,
+        token: CancellationToken,
+        print: bool,
+    ) -> Result<(Vec<QScanResult>, ScanStats), QScanError> {
+        let start = Instant::now();
+        let mut stats = ScanStats::default();
+        let mut unreachable = None;
+        let mut sock_res: Vec<QScanResult> = self
+            .tcp_connect_stream(baseline, token, print)
+            .filter_map(|item| {
+                let res = match item {
+                    Ok((res, errored)) => {
+                        if let QScanResult::TcpConnect(r) = &res {
+                            stats.record(r, errored);
+                        }
+                        Some(res)
+                    }
+                    Err(e) => {
+                        unreachable = Some(e);
+                        None
+                    }
+                };
+                future::ready(res)
+            })
+            .collect()
+            .await;
+        if let Some(e) = unreachable {
+            return Err(e);
+        }
+
+        if self.resolve_ptr {
+            resolve_hostnames(&mut sock_res, self.resolver_config()).await;
+        }
+
+        if self.sort_results {
+            results_sort(&mut sock_res);
+        }
+
+        stats.elapsed = start.elapsed();
+        Ok((sock_res, stats))
+    }
+
+    /// Async TCP connect scan returning a stream of results.
+    ///
+    /// Each result is yielded as soon as the scan for a single target ends,
+    /// with at most `batch` targets scanned in parallel. Results are not
+    /// cached (see [QScanner::get_last_results]). The stream is empty if the
+    /// scan is aborted by the reachability check (see
+    /// [QScanner::set_require_reachable]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use futures::StreamExt;
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let res: Vec<_> = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_tcp_connect_stream().collect());
+    /// ```
+    ///
+    pub fn scan_tcp_connect_stream(&self) -> impl Stream<Item = QScanResult> + '_ {
+        self.tcp_connect_stream(None, CancellationToken::new(), true)
+            .filter_map(|item| future::ready(item.ok().map(|(res, _)| res)))
+    }
+
+    /// Async TCP connect scan returning only the open targets, like the
+    /// original library. Results are not cached (see
+    /// [QScanner::get_last_results]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("127.0.0.1", "80").unwrap();
+    /// let open = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_tcp_connect_open_only());
+    /// ```
+    ///
+    pub async fn scan_tcp_connect_open_only(&self) -> Vec<SocketAddr> {
+        self.scan_tcp_connect_stream()
+            .filter_map(|r| async move {
+                match r {
+                    QScanResult::TcpConnect(r) if r.state == QScanTcpConnectState::Open => {
+                        Some(r.target)
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+            .await
+    }
+
+    /// Async TCP connect scan returning the results grouped by host. Results
+    /// are not cached (see [QScanner::get_last_results]).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("127.0.0.1", "80,443").unwrap();
+    /// let hosts = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_tcp_connect_by_host());
+    /// for (ip, host) in hosts {
+    ///     println!("{}: {:?}", ip, host.open_ports);
+    /// }
+    /// ```
+    ///
+    pub async fn scan_tcp_connect_by_host(&self) -> HashMap<IpAddr, HostScanResult> {
+        let (sock_res, _) = self
+            .tcp_connect_run(None, CancellationToken::new(), true)
+            .await
+            .unwrap_or_default();
+
+        let mut hosts: HashMap<IpAddr, HostScanResult> = HashMap::new();
+        for r in sock_res {
+            if let QScanResult::TcpConnect(r) = r {
+                let host = hosts.entry(r.target.ip()).or_default();
+                match r.state {
+                    QScanTcpConnectState::Open => host.open_ports.push(r.target.port()),
+                    QScanTcpConnectState::Close => host.closed += 1,
+                    QScanTcpConnectState::Filtered => host.filtered += 1,
+                }
+                if r.hostname.is_some() {
+                    host.hostname = r.hostname;
+                }
+            }
+        }
+
+        let order: HashMap<u16, usize> = self
+            .ports
+            .iter()
+            .enumerate()
+            .map(|(idx, port)| (*port, idx))
+            .collect();
+        for host in hosts.values_mut() {
+            host.open_ports.sort_by_key(|port| order.get(port).copied());
+        }
+
+        hosts
+    }
+
+    /// TCP connect scan stream of the results of all the targets, ending as
+    /// soon as `token` is cancelled. The results are printed according to
+    /// the print mode if `print` is set, and come with whether the scan of
+    /// the target failed with an error. Every TCP connect scan goes through
+    /// the reachability check here, the stream yielding only its error if
+    /// it aborts the scan.
+    fn tcp_connect_stream(
+        &self,
+        baseline: Option<Vec<SocketAddr>>,
+        token: CancellationToken,
+        print: bool,
+    ) -> impl Stream<Item = Result<(QScanResult, bool), QScanError>> + '_ {
+        stream::once(async move {
+            let reachable = self.tcp_connect_precheck(baseline.as_deref()).await;
+            (reachable, baseline)
+        })
+        .flat_map(move |(reachable, baseline)| match reachable {
+            Ok(()) => Either::Left(
+                self.tcp_connect_targets_stream(baseline, token.clone(), print)
+                    .map(Ok),
+            ),
+            Err(e) => Either::Right(stream::once(future::ready(Err(e)))),
+        })
+    }
+
+    /// Reachability check of the targets, or of the `baseline` sockets if
+    /// any, before a TCP connect scan (see [QScanner::set_warmup]). Returns
+    /// an error only if the scan must be aborted (see
+    /// [QScanner::set_require_reachable]).
+    async fn tcp_connect_precheck(
+        &self,
+        baseline: Option<&[SocketAddr]>,
+    ) -> Result<(), QScanError> {
+        if !self.warmup && !self.require_reachable {
+            return Ok(());
+        }
+        let reachable = match baseline {
+            Some(socks) => self.check_reachable_of(socks.iter().copied()).await,
+            None => self.check_reachable().await,
+        };
+        match reachable {
+            Err(e) if self.require_reachable => {
+                error!("aborting the scan: {}", e);
+                Err(e)
+            }
+            Err(e) => {
+                warn!("{}, the targets are likely unreachable", e);
+                Ok(())
+            }
+            Ok(()) => Ok(()),
+        }
+    }
+
+    /// See [QScanner::tcp_connect_stream], without the reachability check
+    fn tcp_connect_targets_stream(
+        &self,
+        baseline: Option<Vec<SocketAddr>>,
+        token: CancellationToken,
+        print: bool,
+    ) -> impl Stream<Item = (QScanResult, bool)> + '_ {
+        let batch = self.scan_batch() as usize;
+        let (sock_it, names, total): (_, Box<dyn Iterator<Item = _> + Send>, _) = match baseline {
+            Some(socks) => {
+                let socks: Vec<SocketAddr> = socks
+                    .into_iter()
+                    .filter(|s| {
+                        !self.exclude.contains(&s.ip()) && !self.exclude_ports.contains(&s.port())
+                    })
+                    .collect();
+                let total = socks.len() as u64;
+                (
+                    sockiter::SockIter::from_sockets(socks).with_skip(&self.resumed),
+                    Box::new(std::iter::empty()),
+                    total,
+                )
+            }
+            None => (
+                self.sock_iter(),
+                Box::new(
+                    self.named_targets
+                        .iter()
+                        .cartesian_product(self.ports.iter())
+                        .map(|(name, port)| (name.as_str(), *port)),
+                ),
+                self.targets_count(),
+            ),
+        };
+        let mut st = TcpScanState {
+            sock_it,
+            names,
+            ftrs: FuturesUnordered::new(),
+            // A child token, so that stopping the scan from the result
+            // control callback does not cancel the caller one
+            token: token.child_token(),
+            progress: (
+                ScanProgress {
+                    completed: 0,
+                    total,
+                    open_found: 0,
+                },
+                Instant::now(),
+                None,
+            ),
+            drained: false,
+            batch,
+            controller: self
+                .adaptive_batch
+                .then(|| adaptive::BatchController::new(batch)),
+            deadline: self.deadline.map(|d| Instant::now() + d),
+            ramp_up: self.ramp_up.map(|r| (Instant::now(), r)),
+            launched: 0,
+            host_tokens: self.stop_on_first_open.then(HostTokens::default),
+            checkpoint: self.checkpoint_file.as_deref().and_then(Checkpoint::open),
+            max_open: self.max_open_results,
+            host_timeouts: self.host_down_threshold.map(|n| (n, HashMap::new())),
+            closed_cache: self
+                .closed_cache
+                .as_ref()
+                .map(|(cache, until)| (cache, *until)),
+        };
+        self.emfile_events.store(0, Ordering::Relaxed);
+
+        while st.ftrs.len() < st.limit() {
+            if let Some((target, delay, skip)) = st.next_launch() {
+                st.ftrs
+                    .push(self.scan_target_tcp_connect_after(target, delay, skip));
+            } else {
+                break;
+            }
+        }
+
+        stream::unfold(st, move |mut st| async move {
+            if st.max_open_reached() {
+                self.finish_progress(&mut st.progress, false);
+                self.flush_output();
+                return None;
+            }
+            let result = {
+                let cancelled = st.token.cancelled();
+                futures::pin_mut!(cancelled);
+                match future::select(cancelled, st.ftrs.next()).await {
+                    Either::Right((Some(result), _)) => result,
+                    Either::Right((None, _)) => {
+                        self.finish_progress(&mut st.progress, st.drained);
+                        self.flush_output();
+                        return None;
+                    }
+                    Either::Left(_) => {
+                        self.finish_progress(&mut st.progress, false);
+                        self.flush_output();
+                        return None;
+                    }
+                }
+            };
+
+            let errored = matches!(&result, Err(e) if e.errored());
+            let res = self.tcp_connect_result(result, print);
+            self.report_progress(&mut st.progress, &res);
+
+            if let Some(ResultControl(control)) = &self.on_result_control {
+                if control(&res).is_break() {
+                    st.token.cancel();
+                }
+            }
+
+            if let (Some(checkpoint), QScanResult::TcpConnect(r)) = (&mut st.checkpoint, &res) {
+                checkpoint.record(r.target);
+            }
+
+            if let Some(controller) = &mut st.controller {
+                if self.emfile_events.swap(0, Ordering::Relaxed) > 0 {
+                    controller.back_off();
+                }
+                controller.record(matches!(
+                    &res,
+                    QScanResult::TcpConnect(r) if r.state == QScanTcpConnectState::Filtered
+                ));
+            }
+
+            if let QScanResult::TcpConnect(r) = &res {
+                if r.state == QScanTcpConnectState::Open {
+                    st.stop_host(r);
+                }
+                if r.attempts > 0 {
+                    st.record_host(r);
+                }
+            }
+
+            while st.ftrs.len() < st.limit() {
+                if let Some((target, delay, skip)) = st.next_launch() {
+                    st.ftrs
+                        .push(self.scan_target_tcp_connect_after(target, delay, skip));
+                } else {
+                    break;
+                }
+            }
+
+            Some(((res, errored), st))
+        })
+    }
+
+    /// Update the scan progress with `res` and invoke the progress callback
+    /// if the interval elapsed since the last report.
+    fn report_progress(
+        &self,
+        (progress, last, reported): &mut (ScanProgress, Instant, Option<ScanProgress>),
+        res: &QScanResult,
+    ) {
+        progress.completed += 1;
+        if let QScanResult::TcpConnect(r) = res {
+            if r.state == QScanTcpConnectState::Open {
+                progress.open_found += 1;
+            }
+        }
+
+        if let Some(ProgressCallback { interval, callback }) = &self.on_progress {
+            if last.elapsed() >= *interval {
+                callback(*progress);
+                *last = Instant::now();
+                *reported = Some(*progress);
+            }
+        }
+    }
+
+    /// Invoke the progress callback once the scan ends, unless the last
+    /// report is up to date. The total becomes the number of targets scanned
+    /// if all of them were (`drained`).
+    fn finish_progress(
+        &self,
+        (progress, _, reported): &mut (ScanProgress, Instant, Option<ScanProgress>),
+        drained: bool,
+    ) {
+        if drained {
+            progress.total = progress.completed;
+        }
+        if let Some(ProgressCallback { callback, .. }) = &self.on_progress {
+            if *reported != Some(*progress) {
+                callback(*progress);
+                *reported = Some(*progress);
+            }
+        }
+    }
+
+    /// Convert the outcome of a single TCP connect scan into a result,
+    /// printing it according to the print mode if `print` is set.
+    fn tcp_connect_result(&self, result: Result<TcpOpen, TargetError>, print: bool) -> QScanResult {
+        let r = tcp_connect_outcome(result);
+        let (ip, port) = (r.target.ip(), r.target.port());
+        let print_mode = match print {
+            true => &self.print_mode,
+            false => &QSPrintMode::NonRealTime,
+        };
+
+        match (print_mode, r.state) {
+            (QSPrintMode::RealTime, QScanTcpConnectState::Open) => {
+                self.print_line(format_args!("{}:{}", ip, port))
+            }
+            (QSPrintMode::RealTimeAll, QScanTcpConnectState::Open) => {
+                self.print_line(format_args!("{}:{}:OPEN", ip, port))
+            }
+            (QSPrintMode::RealTimeAll, QScanTcpConnectState::Filtered) => {
+                self.print_line(format_args!("{}:{}:FILTERED", ip, port))
+            }
+            (QSPrintMode::RealTimeAll, QScanTcpConnectState::Close) => {
+                self.print_line(format_args!("{}:{}:CLOSE", ip, port))
+            }
+            _ => {}
+        }
+
+        let res = QScanResult::TcpConnect(r);
+        self.audit(&res);
+        if let QSPrintMode::RealTimeVerbose = print_mode {
+            self.print_line(format_args!("{}", verbose_line(&res)));
+        }
+        if let Some(ResultCallback(callback)) = &self.on_result {
+            callback(&res);
+        }
+
+        res
+    }
+
+    /// Check a single `socket` with TCP connect, honoring the timeout, the
+    /// number of tries and the other TCP connect settings. Nothing is
+    /// printed and the result is not stored in the last results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("", "80").unwrap();
+    /// let res = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.check_socket("127.0.0.1:80".parse().unwrap()));
+    /// assert_eq!(res.target.port(), 80);
+    /// ```
+    pub async fn check_socket(&self, socket: SocketAddr) -> QScanTcpConnectResult {
+        tcp_connect_outcome(self.scan_socket_tcp_connect(socket, None).await)
+    }
+
+    /// Check whether the Unix domain (stream) socket at `path` accepts
+    /// connections, honoring the timeout. The socket is open if the
+    /// connection succeeds, closed if it fails (e.g., the path does not
+    /// exist, is not a socket, or nothing listens on it) and filtered if it
+    /// times out. Nothing is printed and the result is not stored in the
+    /// last results.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::{QScanTcpConnectState, QScanner};
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("", "").unwrap();
+    /// let res = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.check_unix_socket("/nonexistent.sock".as_ref()));
+    /// assert_eq!(res.state, QScanTcpConnectState::Close);
+    /// ```
+    #[cfg(unix)]
+    pub async fn check_unix_socket(&self, path: &Path) -> QScanUnixResult {
+        let state = match timeout(self.to, tokio::net::UnixStream::connect(path)).await {
+            Ok(Ok(_)) => QScanTcpConnectState::Open,
+            Ok(Err(_)) => QScanTcpConnectState::Close,
+            Err(_) => QScanTcpConnectState::Filtered,
+        };
+
+        QScanUnixResult {
+            path: path.to_path_buf(),
+            state,
+        }
+    }
+
+    /// Find out which outbound ports a firewall lets through, before a real
+    /// scan, by connecting to `ports` of a known good host: the first target
+    /// address, which should accept connections on all the ports (e.g.,
+    /// portquiz.net). The connections honor the TCP connect settings (e.g.,
+    /// timeout, tries, source address or proxy).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no targets addresses.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let scanner = QScanner::new("127.0.0.1", "").unwrap();
+    /// let report = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.diagnose_egress(&[80, 443]))
+    ///     .unwrap();
+    /// assert_eq!(
+    ///     report.permitted.len() + report.refused.len() + report.blocked.len(),
+    ///     2
+    /// );
+    /// ```
+    pub async fn diagnose_egress(&self, ports: &[u16]) -> Result<EgressReport, QScanError> {
+        let host = *self.ips.first().ok_or_else(|| {
+            QScanError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no target host to diagnose the egress with",
+            ))
+        })?;
+
+        let results = future::join_all(
+            ports
+                .iter()
+                .map(|&port| self.check_socket(SocketAddr::new(host, port))),
+        )
+        .await;
+
+        let mut report = EgressReport {
+            host,
+            permitted: Vec::new(),
+            refused: Vec::new(),
+            blocked: Vec::new(),
+        };
+        for r in results {
+            let port = r.target.port();
+            match r.state {
+                QScanTcpConnectState::Open => report.permitted.push(port),
+                QScanTcpConnectState::Close => report.refused.push(port),
+                QScanTcpConnectState::Filtered => report.blocked.push(port),
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// TODO: add comments
+    pub async fn scan_ping(&mut self) -> &Vec<QScanResult> {
+        self.scan_ping_with_cancel(CancellationToken::new())
+            .await
+            .expect("Error creating ping clients")
+    }
+
+    /// Async ping scan that can be aborted with a [CancellationToken].
+    ///
+    /// When `token` is cancelled no new target is pinged and the targets
+    /// being pinged are dropped.
+    ///
+    /// # Return
+    ///
+    /// The results gathered until the end of the scan or its cancellation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ICMP sockets cannot be created, e.g. with
+    /// [io::ErrorKind::PermissionDenied] when the process lacks the
+    /// privileges for raw sockets (`root` or CAP_NET_RAW). The last results
+    /// are left unchanged.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// use tokio_util::sync::CancellationToken;
+    /// let mut scanner = QScanner::new("127.0.0.1", "").unwrap();
+    /// let token = CancellationToken::new();
+    /// let res = Runtime::new()
+    ///     .unwrap()
+    ///     .block_on(scanner.scan_ping_with_cancel(token.clone()))
+    ///     .unwrap();
+    /// ```
+    ///
+    pub async fn scan_ping_with_cancel(
+        &mut self,
+        token: CancellationToken,
+    ) -> Result<&Vec<QScanResult>, QScanError> {
+        let (client_v4, client_v6) = ping_clients()?;
+        let mut ip_res = self.ping_targets(&client_v4, &client_v6, true, token).await;
+
+        if self.sort_results {
+            results_sort(&mut ip_res);
+        }
+
+        self.last_results = Some(ip_res);
+        Ok(self.last_results.as_ref().unwrap())
+    }
+
+    /// Ping all the targets (see [QScanner::scan_ping]) and return the ones
+    /// replying, e.g. to scan only their ports afterwards. The timeout, the
+    /// number of tries and the batch size are honored. Nothing is printed and
+    /// the results are not stored in the last results.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the ICMP sockets cannot be created, e.g. with
+    /// [io::ErrorKind::PermissionDenied] when the process lacks the
+    /// privileges for raw sockets (`root` or CAP_NET_RAW).
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let mut scanner = QScanner::new("127.0.0.1", "22,80").unwrap();
+    /// if let Ok(up) = Runtime::new().unwrap().block_on(scanner.ping_sweep()) {
+    ///     scanner.set_vec_targets_addr(up);
+    /// }
+    /// ```
+    pub async fn ping_sweep(&self) -> Result<Vec<IpAddr>, QScanError> {
+        let (client_v4, client_v6) = ping_clients()?;
+
+        Ok(self
+            .ping_targets(&client_v4, &client_v6, false, CancellationToken::new())
+            .await
+            .into_iter()
+            .filter_map(|r| match r {
+                QScanResult::Ping(QScanPingResult {
+                    target,
+                    state: QScanPingState::Up,
+                }) => Some(target),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// Ping all the targets until `token` is cancelled, printing the results
+    /// according to the print mode if `print` is set
+    async fn ping_targets(
+        &self,
+        client_v4: &surge_ping::Client,
+        client_v6: &surge_ping::Client,
+        print: bool,
+        token: CancellationToken,
+    ) -> Vec<QScanResult> {
+        let mut ip_res: Vec<QScanResult> = Vec::new();
+        let mut ftrs = FuturesUnordered::new();
+        let mut ip_it = self.ips.iter();
+        let print_mode = if print {
+            &self.print_mode
+        } else {
+            &QSPrintMode::NonRealTime
+        };
+
+        for _ in 0..self.scan_batch() {
+            if let Some(ip) = ip_it.next() {
+                ftrs.push(self.scan_ip_ping(*ip, client_v4, client_v6));
+            } else {
+                break;
+            }
+        }
+
+        loop {
+            let result = {
+                let cancelled = token.cancelled();
+                futures::pin_mut!(cancelled);
+                match future::select(cancelled, ftrs.next()).await {
+                    Either::Right((Some(result), _)) => result,
+                    _ => break,
+                }
+            };
+
+            if let Some(ip) = ip_it.next() {
+                ftrs.push(self.scan_ip_ping(*ip, client_v4, client_v6));
+            }
+
+            let res = match result {
+                Ok(ip) => {
+                    match print_mode {
+                        QSPrintMode::RealTime => {
+                            self.print_line(format_args!("{}", ip));
+                        }
+                        QSPrintMode::RealTimeAll => {
+                            self.print_line(format_args!("{}:UP", ip));
+                        }
+                        _ => {}
+                    }
+
+                    QScanResult::Ping(QScanPingResult {
+                        target: ip,
+                        state: QScanPingState::Up,
+                    })
+                }
+                Err(ip) => {
+                    if let QSPrintMode::RealTimeAll = print_mode {
+                        self.print_line(format_args!("{}:DOWN", ip));
+                    }
+
+                    QScanResult::Ping(QScanPingResult {
+                        target: ip,
+                        state: QScanPingState::Down,
+                    })
+                }
+            };
+
+            if let QSPrintMode::RealTimeVerbose = print_mode {
+                self.print_line(format_args!("{}", verbose_line(&res)));
+            }
+            ip_res.push(res);
+        }
+        self.flush_output();
+
+        ip_res
+    }
+
+    /// Async UDP scan
+    ///
+    /// A datagram (see [QScanner::set_udp_payload]) is sent to each target and
+    /// the port is considered open if any reply is received, closed if an ICMP
+    /// port unreachable is received and open|filtered if the timeout expires
+    /// for all the tries. On the ports with a probe (see
+    /// [QScanner::set_udp_probes]) only the matching replies are considered.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use qscan::qscanner::QScanner;
+    /// use tokio::runtime::Runtime;
+    /// let mut scanner = QScanner::new("127.0.0.1", "53").unwrap();
+    /// let res = Runtime::new().unwrap().block_on(scanner.scan_udp());
+    /// ```
+    ///
+    pub async fn scan_udp(&mut self) -> &Vec<QScanResult> {
+        let mut sock_res: Vec<QScanResult> = Vec::new();
+        let mut sock_it = self.sock_iter();
+        let mut ftrs = FuturesUnordered::new();
+
+        for _ in 0..self.scan_batch() {
+            if let Some(socket) = sock_it.next() {
+                ftrs.push(self.scan_socket_udp(socket));
+            } else {
+                break;
+            }
+        }
+
+        while let Some((socket, state)) = ftrs.next().await {
+            if let Some(socket) = sock_it.next() {
+                ftrs.push(self.scan_socket_udp(socket));
+            }
+
+            let res = QScanResult::Udp(QScanUdpResult {
+                target: socket,
+                state,
+            });
+            self.audit(&res);
+
+            match self.print_mode {
+                QSPrintMode::RealTime if state == QScanUdpState::Open => {
+                    self.print_line(format_args!("{}:{}", socket.ip(), socket.port()));
+                }
+                QSPrintMode::RealTimeAll => {
+                    self.print_line(format_args!(
+                        "{}:{}:{}",
+                        socket.ip(),
+                        socket.port(),
+                        result_state(&res)
+                    ));
+                }
+                QSPrintMode::RealTimeVerbose => {
+                    self.print_line(format_args!("{}", verbose_line(&res)))
+                }
+                _ => {}
+            }
+
+            sock_res.push(res);
+        }
+        self.flush_output();
+
+        drop(ftrs);
+        drop(sock_it);
+        if self.sort_results {
+            results_sort(&mut sock_res);
+        }
+        self.last_results = Some(sock_res);
+        self.last_results.as_ref().unwrap()
+    }
+
+    /// Iterator over all the targets, in scan order
+    fn sock_iter(&self) -> sockiter::SockIter<'_> {
+        let groups = self
+            .groups
+            .iter()
+            .map(|g| (&g.ips[..], &g.ports[..]))
+            .collect();
+        let it = self
+            .main_sock_iter()
+            .with_groups(groups)
+            .with_skip(&self.resumed);
+        match self.sample_probability() {
+            Some(p) => it.with_sample(p, self.sample_seed),
+            None => it,
+        }
+    }
+
+    /// Iterator over the targets not in a group
+    fn main_sock_iter(&self) -> sockiter::SockIter<'_> {
+        if let Some(path) = &self.targets_file {
+            let file_ips: Box<dyn Iterator<Item = IpAddr> + Send> =
+                match lazy_addresses_from_file(path, self.resolve_policy) {
+                    Ok(ips) => Box::new(ips),
+                    Err(e) => {
+                        error!("cannot read targets file {:?}: {}", path, e);
+                        Box::new(std::iter::empty())
+                    }
+                };
+            let ips = self
+                .ips
+                .iter()
+                .copied()
+                .chain(file_ips.filter(|ip| !self.exclude.contains(ip)));
+
+            sockiter::SockIter::new_lazy(Box::new(ips), &self.ports).with_scopes(&self.scopes)
+        } else if let Some(cidr) = &self.targets_cidr {
+            let ips = self
+                .ips
+                .iter()
+                .copied()
+                .chain(cidr.iter().filter(|ip| !self.exclude.contains(ip)));
+
+            sockiter::SockIter::new_lazy(Box::new(ips), &self.ports).with_scopes(&self.scopes)
+        } else if self.shuffle {
+            sockiter::SockIter::new_shuffled(&self.ips, &self.ports, self.shuffle_seed)
+                .with_scopes(&self.scopes)
+        } else if self.iter_order == IterOrder::HostMajor {
+            sockiter::SockIter::new_lazy(Box::new(self.ips.iter().copied()), &self.ports)
+                .with_scopes(&self.scopes)
+        } else {
+            sockiter::SockIter::new(&self.ips, &self.ports).with_scopes(&self.scopes)
+        }
+    }
+
+    async fn scan_socket_udp(&self, socket: SocketAddr) -> (SocketAddr, QScanUdpState) {
+        let local: SocketAddr = if socket.is_ipv4() {
+            (Ipv4Addr::UNSPECIFIED, 0).into()
+        } else {
+            (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+
+        let udp = match UdpSocket::bind(local).await {
+            Ok(udp) => udp,
+            Err(_) => return (socket, QScanUdpState::OpenFiltered),
+        };
+
+        // Connecting the socket allows to receive ICMP port unreachable errors
+        if let Err(e) = udp.connect(socket).await {
+            return (socket, udp_error_state(&e));
+        }
+
+        let probe = self.udp_probes.get(&socket.port());
+        let payload = probe.map_or(&self.udp_payload[..], |p| p.payload());
+        let mut buf = vec![0u8; if probe.is_some() { 4096 } else { 1 }];
+
+        for _ in 0..self.tries.get() {
+            if let Err(e) = udp.send(payload).await {
+                if e.kind() == io::ErrorKind::ConnectionRefused {
+                    return (socket, QScanUdpState::Close);
+                }
+                continue;
+            }
+
+            let recv = async {
+                loop {
+                    let n = udp.recv(&mut buf).await?;
+                    if probe.is_none_or(|p| p.matches(&buf[..n])) {
+                        return Ok::<(), io::Error>(());
+                    }
+                }
+            };
+
+            match timeout(self.to, recv).await {
+                Ok(Ok(())) => return (socket, QScanUdpState::Open),
+                Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    return (socket, QScanUdpState::Close);
+                }
+                _ => {}
+            }
+
+            // A pending ICMP error does not always wake up the receiver
+            if let Ok(Some(e)) = udp.take_error() {
+                if e.kind() == io::ErrorKind::ConnectionRefused {
+                    return (socket, QScanUdpState::Close);
+                }
+            }
+        }
+
+        (socket, QScanUdpState::OpenFiltered)
+    }
+
+    /// TCP connect scan of `target`, see
+    /// [QScanner::scan_socket_tcp_connect_after]
+    fn scan_target_tcp_connect_after<'a>(
+        &'a self,
+        target: ScanTarget<'a>,
+        delay: Duration,
+        skip: Option<CancellationToken>,
+    ) -> impl std::future::Future<Output = Result<TcpOpen, TargetError>> + 'a {
+        match target {
+            ScanTarget::Socket(socket) => Either::Left(Either::Left(
+                self.scan_socket_tcp_connect_after(socket, delay, skip),
+            )),
+            ScanTarget::Down(socket) => Either::Left(Either::Right(future::ready(Err(
+                skipped_target(socket, QScanSkipReason::HostDown),
+            )))),
+            ScanTarget::Cached(socket) => Either::Left(Either::Right(future::ready(Err(
+                skipped_target(socket, QScanSkipReason::CachedClosed),
+            )))),
+            ScanTarget::HostOpen(socket) => Either::Left(Either::Right(future::ready(Err(
+                skipped_target(socket, QScanSkipReason::HostOpen),
+            )))),
+            ScanTarget::Name(name, port) => {
+                Either::Right(self.scan_name_tcp_connect_after(name, port, delay, skip))
+            }
+        }
+    }
+
+    /// TCP connect scan of `port` of the host `name`, resolved after
+    /// `delay`, see [QScanner::set_resolve_at_connect]. The target is
+    /// skipped ([QScanSkipReason::HostOpen]) if `skip` is cancelled before
+    /// the scan ends, and reported filtered with the unspecified address if
+    /// `name` cannot be resolved.
+    async fn scan_name_tcp_connect_after(
+        &self,
+        name: &str,
+        port: u16,
+        delay: Duration,
+        skip: Option<CancellationToken>,
+    ) -> Result<TcpOpen, TargetError> {
+        if !delay.is_zero() {
+            time::sleep(delay).await;
+        }
+        let result = match self.resolve_name(name, port).await {
+            Ok(ip) => {
+                let socket = SocketAddr::new(ip, port);
+                skippable(self.scan_socket_tcp_connect(socket, Some(name)), skip)
+                    .await
+                    .unwrap_or_else(|| Err(skipped_target(socket, QScanSkipReason::HostOpen)))
+            }
+            Err(e) => {
+                warn!("cannot resolve {:?}: {}", name, e);
+                Err(TargetError {
+                    error: QScanError::Io(e),
+                    sock: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port),
+                    state: QScanTcpConnectState::Filtered,
+                    attempts: 0,
+                    hostname: None,
+                    skipped: None,
+                    scanned_at: None,
+                })
+            }
+        };
+        match result {
+            Ok(open) => Ok(TcpOpen {
+                hostname: Some(name.to_string()),
+                ..open
+            }),
+            Err(error) => Err(TargetError {
+                hostname: Some(name.to_string()),
+                ..error
+            }),
+        }
+    }
+
+    /// Address of the host `name` for a scan of `port`
+    async fn resolve_name(&self, name: &str, port: u16) -> io::Result<IpAddr> {
+        tokio::net::lookup_host((name, port))
+            .await
+            .and_then(|addrs| {
+                let ips = self
+                    .resolve_policy
+                    .select(addrs.map(|a| a.ip()).unique().collect());
+                ips.first().copied().ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("no address selected for {}", name),
+                    )
+                })
+            })
+    }
+
+    /// TCP connect scan of `socket` starting after `delay`. The target is
+    /// skipped ([QScanSkipReason::HostOpen]) if `skip` is cancelled before
+    /// the scan ends.
+    async fn scan_socket_tcp_connect_after(
+        &self,
+        socket: SocketAddr,
+        delay: Duration,
+        skip: Option<CancellationToken>,
+    ) -> Result<TcpOpen, TargetError> {
+        let scan = async {
+            if !delay.is_zero() {
+                time::sleep(delay).await;
+            }
+            self.scan_socket_tcp_connect(socket, None).await
+        };
+        skippable(scan, skip)
+            .await
+            .unwrap_or_else(|| Err(skipped_target(socket, QScanSkipReason::HostOpen)))
+    }
+
+    /// TCP connect scan of `socket`, whose host name is `name` if known
+    async fn scan_socket_tcp_connect(
+        &self,
+        socket: SocketAddr,
+        name: Option<&str>,
+    ) -> Result<TcpOpen, TargetError> {
+        let tries = self.tries.get();
+
+        if self.parallel_tries && tries > 1 {
+            let mut attempts: FuturesUnordered<_> = (0..tries)
+                .map(|ntry| self.tcp_connect_attempt(socket, ntry))
+                .collect();
+            let mut failed = None;
+            let mut done = 0;
+
+            while let Some((res, latency, at)) = attempts.next().await {
+                done += 1;
+                match res {
+                    Ok(Ok(_)) => {
+                        drop(attempts);
+                        return self
+                            .tcp_connect_finish(socket, name, res, (latency, at), done)
+                            .await;
+                    }
+                    // A connection error is more conclusive than a timeout
+                    Ok(Err(_)) => failed = Some((res, (latency, at), done)),
+                    Err(_) if failed.is_none() => failed = Some((res, (latency, at), done)),
+                    Err(_) => {}
+                }
+            }
+
+            let (res, timing, done) = failed.unwrap();
+            return self
+                .tcp_connect_finish(socket, name, res, timing, done)
+                .await;
+        }
+
+        for ntry in 0..tries {
+            if ntry > 0 && !self.retry_jitter.is_zero() {
+                let jitter = self
+                    .jitter_rng
+                    .lock()
+                    .unwrap()
+                    .gen_range(Duration::ZERO..self.retry_jitter);
+                time::sleep(jitter).await;
+            }
+
+            let (res, latency, at) = self.tcp_connect_attempt(socket, ntry).await;
+            let retry = match &res {
+                Ok(Ok(_)) => false,
+                Ok(Err(e)) => self.is_retryable(e),
+                Err(_) => true,
+            };
+
+            if ntry == tries - 1 || !retry {
+                return self
+                    .tcp_connect_finish(socket, name, res, (latency, at), ntry + 1)
+                    .await;
+            }
+        }
+        unreachable!();
+    }
+
+    /// Single TCP connect try, waiting for the rate limiter and, for at most
+    /// the connect timeout, for file descriptors to be available. Returns
+    /// the connection latency and the time the try ended at too.
+    async fn tcp_connect_attempt(
+        &self,
+        socket: SocketAddr,
+        ntry: u8,
+    ) -> (Result<io::Result<TcpStream>, Elapsed>, Duration, SystemTime) {
+        let mut emfile_wait = Duration::ZERO;
+        loop {
+            if let Some(rl) = &self.rate_limiter {
+                rl.acquire().await;
+            }
+
+            let start = Instant::now();
+            let res = self.tcp_connect(socket, ntry).await;
+            let latency = start.elapsed();
+            let scanned_at = SystemTime::now();
+
+            if let Some(rtt) = &self.rtt {
+                match &res {
+                    Ok(Ok(_)) => rtt.lock().unwrap().record(socket.ip(), latency),
+                    Ok(Err(e)) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                        rtt.lock().unwrap().record(socket.ip(), latency)
+                    }
+                    _ => {}
+                }
+            }
+
+            // Out of file descriptors: wait for other sockets to be
+            // closed and try again, until the target is reported errored
+            match &res {
+                Ok(Err(e)) if is_too_many_open_files(e) && emfile_wait < self.to => {
+                    self.emfile_events.fetch_add(1, Ordering::Relaxed);
+                    time::sleep(EMFILE_BACKOFF).await;
+                    emfile_wait += EMFILE_BACKOFF;
+                }
+                _ => return (res, latency, scanned_at),
+            }
+        }
+    }
+
+    /// Turn the last TCP connect try, with its latency and end time, into
+    /// the scan outcome, probing and closing the connection if it succeeded
+    async fn tcp_connect_finish(
+        &self,
+        socket: SocketAddr,
+        name: Option<&str>,
+        res: Result<io::Result<TcpStream>, Elapsed>,
+        (latency, scanned_at): (Duration, SystemTime),
+        attempts: u8,
+    ) -> Result<TcpOpen, TargetError> {
+        match res {
+            Ok(Ok(mut x)) => {
+                let probe_http = self.http_probe && self.http_ports.contains(&socket.port());
+                let http = if probe_http {
+                    self.http_probe(&mut x, socket).await
+                } else {
+                    None
+                };
+                let probe_tls = !probe_http && self.tls_probe.is_some();
+                let tls = match &self.tls_probe {
+                    Some(TlsProbe(connector)) if probe_tls => {
+                        timeout(self.to, tls::probe(connector, &mut x, socket.ip(), name))
+                            .await
+                            .ok()
+                            .flatten()
+                    }
+                    _ => None,
+                };
+
+                let (probe, closed) = match &self.service_probe {
+                    Some(ServiceProbeBox(probe)) => {
+                        (timeout(self.to, probe.probe(x)).await.ok(), Ok(()))
+                    }
+                    None => (None, self.close(&mut x).await),
+                };
+
+                // The peer may have already closed the connection after
+                // the probe: the port is open anyway
+                match closed {
+                    Err(e) if !probe_http && !probe_tls => Err(TargetError {
+                        error: QScanError::connect(socket, e),
+                        sock: socket,
+                        state: QScanTcpConnectState::Close,
+                        attempts,
+                        hostname: None,
+                        skipped: None,
+                        scanned_at: Some(scanned_at),
+                    }),
+                    _ => Ok(TcpOpen {
+                        sock: socket,
+                        latency,
+                        scanned_at,
+                        http,
+                        tls,
+                        probe,
+                        attempts,
+                        hostname: None,
+                    }),
+                }
+            }
+            Ok(Err(e)) => Err(TargetError {
+                error: QScanError::connect(socket, e),
+                sock: socket,
+                state: QScanTcpConnectState::Close,
+                attempts,
+                hostname: None,
+                skipped: None,
+                scanned_at: Some(scanned_at),
+            }),
+            Err(e) => Err(TargetError {
+                error: QScanError::connect(socket, e.into()),
+                sock: socket,
+                state: QScanTcpConnectState::Filtered,
+                attempts,
+                hostname: None,
+                skipped: None,
+                scanned_at: Some(scanned_at),
+            }),
+        }
+    }
+
+    /// Whether a TCP connect failing with `e` is tried again
+    fn is_retryable(&self, e: &io::Error) -> bool {
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => self.retry_on_refused,
+            io::ErrorKind::TimedOut
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::Interrupted => true,
+            _ => false,
+        }
+    }
+
+    /// Close `stream` according to the close mode. With [CloseMode::Reset]
+    /// the RST is sent when `stream` is dropped.
+    async fn close(&self, stream: &mut TcpStream) -> io::Result<()> {
+        match self.close_mode {
+            CloseMode::Graceful => stream.shutdown().await,
+            CloseMode::Reset => stream.set_linger(Some(Duration::ZERO)),
+        }
+    }
+
+    /// Send a `GET / HTTP/1.0` request to `target` and parse the response.
+    /// Return `None` if the response is not HTTP or is not received within
+    /// the timeout.
+    async fn http_probe(
+        &self,
+        stream: &mut TcpStream,
+        target: SocketAddr,
+    ) -> Option<QScanHttpInfo> {
+        let host = match target.ip() {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{}]", ip),
+        };
+        let req = format!("GET / HTTP/1.0\r\nHost: {}\r\n\r\n", host);
+
+        let probe = async {
+            stream.write_all(req.as_bytes()).await?;
+
+            let mut buf = Vec::new();
+            let mut chunk = [0u8; 1024];
+            while buf.len() < HTTP_PROBE_MAX && !buf.windows(4).any(|w| w == b"\r\n\r\n") {
+                let n = stream.read(&mut chunk).await?;
+                if n == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&chunk[..n]);
+            }
+            Ok::<_, io::Error>(buf)
+        };
+
+        match timeout(self.to, probe).await {
+            Ok(Ok(buf)) => http_response_parse(&buf),
+            _ => None,
+        }
+    }
+
+    /// Number of targets scanned in parallel: the batch size clamped to the
+    /// open files limit
+    fn scan_batch(&self) -> u16 {
+        let batch = clamp_batch(self.batch, fd_limit());
+
+        if batch < self.batch {
+            warn!(
+                "batch size {} exceeds the open files limit, using {}",
+                self.batch, batch
+            );
+        }
+
+        batch
+    }
+
+    async fn scan_ip_ping(
+        &self,
+        ip: IpAddr,
+        client4: &surge_ping::Client,
+        client6: &surge_ping::Client,
+    ) -> Result<IpAddr, IpAddr> {
+        let mut client = client4;
+
+        if ip.is_ipv6() {
+            client = client6;
+        }
+
+        match self.ping(client, ip).await {
+            QScanPingState::Up => Ok(ip),
+            QScanPingState::Down => Err(ip),
+        }
+    }
+
+    async fn tcp_connect(
+        &self,
+        socket: SocketAddr,
+        ntry: u8,
+    ) -> Result<io::Result<TcpStream>, Elapsed> {
+        let to = self
+            .retry_backoff
+            .timeout(self.socket_timeout(socket), ntry);
+        let addr = self.proxy.as_ref().map_or(socket, |p| p.addr);
+
+        let connect = async {
+            let tcp_socket = match (&self.local_ports, self.source_addr) {
+                (Some(range), _) => Some(self.local_port_socket(range, addr)?),
+                (None, Some(src)) => Some(bound_tcp_socket(src, addr)?),
+                (None, None) if self.tcp_fast_open => Some(match addr {
+                    SocketAddr::V4(_) => TcpSocket::new_v4()?,
+                    SocketAddr::V6(_) => TcpSocket::new_v6()?,
+                }),
+                (None, None) => None,
+            };
+            let stream = match tcp_socket {
+                Some(tcp_socket) => {
+                    if self.tcp_fast_open {
+                        enable_tcp_fast_open(&tcp_socket);
+                    }
+                    tcp_socket.connect(addr).await?
+                }
+                None => TcpStream::connect(addr).await?,
+            };
+
+            match &self.proxy {
+                Some(proxy) => socks5::connect(stream, proxy, socket).await,
+                None => Ok(stream),
+            }
+        };
+
+        // See https://stackoverflow.com/questions/30022084/how-do-i-set-connect-timeout-on-tcpstream
+        timeout(to, connect).await
+    }
+
+    /// TCP socket for connecting to `target`, bound to the next free port of
+    /// `range` (see [QScanner::set_local_port_range]) and to the source
+    /// address, if any
+    fn local_port_socket(
+        &self,
+        range: &RangeInclusive<u16>,
+        target: SocketAddr,
+    ) -> io::Result<TcpSocket> {
+        let src = match self.source_addr {
+            Some(src) => {
+                family_check(src, target)?;
+                src
+            }
+            None if target.is_ipv4() => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            None => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+        };
+        let len = (*range.end() - *range.start()) as usize + 1;
+
+        let mut in_use = None;
+        for _ in 0..len.min(LOCAL_PORT_TRIES) {
+            let offset = self.next_local_port.fetch_add(1, Ordering::Relaxed) % len;
+            let port = *range.start() + offset as u16;
+            let socket = match src {
+                IpAddr::V4(_) => TcpSocket::new_v4()?,
+                IpAddr::V6(_) => TcpSocket::new_v6()?,
+            };
+            socket.set_reuseaddr(true)?;
+            match socket.bind(SocketAddr::new(src, port)) {
+                Ok(()) => return Ok(socket),
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => in_use = Some(e),
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(in_use.unwrap())
+    }
+
+    async fn ping(&self, client: &surge_ping::Client, addr: IpAddr) -> QScanPingState {
+        let mut pinger = client
+            .pinger(addr, surge_ping::PingIdentifier(rand::random()))
+            .await;
+        pinger.timeout(self.to);
+        let mut interval = time::interval(self.ping_interval);
+        for idx in 0..self.tries.get() {
+            match pinger
+                .ping(surge_ping::PingSequence(idx as u16), &self.ping_payload)
+                .await
+            {
+                Ok((surge_ping::IcmpPacket::V4(_), _)) => {
+                    return QScanPingState::Up;
+                }
+                Ok((surge_ping::IcmpPacket::V6(_), _)) => {
+                    return QScanPingState::Up;
+                }
+                _ => {}
+            }
+            interval.tick().await;
+        }
+        QScanPingState::Down
+    }
+}
+
+/// State of a UDP target given the error returned by the socket
+fn udp_error_state(e: &io::Error) -> QScanUdpState {
+    if e.kind() == io::ErrorKind::ConnectionRefused {
+        QScanUdpState::Close
+    } else {
+        QScanUdpState::OpenFiltered
+    }
+}
+
+/// TCP socket bound to the source address `src`, for connecting to `target`
+fn bound_tcp_socket(src: IpAddr, target: SocketAddr) -> io::Result<TcpSocket> {
+    family_check(src, target)?;
+    source_tcp_socket(src)
+}
+
+/// Check that the source address `src` can connect to `target`
+fn family_check(src: IpAddr, target: SocketAddr) -> io::Result<()> {
+    if src.is_ipv4() != target.is_ipv4() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "source address {} does not match the family of {}",
+                src, target
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Set `TCP_FASTOPEN_CONNECT` on `socket`, ignoring the errors of kernels
+/// without TFO support
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn enable_tcp_fast_open(socket: &TcpSocket) {
+    use std::os::unix::io::AsRawFd;
+
+    let on: libc::c_int = 1;
+    unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &on as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        );
+    }
+}
+
+/// TCP Fast Open is not supported on this platform
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn enable_tcp_fast_open(_socket: &TcpSocket) {}
+
+/// TCP socket bound to the source address `src`
+fn source_tcp_socket(src: IpAddr) -> io::Result<TcpSocket> {
+    let tcp_socket = match src {
+        IpAddr::V4(_) => TcpSocket::new_v4()?,
+        IpAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+
+    tcp_socket.bind(SocketAddr::new(src, 0)).map_err(|e| {
+        io::Error::new(
+            e.kind(),
+            format!("cannot bind source address {}: {}", src, e),
+        )
+    })?;
+
+    Ok(tcp_socket)
+}
+
+/// Clamp `batch` to 3/4 of the open files limit, leaving room for the other
+/// files of the process
+fn clamp_batch(batch: u16, fd_limit: Option<u64>) -> u16 {
+    match fd_limit {
+        Some(limit) => {
+            let max = (limit / 4 * 3).clamp(1, u16::MAX as u64) as u16;
+            batch.min(max)
+        }
+        None => batch,
+    }
+}
+
+/// Soft limit of open files of the process, if available
+#[cfg(unix)]
+fn fd_limit() -> Option<u64> {
+    let mut rlim = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+
+    // SAFETY: rlim is a valid rlimit struct
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut rlim) } != 0
+        || rlim.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+
+    // rlim_t is not u64 on every platform
+    #[allow(clippy::unnecessary_cast)]
+    Some(rlim.rlim_cur as u64)
+}
+
+#[cfg(not(unix))]
+fn fd_limit() -> Option<u64> {
+    None
+}
+
+/// ICMP clients (IPv4 and IPv6) of ping scans
+fn ping_clients() -> io::Result<(surge_ping::Client, surge_ping::Client)> {
+    let client_v4 = surge_ping::Client::new(&surge_ping::Config::default())?;
+    let client_v6 = surge_ping::Client::new(
+        &surge_ping::Config::builder()
+            .kind(surge_ping::ICMP::V6)
+            .build(),
+    )?;
+    Ok((client_v4, client_v6))
+}
+
+/// Run `scan`, `None` if `skip` is cancelled before it ends
+async fn skippable<T>(
+    scan: impl std::future::Future<Output = T>,
+    skip: Option<CancellationToken>,
+) -> Option<T> {
+    match skip {
+        Some(skip) => {
+            let skipped = skip.cancelled();
+            futures::pin_mut!(scan, skipped);
+            match future::select(skipped, scan).await {
+                Either::Left(_) => None,
+                Either::Right((result, _)) => Some(result),
+            }
+        }
+        None => Some(scan.await),
+    }
+}
+
+/// Outcome of a target reported without connecting to it because of `reason`
+fn skipped_target(socket: SocketAddr, reason: QScanSkipReason) -> TargetError {
+    let (state, error) = match reason {
+        QScanSkipReason::HostDown => (
+            QScanTcpConnectState::Filtered,
+            io::Error::new(io::ErrorKind::TimedOut, "host is down"),
+        ),
+        QScanSkipReason::CachedClosed => (
+            QScanTcpConnectState::Close,
+            io::Error::new(io::ErrorKind::ConnectionRefused, "cached closed"),
+        ),
+        QScanSkipReason::HostOpen => (
+            QScanTcpConnectState::Close,
+            io::Error::other("host found open"),
+        ),
+    };
+    TargetError {
+        error: QScanError::connect(socket, error),
+        sock: socket,
+        state,
+        attempts: 0,
+        hostname: None,
+        skipped: Some(reason),
+        scanned_at: None,
+    }
+}
+
+/// Result of a single TCP connect scan
+fn tcp_connect_outcome(result: Result<TcpOpen, TargetError>) -> QScanTcpConnectResult {
+    match result {
+        Ok(open) => QScanTcpConnectResult {
+            target: open.sock,
+            state: QScanTcpConnectState::Open,
+            latency: Some(open.latency),
+            hostname: open.hostname,
+            http: open.http,
+            tls: open.tls,
+            probe: open.probe,
+            attempts: open.attempts,
+            error_kind: None,
+            skipped: None,
+            scanned_at: open.scanned_at,
+        },
+        Err(error) => QScanTcpConnectResult {
+            target: error.sock,
+            state: error.state,
+            latency: None,
+            hostname: error.hostname,
+            http: None,
+            tls: None,
+            probe: None,
+            attempts: error.attempts,
+            error_kind: error.skipped.is_none().then(|| error.error.kind()),
+            skipped: error.skipped,
+            scanned_at: error.scanned_at.unwrap_or_else(SystemTime::now),
+        },
+    }
+}
+
+/// Parse the status line and the `Server:` header of an HTTP response
+fn http_response_parse(buf: &[u8]) -> Option<QScanHttpInfo> {
+    let resp = String::from_utf8_lossy(buf);
+    let mut lines = resp.split("\r\n");
+
+    let status_line = lines.next()?.trim();
+    if !status_line.starts_with("HTTP/") {
+        return None;
+    }
+    let status_code = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|c| c.parse().ok());
+
+    let server = lines
+        .take_while(|l| !l.is_empty())
+        .filter_map(|l| l.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("server"))
+        .map(|(_, value)| value.trim().to_string());
+
+    Some(QScanHttpInfo {
+        status_line: status_line.to_string(),
+        status_code,
+        server,
+    })
+}
+
+/// Sort `results` by IP, then by port
+fn results_sort(results: &mut [QScanResult]) {
+    results.sort_by_key(|r| match r {
+        QScanResult::TcpConnect(r) => (r.target.ip(), r.target.port()),
+        QScanResult::Ping(r) => (r.target, 0),
+        QScanResult::Udp(r) => (r.target.ip(), r.target.port()),
+    });
+}
+
+fn is_too_many_open_files(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EMFILE) {
+        return true;
+    }
+    e.to_string().to_lowercase().contains("too many open files")
+}
+
+/// Parse ports strings, comma separated strings and ranges. Ports can also
+/// be service names (see [SERVICE_PORTS]) or the path of a file containing
+/// a ports string for each line.
+/// E.g., "80", "80,443", "80,100-200,443", "http,https,ssh", "/tmp/ports.txt"
+fn ports_parse(ports: &str) -> Result<Vec<u16>, PortParseError> {
+    Ok(ports_parse_with_duplicates(ports)?
+        .into_iter()
+        .unique()
+        .collect())
+}
+
+/// Parse ports strings (see [ports_parse]) keeping the duplicated ports
+fn ports_parse_with_duplicates(ports: &str) -> Result<Vec<u16>, PortParseError> {
+    let mut pv: Vec<u16> = Vec::new();
+    let ps: String = ports.chars().filter(|c| !c.is_whitespace()).collect();
+
+    for p in ps.split(',') {
+        if let Err(e) = port_token_parse(p, &mut pv) {
+            // Check if we have a file to read ports from
+            let file_path = Path::new(p);
+            if !file_path.is_file() {
+                return Err(e);
+            }
+            read_ports_from_file(file_path, &mut pv)?;
+        }
+
+        if pv.len() > PORTS_MAX {
+            return Err(PortParseError::TooManyPorts(PORTS_MAX));
+        }
+    }
+
+    Ok(pv)
+}
+
+/// Parse a single comma separated token (a port, a range or a service name)
+/// of a ports string, appending the ports to `pv`
+fn port_token_parse(p: &str, pv: &mut Vec<u16>) -> Result<(), PortParseError> {
+    if p.is_empty() {
+        return Ok(());
+    }
+
+    if let Some(port) = service_port(p) {
+        pv.push(port);
+        return Ok(());
+    }
+
+    let range = p
+        .split('-')
+        .map(|x| port_parse(x, p))
+        .collect::<Result<Vec<u16>, PortParseError>>()?;
+
+    match range.len() {
+        1 => pv.push(range[0]),
+        2 => {
+            if range[0] > range[1] {
+                return Err(PortParseError::ReversedRange(range[0], range[1]));
+            }
+            pv.extend(range[0]..=range[1]);
+        }
+        _ => {
+            return Err(PortParseError::InvalidRange(p.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Read socket addresses from a file, one ip:port for each line, skipping
+/// with a warning the invalid lines of the `what` file
+fn read_sockets_from_file(path: &Path, what: &str) -> Result<Vec<SocketAddr>, QScanError> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut socks = Vec::new();
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match line.parse() {
+            Ok(sock) => socks.push(sock),
+            Err(_) => warn!("line {} in {} file is not valid", idx, what),
+        }
+    }
+
+    Ok(socks)
+}
+
+/// Read ports from a file, one ports string (ports, ranges and service
+/// names, comma separated) for each line
+fn read_ports_from_file(ports_file_path: &Path, pv: &mut Vec<u16>) -> Result<(), PortParseError> {
+    let unreadable = || PortParseError::UnreadableFile(ports_file_path.display().to_string());
+    let file = File::open(ports_file_path).map_err(|_| unreadable())?;
+    let reader = BufReader::new(file);
+
+    for ports_line in reader.lines() {
+        let ports_line = ports_line.map_err(|_| unreadable())?;
+        let ps: String = ports_line.chars().filter(|c| !c.is_whitespace()).collect();
+        for p in ps.split(',') {
+            port_token_parse(p, pv)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Parse a single port number belonging to the comma separated token `token`
+fn port_parse(port: &str, token: &str) -> Result<u16, PortParseError> {
+    if port.is_empty() {
+        return Err(PortParseError::EmptyToken(token.to_string()));
+    }
+
+    if let Some(port) = service_port(port) {
+        return Ok(port);
+    }
+
+    if port.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return Err(PortParseError::UnknownService(port.to_string()));
+    }
+
+    port.parse::<u16>()
+        .map_err(|_| PortParseError::InvalidNumber(port.to_string()))
+}
+
+/// IP, port (not for ping results) and latency (if any) of a result
+fn result_fields(r: &QScanResult) -> (IpAddr, Option<u16>, Option<Duration>) {
+    match r {
+        QScanResult::TcpConnect(r) => (r.target.ip(), Some(r.target.port()), r.latency),
+        QScanResult::Udp(r) => (r.target.ip(), Some(r.target.port()), None),
+        QScanResult::Ping(r) => (r.target, None, None),
+    }
+}
+
+/// State of a result, e.g. "OPEN", "FILTERED", "UP"
+fn result_state(r: &QScanResult) -> &'static str {
+    match r {
+        QScanResult::TcpConnect(r) => match r.state {
+            QScanTcpConnectState::Open => "OPEN",
+            QScanTcpConnectState::Close => "CLOSE",
+            QScanTcpConnectState::Filtered => "FILTERED",
+        },
+        QScanResult::Udp(r) => match r.state {
+            QScanUdpState::Open => "OPEN",
+            QScanUdpState::OpenFiltered => "OPEN_FILTERED",
+            QScanUdpState::Close => "CLOSE",
+        },
+        QScanResult::Ping(r) => match r.state {
+            QScanPingState::Up => "UP",
+            QScanPingState::Down => "DOWN",
+        },
+    }
+}
+
+/// Line printed for a result in [QSPrintMode::RealTimeVerbose], e.g.
+/// `127.0.0.1:80:OPEN:0.152ms`, `127.0.0.1:81:CLOSE`, `127.0.0.1:UP`
+fn verbose_line(r: &QScanResult) -> String {
+    let (ip, port, latency) = result_fields(r);
+    let mut line = match port {
+        Some(port) => format!("{}:{}:{}", ip, port, result_state(r)),
+        None => format!("{}:{}", ip, result_state(r)),
+    };
+    if let Some(latency) = latency {
+        line.push_str(&format!(":{:.3}ms", latency.as_secs_f64() * 1000.0));
+    }
+    line
+}
+
+/// Write `results` as CSV to `writer`: a header `ip,port,state,latency_ms`
+/// and one row for each result. The port of ping results and the latency of
+/// the results without it are left empty.
+///
+/// # Examples
+///
+/// ```
+/// use qscan::qscanner::QScanner;
+/// use tokio::runtime::Runtime;
+/// let mut scanner = QScanner::new("127.0.0.1", "80").unwrap();
+/// let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+/// let mut csv = Vec::new();
+/// qscan::write_results_csv(&mut csv, res).unwrap();
+/// assert!(csv.starts_with(b"ip,port,state,latency_ms\n127.0.0.1,80,"));
+/// ```
+pub fn write_results_csv<W: std::io::Write>(
+    writer: &mut W,
+    results: &[QScanResult],
+) -> io::Result<()> {
+    writeln!(writer, "ip,port,state,latency_ms")?;
+
+    for r in results {
+        let (ip, port, latency) = result_fields(r);
+        let state = result_state(r);
+
+        writeln!(
+            writer,
+            "{},{},{},{}",
+            csv_field(&ip.to_string()),
+            port.map(|p| p.to_string()).unwrap_or_default(),
+            state,
+            latency
+                .map(|l| format!("{:.3}", l.as_secs_f64() * 1000.0))
+                .unwrap_or_default()
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Quote a CSV field if it contains separators, quotes or new lines
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// Write `results` to the SQLite database at `path`, created if missing, in
+/// a single transaction. The rows are appended to a `results` table (ip,
+/// port, state, latency_ms, scanned_at), e.g. to track the open ports across
+/// recurring scans. `scanned_at` is in milliseconds since the Unix epoch; it
+/// is left null, like the port of ping results and the latency of the
+/// results without it, for the results other than TCP connect ones.
+///
+/// # Examples
+///
+/// ```no_run
+/// use qscan::qscanner::QScanner;
+/// use tokio::runtime::Runtime;
+/// let mut scanner = QScanner::new("127.0.0.1", "1-1024").unwrap();
+/// let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+/// qscan::write_results_sqlite("/tmp/scans.db", res).unwrap();
+/// ```
+#[cfg(feature = "sqlite")]
+pub fn write_results_sqlite<P: AsRef<Path>>(
+    path: P,
+    results: &[QScanResult],
+) -> rusqlite::Result<()> {
+    let mut conn = rusqlite::Connection::open(path)?;
+    sqlite_insert(&mut conn, results)
+}
+
+/// Create the table of [write_results_sqlite] in `conn`, if missing, and
+/// insert `results` in a transaction
+#[cfg(feature = "sqlite")]
+fn sqlite_insert(conn: &mut rusqlite::Connection, results: &[QScanResult]) -> rusqlite::Result<()> {
+    let tx = conn.transaction()?;
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS results (
+            ip TEXT NOT NULL,
+            port INTEGER,
+            state TEXT NOT NULL,
+            latency_ms REAL,
+            scanned_at INTEGER
+        )",
+        [],
+    )?;
+
+    {
+        let mut insert = tx.prepare(
+            "INSERT INTO results (ip, port, state, latency_ms, scanned_at)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for r in results {
+            let (ip, port, latency) = result_fields(r);
+            let scanned_at = match r {
+                QScanResult::TcpConnect(r) => r
+                    .scanned_at
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .ok()
+                    .map(|t| t.as_millis() as i64),
+                _ => None,
+            };
+            insert.execute(rusqlite::params![
+                ip.to_string(),
+                port,
+                result_state(r),
+                latency.map(|l| l.as_secs_f64() * 1000.0),
+                scanned_at,
+            ])?;
+        }
+    }
+
+    tx.commit()
+}
+
+/// Merge the results of several scans (e.g. of scanners run concurrently on
+/// different shards of the targets) into a single set. The results for the
+/// same socket address and scan type are deduplicated keeping the most open
+/// state (open, then filtered/open|filtered, then closed); the order of the
+/// first appearance is preserved.
+///
+/// # Examples
+///
+/// ```
+/// use qscan::{QScanPingResult, QScanPingState, QScanResult};
+/// let ping = |state| {
+///     QScanResult::Ping(QScanPingResult {
+///         target: "127.0.0.1".parse().unwrap(),
+///         state,
+///     })
+/// };
+/// let merged = qscan::merge_results(vec![
+///     vec![ping(QScanPingState::Down)],
+///     vec![ping(QScanPingState::Up)],
+/// ]);
+/// assert_eq!(merged.len(), 1);
+/// assert!(matches!(&merged[0], QScanResult::Ping(r) if r.state == QScanPingState::Up));
+/// ```
+pub fn merge_results(results: Vec<Vec<QScanResult>>) -> Vec<QScanResult> {
+    let mut merged: Vec<QScanResult> = Vec::new();
+    let mut index: HashMap<(u8, IpAddr, u16), usize> = HashMap::new();
+
+    for r in results.into_iter().flatten() {
+        let key = match &r {
+            QScanResult::TcpConnect(r) => (0, r.target.ip(), r.target.port()),
+            QScanResult::Ping(r) => (1, r.target, 0),
+            QScanResult::Udp(r) => (2, r.target.ip(), r.target.port()),
+        };
+
+        match index.get(&key) {
+            Some(&i) => {
+                if result_openness(&r) > result_openness(&merged[i]) {
+                    merged[i] = r;
+                }
+            }
+            None => {
+                index.insert(key, merged.len());
+                merged.push(r);
+            }
+        }
+    }
+
+    merged
+}
+
+/// Drop the TCP connect results of the ports found open on more than
+/// `threshold` (a fraction between 0 and 1) of the scanned hosts, e.g. the
+/// ports a transparent proxy accepts for every address of a subnet. The
+/// hosts are the addresses with at least a TCP connect result; the other
+/// results are kept in their order.
+pub fn filter_ubiquitous_ports(results: Vec<QScanResult>, threshold: f64) -> Vec<QScanResult> {
+    let mut hosts: HashSet<IpAddr> = HashSet::new();
+    let mut open: HashMap<u16, HashSet<IpAddr>> = HashMap::new();
+    for r in &results {
+        if let QScanResult::TcpConnect(r) = r {
+            hosts.insert(r.target.ip());
+            if r.state == QScanTcpConnectState::Open {
+                open.entry(r.target.port())
+                    .or_default()
+                    .insert(r.target.ip());
+            }
+        }
+    }
+
+    let ubiquitous: HashSet<u16> = open
+        .into_iter()
+        .filter(|(_, ips)| ips.len() as f64 > threshold * hosts.len() as f64)
+        .map(|(port, _)| port)
+        .collect();
+
+    results
+        .into_iter()
+        .filter(
+            |r| !matches!(r, QScanResult::TcpConnect(r) if ubiquitous.contains(&r.target.port())),
+        )
+        .collect()
+}
+
+/// Format the open ports of the TCP connect and UDP `results`, one line for
+/// each host (sorted by IP) with open ports, collapsing the consecutive ports
+/// into ranges, e.g. `192.168.1.1: 80, 443, 8000-8005`. The lines are
+/// separated by new lines.
+///
+/// # Examples
+///
+/// ```
+/// use qscan::{QScanResult, QScanUdpResult, QScanUdpState};
+/// let results: Vec<QScanResult> = [53, 54, 55, 123]
+///     .iter()
+///     .map(|port| {
+///         QScanResult::Udp(QScanUdpResult {
+///             target: std::net::SocketAddr::new([127, 0, 0, 1].into(), *port),
+///             state: QScanUdpState::Open,
+///         })
+///     })
+///     .collect();
+/// assert_eq!(qscan::format_results_ranges(&results), "127.0.0.1: 53-55, 123");
+/// ```
+pub fn format_results_ranges(results: &[QScanResult]) -> String {
+    let mut hosts: BTreeMap<IpAddr, Vec<u16>> = BTreeMap::new();
+    for socket in open_sockets(results) {
+        hosts.entry(socket.ip()).or_default().push(socket.port());
+    }
+
+    hosts
+        .into_iter()
+        .map(|(ip, mut ports)| {
+            ports.sort_unstable();
+            let mut ranges: Vec<(u16, u16)> = Vec::new();
+            for port in ports {
+                match ranges.last_mut() {
+                    Some((_, end)) if *end + 1 == port => *end = port,
+                    _ => ranges.push((port, port)),
+                }
+            }
+            let ranges = ranges.iter().map(|&(start, end)| {
+                if start == end {
+                    start.to_string()
+                } else {
+                    format!("{}-{}", start, end)
+                }
+            });
+            format!("{}: {}", ip, ranges.format(", "))
+        })
+        .join("\n")
+}
+
+/// Open TCP connect and UDP targets of `results`, without duplicates
+fn open_sockets(results: &[QScanResult]) -> Vec<SocketAddr> {
+    results
+        .iter()
+        .filter(|r| result_openness(r) == 2)
+        .filter_map(|r| match r {
+            QScanResult::TcpConnect(r) => Some(r.target),
+            QScanResult::Udp(r) => Some(r.target),
+            QScanResult::Ping(_) => None,
+        })
+        .unique()
+        .collect()
+}
+
+/// Changes of the open targets between two scans, see [diff_results]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ScanDiff {
+    /// Targets open in the new scan only
+    pub opened: Vec<SocketAddr>,
+    /// Targets open in the old scan only
+    pub closed: Vec<SocketAddr>,
+}
+
+/// Compare the TCP connect and UDP results of a scan with the `old` ones of
+/// a previous scan, by target socket address, e.g. to alert on changes when
+/// monitoring a network. A target missing from a set of results is
+/// considered not open, so that open-only results can be compared too. The
+/// targets are listed in the order of their results.
+///
+/// # Examples
+///
+/// ```
+/// use qscan::{QScanResult, QScanUdpResult, QScanUdpState};
+/// let udp = |state| {
+///     QScanResult::Udp(QScanUdpResult {
+///         target: "127.0.0.1:53".parse().unwrap(),
+///         state,
+///     })
+/// };
+/// let diff = qscan::diff_results(&[udp(QScanUdpState::Close)], &[udp(QScanUdpState::Open)]);
+/// assert_eq!(diff.opened, vec!["127.0.0.1:53".parse().unwrap()]);
+/// assert!(diff.closed.is_empty());
+/// ```
+pub fn diff_results(old: &[QScanResult], new: &[QScanResult]) -> ScanDiff {
+    let (old, new) = (open_sockets(old), open_sockets(new));
+    let (old_set, new_set): (HashSet<_>, HashSet<_>) = (old.iter().collect(), new.iter().collect());
+
+    ScanDiff {
+        opened: new
+            .iter()
+            .filter(|s| !old_set.contains(s))
+            .copied()
+            .collect(),
+        closed: old
+            .iter()
+            .filter(|s| !new_set.contains(s))
+            .copied()
+            .collect(),
+    }
+}
+
+/// Rank of the state of a result, the higher the more open
+fn result_openness(r: &QScanResult) -> u8 {
+    match r {
+        QScanResult::TcpConnect(r) => match r.state {
+            QScanTcpConnectState::Open => 2,
+            QScanTcpConnectState::Filtered => 1,
+            QScanTcpConnectState::Close => 0,
+        },
+        QScanResult::Ping(r) => match r.state {
+            QScanPingState::Up => 2,
+            QScanPingState::Down => 0,
+        },
+        QScanResult::Udp(r) => match r.state {
+            QScanUdpState::Open => 2,
+            QScanUdpState::OpenFiltered => 1,
+            QScanUdpState::Close => 0,
+        },
+    }
+}
+
+/// Name of the service usually listening on `port`, e.g. `http` for 80
+///
+/// ```
+/// assert_eq!(qscan::service_name(22), Some("ssh"));
+/// ```
+pub fn service_name(port: u16) -> Option<&'static str> {
+    SERVICE_PORTS
+        .iter()
+        .find(|&&(_, p)| p == port)
+        .map(|&(service, _)| service)
+}
+
+/// Port of the service `name` (case insensitive)
+fn service_port(name: &str) -> Option<u16> {
+    SERVICE_PORTS
+        .iter()
+        .find(|(service, _)| service.eq_ignore_ascii_case(name))
+        .map(|&(_, port)| port)
+}
+
+/// Addresses excluded from the scan, see [QScanner::set_exclude_addresses]
+#[derive(Debug, Default)]
+struct Excludes {
+    ips: HashSet<IpAddr>,
+    /// Excluded CIDRs, subtracted from the targets CIDRs before expanding
+    /// them
+    cidrs: Vec<IpCidr>,
+}
+
+impl Excludes {
+    fn is_empty(&self) -> bool {
+        self.ips.is_empty() && self.cidrs.is_empty()
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.ips.contains(ip) || self.cidrs.iter().any(|c| c.contains(*ip))
+    }
+}
+
+/// Targets with their own ports, see [QScanner::add_target_group]
+#[derive(Debug)]
+struct TargetGroup {
+    ips: Vec<IpAddr>,
+    ports: Vec<u16>,
+}
+
+/// Addresses parsed from targets strings
+#[derive(Debug, Default)]
+struct ParsedAddresses {
+    ips: Vec<IpAddr>,
+    /// Host names kept as names, see [QScanner::set_resolve_at_connect]
+    names: Vec<String>,
+    /// Targets (or lines of targets files) which produced no address, e.g.
+    /// domain names which could not be resolved
+    unresolved: Vec<String>,
+    /// Zone (scope) ids of the IPv6 addresses, e.g. "fe80::1%eth0"
+    scopes: HashMap<Ipv6Addr, u32>,
+}
+
+/// Parse IP addresses strings.
+/// E.g., "1.2.3.4", "1.2.3.4,8.8.8.8", 192.168.1.0/24", "::1", "[2001:db8::1]:80",
+/// "2001:db8::/120", "fe80::1%eth0", "fe80::/120%2"
+fn addresses_parse(
+    addresses: &str,
+    max_cidr_hosts: u64,
+    max_targets: u64,
+    alt_resolver: Option<&Resolver>,
+) -> ParsedAddresses {
+    let mut parsed = addresses_parse_with_duplicates(
+        addresses,
+        max_cidr_hosts,
+        max_targets,
+        alt_resolver,
+        ResolvePolicy::All,
+        &[],
+        DNS_CONCURRENCY_DEF,
+    );
+    parsed.ips = parsed.ips.into_iter().unique().collect();
+    parsed
+}
+
+/// Parse IP addresses strings (see [addresses_parse]) keeping the duplicated
+/// addresses
+fn addresses_parse_with_duplicates(
+    addresses: &str,
+    max_cidr_hosts: u64,
+    max_targets: u64,
+    alt_resolver: Option<&Resolver>,
+    policy: ResolvePolicy,
+    exclude: &[IpCidr],
+    dns_concurrency: usize,
+) -> ParsedAddresses {
+    let mut parsed = ParsedAddresses::default();
+    let targets = targets_split(addresses);
+    let names = hostnames_resolve(
+        targets.iter().map(String::as_str),
+        alt_resolver,
+        policy,
+        dns_concurrency,
+    );
+
+    for addr in &targets {
+        let addr = addr.as_str();
+
+        if addr == STDIN_TARGETS {
+            let max_hosts = max_cidr_hosts.min(max_targets.saturating_sub(parsed.ips.len() as u64));
+            let read = read_addresses(
+                std::io::stdin().lock(),
+                alt_resolver,
+                policy,
+                max_hosts,
+                exclude,
+                dns_concurrency,
+                &mut parsed,
+            );
+            if let Err(e) = read {
+                error!("cannot read targets from stdin: {}", e);
+            }
+            continue;
+        }
+
+        let max_hosts = max_cidr_hosts.min(max_targets.saturating_sub(parsed.ips.len() as u64));
+        let parsed_addr = match names.get(addr) {
+            Some(ips) => ips.clone(),
+            None => scoped_address_parse(
+                addr,
+                alt_resolver,
+                policy,
+                max_hosts,
+                exclude,
+                &mut parsed.scopes,
+            ),
+        };
+
+        if !parsed_addr.is_empty() {
+            parsed.ips.extend(parsed_addr);
+        } else if !IpCidr::is_ip_cidr(addr) {
+            // Check if we have a file to read addresses from
+            let file_path = Path::new(addr);
+            if !file_path.is_file() {
+                warn!("not a file {:?}", addr);
+                parsed.unresolved.push(addr.to_string());
+                continue;
+            }
+
+            if read_addresses_from_file(
+                file_path,
+                alt_resolver,
+                policy,
+                max_hosts,
+                exclude,
+                dns_concurrency,
+                &mut parsed,
+            )
+            .is_err()
+            {
+                error!("unknown target {:?}", addr);
+                parsed.unresolved.push(addr.to_string());
+            }
+        }
+    }
+
+    parsed
+}
+
+/// Resolve the host names of `targets` (see [is_hostname]), each only once,
+/// with blocking lookups on up to `concurrency` scoped threads. Nothing is
+/// resolved with a single thread or name, the targets being then resolved
+/// one at a time while parsed.
+fn hostnames_resolve<'a>(
+    targets: impl Iterator<Item = &'a str>,
+    resolver: Option<&Resolver>,
+    policy: ResolvePolicy,
+    concurrency: usize,
+) -> HashMap<&'a str, Vec<IpAddr>> {
+    let names: Vec<&str> = targets.filter(|t| is_hostname(t)).unique().collect();
+    if concurrency < 2 || names.len() < 2 {
+        return HashMap::new();
+    }
+
+    let next = AtomicUsize::new(0);
+    let resolved = std::sync::Mutex::new(HashMap::with_capacity(names.len()));
+    std::thread::scope(|s| {
+        for _ in 0..concurrency.min(names.len()) {
+            s.spawn(|| {
+                while let Some(name) = names.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let ips = address_parse(name, resolver, policy, 0, &[]);
+                    resolved.lock().unwrap().insert(*name, ips);
+                }
+            });
+        }
+    });
+
+    resolved.into_inner().unwrap()
+}
+
+/// Whether the target `addr` is a host name, rather than an address, a
+/// CIDR, a range or a file of targets
+fn is_hostname(addr: &str) -> bool {
+    addr.chars().any(|c| c.is_ascii_alphabetic())
+        && addr
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        && !Path::new(addr).is_file()
+}
+
+/// Split a targets string on commas, spaces and new lines. The spaces around
+/// the separators of ranges, CIDRs and zone ids (e.g. "10.0.0.1 - 10",
+/// "10.0.0.0 / 24") and inside brackets are ignored. A standalone
+/// [`STDIN_TARGETS`] marker (e.g. "- 10.0.0.1" or "host.example -") is kept
+/// as a token of its own.
+fn targets_split(targets: &str) -> Vec<String> {
+    const JOINERS: [char; 3] = ['-', '/', '%'];
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut in_brackets = false;
+    let mut spaced = false;
+    // The token ends with a `-` glued to it across a space, which is the
+    // stdin marker unless something follows it
+    let mut dangling = false;
+
+    let mut flush = |token: &mut String, dangling: bool| {
+        if dangling {
+            token.pop();
+            tokens.push(std::mem::take(token));
+            tokens.push(STDIN_TARGETS.to_string());
+        } else {
+            tokens.push(std::mem::take(token));
+        }
+    };
+
+    for c in targets.chars() {
+        if c == ',' {
+            flush(&mut token, dangling);
+            spaced = false;
+            dangling = false;
+            continue;
+        }
+        if c.is_whitespace() {
+            spaced = !token.is_empty();
+            continue;
+        }
+
+        let joined = in_brackets
+            || (token.ends_with(JOINERS) && token != STDIN_TARGETS)
+            || JOINERS.contains(&c)
+            || (c == ':' && token.ends_with(']'));
+        if spaced && !joined {
+            flush(&mut token, dangling);
+        }
+        dangling = spaced && joined && c == '-' && !in_brackets;
+        spaced = false;
+
+        match c {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            _ => {}
+        }
+        token.push(c);
+    }
+    flush(&mut token, dangling);
+
+    tokens.retain(|t| !t.is_empty());
+    tokens
+}
+
+/// Parse an address with an optional IPv6 zone id suffix (interface name or
+/// index), e.g. "fe80::1%eth0" or "fe80::/120%2", recording the zone id of
+/// the IPv6 addresses in `scopes`
+fn scoped_address_parse(
+    addr: &str,
+    resolver: Option<&Resolver>,
+    policy: ResolvePolicy,
+    max_cidr_hosts: u64,
+    exclude: &[IpCidr],
+    scopes: &mut HashMap<Ipv6Addr, u32>,
+) -> Vec<IpAddr> {
+    let (addr, zone) = match addr.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone)),
+        None => (addr, None),
+    };
+    let ips = address_parse(addr, resolver, policy, max_cidr_hosts, exclude);
+
+    match zone {
+        Some(zone) => {
+            let scope = match zone_id_parse(zone) {
+                Some(scope) => scope,
+                None => {
+                    warn!("unknown zone {:?} of {:?}", zone, addr);
+                    return Vec::new();
+                }
+            };
+            for ip in &ips {
+                if let IpAddr::V6(ip) = ip {
+                    scopes.insert(*ip, scope);
+                }
+            }
+        }
+        None => {
+            if ips.iter().any(is_link_local) {
+                warn!(
+                    "link-local target {:?} without zone id (e.g., {}%eth0)",
+                    addr, addr
+                );
+            }
+        }
+    }
+
+    ips
+}
+
+/// Index of the zone (interface name or index) of an IPv6 address
+fn zone_id_parse(zone: &str) -> Option<u32> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Some(index);
+    }
+
+    #[cfg(unix)]
+    {
+        let name = std::ffi::CString::new(zone).ok()?;
+        // SAFETY: `name` is a valid NUL terminated string
+        let index = unsafe { libc::if_nametoindex(name.as_ptr()) };
+        if index != 0 {
+            return Some(index);
+        }
+    }
+
+    None
+}
+
+/// Source address of the host for reaching `remote`, found connecting (no
+/// packet is sent) a UDP socket
+fn primary_ip(remote: IpAddr) -> std::io::Result<IpAddr> {
+    let local = match remote {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = std::net::UdpSocket::bind(SocketAddr::new(local, 0))?;
+    socket.connect(SocketAddr::new(remote, 53))?;
+    Ok(socket.local_addr()?.ip())
+}
+
+/// Hosts of the local subnet of `local`, see [QScanner::local_subnet_targets]
+fn subnet_targets(local: IpAddr) -> Vec<IpAddr> {
+    match local {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            (1..=254)
+                .map(|d| IpAddr::V4(Ipv4Addr::new(a, b, c, d)))
+                .filter(|addr| *addr != local)
+                .collect()
+        }
+        IpAddr::V6(ip) => {
+            let prefix = u128::from(ip) & !u128::from(u64::MAX);
+            (1..=LOCAL_SUBNET_V6_HOSTS)
+                .map(|id| IpAddr::V6(Ipv6Addr::from(prefix | u128::from(id))))
+                .filter(|addr| *addr != local)
+                .collect()
+        }
+    }
+}
+
+/// IPv6 link-local unicast address (fe80::/10)
+fn is_link_local(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        IpAddr::V4(_) => false,
+    }
+}
+
+fn address_parse(
+    addr: &str,
+    resolver: Option<&Resolver>,
+    policy: ResolvePolicy,
+    max_cidr_hosts: u64,
+    exclude: &[IpCidr],
+) -> Vec<IpAddr> {
+    if let Ok(cidr) = IpCidr::from_str(addr) {
+        // Only the blocks surviving the excluded CIDRs are expanded
+        let blocks = cidr_subtract(cidr, exclude);
+        if blocks.iter().map(cidr_hosts).sum::<u128>() > max_cidr_hosts as u128 {
+            error!(
+                "CIDR {:?} exceeds the maximum of {} hosts",
+                addr, max_cidr_hosts
+            );
+            return Vec::new();
+        }
+
+        return blocks.iter().flat_map(|b| b.iter()).collect();
+    }
+
+    if let Some(range) = ipv4_range_parse(addr) {
+        return match range {
+            Ok((start, end)) if (u32::from(end) - u32::from(start)) as u64 >= max_cidr_hosts => {
+                error!(
+                    "range {:?} exceeds the maximum of {} hosts",
+                    addr, max_cidr_hosts
+                );
+                Vec::new()
+            }
+            Ok((start, end)) => (u32::from(start)..=u32::from(end))
+                .map(|ip| IpAddr::V4(Ipv4Addr::from(ip)))
+                .collect(),
+            Err(e) => {
+                error!("invalid range {:?}: {}", addr, e);
+                Vec::new()
+            }
+        };
+    }
+
+    if let Some(ip) = bracketed_address_parse(addr) {
+        return vec![ip];
+    }
+
+    if let Ok(ip) = addr.parse::<IpAddr>() {
+        return vec![ip];
+    }
+
+    let ips = format!("{}:{}", &addr, 80)
+        .to_socket_addrs()
+        .map(socket_addrs_ips)
+        .unwrap_or_else(|_| domain_name_resolve_to_ip(addr, resolver));
+    policy.select(ips)
+}
+
+/// Addresses of the resolved socket addresses `addrs`, without duplicates
+/// (e.g., the same address for several socket types), in resolution order
+fn socket_addrs_ips(addrs: impl Iterator<Item = SocketAddr>) -> Vec<IpAddr> {
+    addrs.map(|sa| sa.ip()).unique().collect()
+}
+
+/// Parse an IPv4 range, e.g. "192.168.1.1-192.168.1.50" or the last octet
+/// shorthand "192.168.1.1-50". Both ends must be in the same /24 network.
+/// Returns `None` if `addr` is not a range.
+fn ipv4_range_parse(addr: &str) -> Option<Result<(Ipv4Addr, Ipv4Addr), &'static str>> {
+    let (start, end) = addr.split_once('-')?;
+    let start: Ipv4Addr = start.parse().ok()?;
+
+    let end = match end.parse::<u8>() {
+        Ok(last) => {
+            let [a, b, c, _] = start.octets();
+            Ipv4Addr::new(a, b, c, last)
+        }
+        Err(_) => match end.parse::<Ipv4Addr>() {
+            Ok(end) => end,
+            Err(_) => return Some(Err("invalid end address")),
+        },
+    };
+
+    Some(if start.octets()[..3] != end.octets()[..3] {
+        Err("the ends are in different /24 networks")
+    } else if start > end {
+        Err("the start is greater than the end")
+    } else {
+        Ok((start, end))
+    })
+}
+
+/// Minimal set of CIDRs covering the addresses of `cidr` not in any of the
+/// `exclude` CIDRs, in address order. E.g., "10.0.0.0/24" minus
+/// "10.0.0.128/25" is "10.0.0.0/25".
+fn cidr_subtract(cidr: IpCidr, exclude: &[IpCidr]) -> Vec<IpCidr> {
+    let mut blocks = Vec::new();
+    let mut stack = vec![cidr];
+
+    while let Some(block) = stack.pop() {
+        let (first, last) = (block.first_as_ip_addr(), block.last_as_ip_addr());
+
+        if exclude
+            .iter()
+            .any(|e| e.contains(first) && e.contains(last))
+        {
+            continue;
+        }
+
+        let overlaps = exclude
+            .iter()
+            .any(|e| e.contains(first) || block.contains(e.first_as_ip_addr()));
+        match cidr_halves(&block) {
+            Some((low, high)) if overlaps => {
+                stack.push(high);
+                stack.push(low);
+            }
+            _ => blocks.push(block),
+        }
+    }
+
+    blocks
+}
+
+/// The two halves of a CIDR, `None` for a single address
+fn cidr_halves(cidr: &IpCidr) -> Option<(IpCidr, IpCidr)> {
+    match cidr {
+        IpCidr::V4(c) if c.get_bits() < 32 => {
+            let bits = c.get_bits() + 1;
+            let high = c.get_prefix() | (1 << (32 - bits));
+            Some((
+                IpCidr::V4(Ipv4Cidr::from_prefix_and_bits(c.get_prefix(), bits).ok()?),
+                IpCidr::V4(Ipv4Cidr::from_prefix_and_bits(high, bits).ok()?),
+            ))
+        }
+        IpCidr::V6(c) if c.get_bits() < 128 => {
+            let bits = c.get_bits() + 1;
+            let high = c.get_prefix() | (1 << (128 - bits));
+            Some((
+                IpCidr::V6(Ipv6Cidr::from_prefix_and_bits(c.get_prefix(), bits).ok()?),
+                IpCidr::V6(Ipv6Cidr::from_prefix_and_bits(high, bits).ok()?),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// Number of hosts in a CIDR
+fn cidr_hosts(cidr: &IpCidr) -> u128 {
+    match cidr {
+        IpCidr::V4(c) => c.size() as u128,
+        IpCidr::V6(c) => match c.get_bits() {
+            0 => u128::MAX,
+            bits => 1 << (128 - bits),
+        },
+    }
+}
+
+/// Parse IPv6 addresses in brackets, with an optional port which is ignored.
+/// E.g., "[::1]", "[2001:db8::1]:80"
+fn bracketed_address_parse(addr: &str) -> Option<IpAddr> {
+    let (ip, rest) = addr.strip_prefix('[')?.split_once(']')?;
+
+    if !rest.is_empty() && rest.strip_prefix(':')?.parse::<u16>().is_err() {
+        return None;
+    }
+
+    ip.parse::<Ipv6Addr>().ok().map(IpAddr::V6)
+}
+
+fn domain_name_resolve_to_ip(source: &str, alt_resolver: Option<&Resolver>) -> Vec<IpAddr> {
+    if let Ok(addrs) = source.to_socket_addrs() {
+        socket_addrs_ips(addrs)
+    } else if let Some(Ok(addrs)) = alt_resolver.map(|r| r.lookup_ip(source)) {
+        addrs.iter().unique().collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Set the host name of the TCP connect results with open ports. Each IP is
+/// looked up only once.
+async fn resolve_hostnames(
+    results: &mut [QScanResult],
+    config: Option<(ResolverConfig, ResolverOpts)>,
+) {
+    let resolver = match config {
+        Some((config, opts)) => TokioAsyncResolver::tokio(config, opts),
+        None => TokioAsyncResolver::tokio_from_system_conf(),
+    };
+    let resolver = match resolver {
+        Ok(r) => r,
+        Err(e) => {
+            warn!("cannot create resolver: {}", e);
+            return;
+        }
+    };
+    let mut cache: HashMap<IpAddr, Option<String>> = HashMap::new();
+
+    for r in results.iter_mut() {
+        if let QScanResult::TcpConnect(res) = r {
+            if res.state != QScanTcpConnectState::Open || res.hostname.is_some() {
+                continue;
+            }
+
+            let ip = res.target.ip();
+            res.hostname = match cache.get(&ip) {
+                Some(hostname) => hostname.clone(),
+                None => {
+                    let hostname = reverse_lookup(&resolver, ip).await;
+                    cache.insert(ip, hostname.clone());
+                    hostname
+                }
+            };
+        }
+    }
+}
+
+/// Reverse DNS lookup of `ip`, `None` if there is no PTR record
+async fn reverse_lookup(resolver: &TokioAsyncResolver, ip: IpAddr) -> Option<String> {
+    let lookup = resolver.reverse_lookup(ip).await.ok()?;
+    let name = lookup.iter().next()?;
+
+    Some(name.to_utf8().trim_end_matches('.').to_string())
+}
+
+/// Addresses of the file at `path`, lazily read and expanded
+fn lazy_addresses_from_file(
+    path: &Path,
+    policy: ResolvePolicy,
+) -> io::Result<impl Iterator<Item = IpAddr> + Send> {
+    let reader = BufReader::new(File::open(path)?);
+
+    Ok(reader
+        .lines()
+        .enumerate()
+        .flat_map(move |(idx, line)| match line {
+            Ok(line) => lazy_address_parse(line.trim(), idx, policy),
+            Err(_) => {
+                warn!("line {} in file is not valid", idx);
+                Box::new(std::iter::empty())
+            }
+        }))
+}
+
+/// Addresses of a single line of a targets file, CIDRs are expanded lazily
+fn lazy_address_parse(
+    addr: &str,
+    idx: usize,
+    policy: ResolvePolicy,
+) -> Box<dyn Iterator<Item = IpAddr> + Send> {
+    if addr.is_empty() {
+        return Box::new(std::iter::empty());
+    }
+
+    if let Ok(cidr) = IpCidr::from_str(addr) {
+        return Box::new(cidr.iter());
+    }
+
+    let ips = address_parse(addr, None, policy, u64::MAX, &[]);
+    if ips.is_empty() {
+        warn!("unknown target {:?} in line {} of file", addr, idx);
+    }
+
+    Box::new(ips.into_iter())
+}
+
+// Read ips or fomain name from a file into `parsed`, expanding at most
+// `max_hosts` addresses
+fn read_addresses_from_file(
+    addrs_file_path: &Path,
+    backup_resolver: Option<&Resolver>,
+    policy: ResolvePolicy,
+    max_hosts: u64,
+    exclude: &[IpCidr],
+    dns_concurrency: usize,
+    parsed: &mut ParsedAddresses,
+) -> Result<(), std::io::Error> {
+    let file = File::open(addrs_file_path)?;
+    read_addresses(
+        BufReader::new(file),
+        backup_resolver,
+        policy,
+        max_hosts,
+        exclude,
+        dns_concurrency,
+        parsed,
+    )
+}
+
+/// Read the addresses of `reader`, one target for each line, see
+/// [read_addresses_from_file]
+fn read_addresses(
+    reader: impl BufRead,
+    backup_resolver: Option<&Resolver>,
+    policy: ResolvePolicy,
+    max_hosts: u64,
+    exclude: &[IpCidr],
+    dns_concurrency: usize,
+    parsed: &mut ParsedAddresses,
+) -> Result<(), std::io::Error> {
+    let lines: Vec<std::io::Result<String>> = reader.lines().collect();
+    let names = hostnames_resolve(
+        lines.iter().filter_map(|l| l.as_deref().ok()),
+        backup_resolver,
+        policy,
+        dns_concurrency,
+    );
+    let start = parsed.ips.len();
+
+    for (idx, address_line) in lines.iter().enumerate() {
+        if let Ok(address) = address_line {
+            let remaining = max_hosts.saturating_sub((parsed.ips.len() - start) as u64);
+            let ips = match names.get(address.as_str()) {
+                Some(ips) => ips.clone(),
+                None => scoped_address_parse(
+                    address,
+                    backup_resolver,
+                    policy,
+                    remaining,
+                    exclude,
+                    &mut parsed.scopes,
+                ),
+            };
+
+            if ips.is_empty() && !address.is_empty() && !IpCidr::is_ip_cidr(address) {
+                parsed.unresolved.push(address.clone());
+            } else {
+                parsed.ips.extend(ips);
+            }
+        } else {
+            warn!("line {} in file is not valid", idx);
+        }
+    }
+
+    Ok(())
+}
+
+mod ratelimit {
+    use std::sync::Mutex;
+    use std::time::Duration;
+    use tokio::time::{self, Instant};
+
+    /// Token bucket of size one refilled at a fixed rate. Each
+    /// [RateLimiter::acquire] reserves the next available slot, so concurrent
+    /// callers are served in order and none of them is starved.
+    #[derive(Debug)]
+    pub struct RateLimiter {
+        interval: Duration,
+        next: Mutex<Option<Instant>>,
+    }
+
+    impl RateLimiter {
+        pub fn new(pps: u32) -> Self {
+            Self {
+                interval: Duration::from_secs(1) / pps,
+                next: Mutex::new(None),
+            }
+        }
+
+        /// Wait until a new attempt can be initiated
+        pub async fn acquire(&self) {
+            let slot = {
+                let mut next = self.next.lock().unwrap();
+                let now = Instant::now();
+                let slot = match *next {
+                    Some(n) if n > now => n,
+                    _ => now,
+                };
+                *next = Some(slot + self.interval);
+                slot
+            };
+
+            time::sleep_until(slot).await;
+        }
+    }
+}
+
+mod adaptive {
+    use std::collections::HashMap;
+    use std::net::IpAddr;
+    use std::time::Duration;
+
+    /// Initial number of targets scanned in parallel
+    const START: usize = 64;
+    /// Error rate (percent) over which the batch is halved
+    const HIGH_ERRORS: usize = 20;
+    /// Error rate (percent) under which the batch is doubled
+    const LOW_ERRORS: usize = 5;
+
+    /// Batch size controller. The error rate is evaluated every `limit`
+    /// results, i.e., about once per batch.
+    #[derive(Debug)]
+    pub struct BatchController {
+        limit: usize,
+        max: usize,
+        results: usize,
+        errors: usize,
+    }
+
+    impl BatchController {
+        pub fn new(max: usize) -> Self {
+            Self {
+                limit: START.min(max).max(1),
+                max: max.max(1),
+                results: 0,
+                errors: 0,
+            }
+        }
+
+        pub fn limit(&self) -> usize {
+            self.limit
+        }
+
+        /// Record the outcome of a single target scan
+        pub fn record(&mut self, error: bool) {
+            self.results += 1;
+            if error {
+                self.errors += 1;
+            }
+
+            if self.results < self.limit {
+                return;
+            }
+
+            let rate = self.errors * 100 / self.results;
+            if rate > HIGH_ERRORS {
+                self.back_off();
+            } else {
+                if rate < LOW_ERRORS {
+                    self.limit = (self.limit * 2).min(self.max);
+                }
+                self.reset();
+            }
+        }
+
+        /// Halve the batch size immediately
+        pub fn back_off(&mut self) {
+            self.limit = (self.limit / 2).max(1);
+            self.reset();
+        }
+
+        fn reset(&mut self) {
+            self.results = 0;
+            self.errors = 0;
+        }
+    }
+
+    /// RTT samples of a host before its timeout is scaled
+    const RTT_SAMPLES: u32 = 3;
+    /// Lower bound of the scaled timeouts
+    const RTT_TIMEOUT_MIN: Duration = Duration::from_millis(100);
+
+    /// Smoothed RTT and RTT variation of a host (RFC 6298)
+    #[derive(Debug, Clone, Copy)]
+    struct HostRtt {
+        srtt: Duration,
+        rttvar: Duration,
+        samples: u32,
+    }
+
+    /// Per host RTT estimator of the adaptive timeout
+    #[derive(Debug, Default)]
+    pub struct RttEstimator {
+        hosts: HashMap<IpAddr, HostRtt>,
+    }
+
+    impl RttEstimator {
+        /// Record the RTT of a connect to `ip`
+        pub fn record(&mut self, ip: IpAddr, rtt: Duration) {
+            self.hosts
+                .entry(ip)
+                .and_modify(|h| {
+                    h.rttvar = (h.rttvar * 3 + h.srtt.abs_diff(rtt)) / 4;
+                    h.srtt = (h.srtt * 7 + rtt) / 8;
+                    h.samples += 1;
+                })
+                .or_insert(HostRtt {
+                    srtt: rtt,
+                    rttvar: rtt / 2,
+                    samples: 1,
+                });
+        }
+
+        /// Timeout of the connects to `ip`, at most `ceiling`
+        pub fn timeout(&self, ip: IpAddr, ceiling: Duration) -> Duration {
+            match self.hosts.get(&ip) {
+                Some(h) if h.samples >= RTT_SAMPLES => {
+                    (h.srtt + h.rttvar * 4).max(RTT_TIMEOUT_MIN).min(ceiling)
+                }
+                _ => ceiling,
+            }
+        }
+    }
+}
+
+mod socks5 {
+    //! Minimal SOCKS5 client (RFC 1928), with username/password
+    //! authentication (RFC 1929)
+
+    use super::ProxyConfig;
+    use std::net::SocketAddr;
+    use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpStream;
+
+    const VERSION: u8 = 0x05;
+    const NO_AUTH: u8 = 0x00;
+    const USER_PASS: u8 = 0x02;
+    const NO_ACCEPTABLE: u8 = 0xff;
+    const CMD_CONNECT: u8 = 0x01;
+    const ATYP_V4: u8 = 0x01;
+    const ATYP_DOMAIN: u8 = 0x03;
+    const ATYP_V6: u8 = 0x04;
+    const REP_SUCCEEDED: u8 = 0x00;
+    const REP_REFUSED: u8 = 0x05;
+
+    /// Ask the proxy connected through `stream` to connect to `target`
+    pub async fn connect(
+        mut stream: TcpStream,
+        proxy: &ProxyConfig,
+        target: SocketAddr,
+    ) -> io::Result<TcpStream> {
+        authenticate(&mut stream, proxy).await?;
+
+        let mut req = vec![VERSION, CMD_CONNECT, 0x00];
+        match target {
+            SocketAddr::V4(a) => {
+                req.push(ATYP_V4);
+                req.extend(a.ip().octets());
+            }
+            SocketAddr::V6(a) => {
+                req.push(ATYP_V6);
+                req.extend(a.ip().octets());
+            }
+        }
+        req.extend(target.port().to_be_bytes());
+        stream.write_all(&req).await?;
+
+        let mut rep = [0u8; 4];
+        stream.read_exact(&mut rep).await?;
+        check_version(rep[0])?;
+
+        match rep[1] {
+            REP_SUCCEEDED => {}
+            REP_REFUSED => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "connection refused by the proxy target",
+                ))
+            }
+            code => {
+                return Err(io::Error::other(format!(
+                    "proxy CONNECT failed with code {}",
+                    code
+                )))
+            }
+        }
+
+        // Skip the bound address
+        let addr_len = match rep[3] {
+            ATYP_V4 => 4,
+            ATYP_V6 => 16,
+            ATYP_DOMAIN => stream.read_u8().await? as usize,
+            atyp => return Err(invalid_data(format!("unknown address type {}", atyp))),
+        };
+        let mut bound = vec![0u8; addr_len + 2];
+        stream.read_exact(&mut bound).await?;
+
+        Ok(stream)
+    }
+
+    async fn authenticate(stream: &mut TcpStream, proxy: &ProxyConfig) -> io::Result<()> {
+        let methods: &[u8] = match proxy.credentials {
+            Some(_) => &[NO_AUTH, USER_PASS],
+            None => &[NO_AUTH],
+        };
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend(methods);
+        stream.write_all(&greeting).await?;
+
+        let mut choice = [0u8; 2];
+        stream.read_exact(&mut choice).await?;
+        check_version(choice[0])?;
+
+        match (choice[1], &proxy.credentials) {
+            (NO_AUTH, _) => Ok(()),
+            (USER_PASS, Some((username, password))) => {
+                let mut req = vec![0x01, username.len() as u8];
+                req.extend(username.as_bytes());
+                req.push(password.len() as u8);
+                req.extend(password.as_bytes());
+                stream.write_all(&req).await?;
+
+                let mut status = [0u8; 2];
+                stream.read_exact(&mut status).await?;
+                if status[1] != 0x00 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::PermissionDenied,
+                        "proxy authentication failed",
+                    ));
+                }
+                Ok(())
+            }
+            (NO_ACCEPTABLE, _) => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "no acceptable proxy authentication method",
+            )),
+            (method, _) => Err(invalid_data(format!(
+                "unexpected proxy authentication method {}",
+                method
+            ))),
+        }
+    }
+
+    fn check_version(version: u8) -> io::Result<()> {
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unexpected SOCKS version {}",
+                version
+            )));
+        }
+        Ok(())
+    }
+
+    fn invalid_data(msg: String) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    }
+}
+
+mod tls {
+    //! TLS probe: handshake without certificate verification and minimal
+    //! DER parsing of the leaf certificate (subject CN and notAfter)
+
+    use super::QScanTlsInfo;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+    use std::time::SystemTime;
+    use tokio::net::TcpStream;
+    use tokio_rustls::rustls::{
+        self,
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, ProtocolVersion, ServerName,
+    };
+    use tokio_rustls::TlsConnector;
+
+    const TAG_SEQUENCE: u8 = 0x30;
+    const TAG_SET: u8 = 0x31;
+    const TAG_OID: u8 = 0x06;
+    const TAG_UTC_TIME: u8 = 0x17;
+    const TAG_GENERALIZED_TIME: u8 = 0x18;
+    const TAG_VERSION: u8 = 0xa0;
+    const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+    /// The probe records the certificate of any server, trusted or not
+    struct AcceptAnyCert;
+
+    impl ServerCertVerifier for AcceptAnyCert {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &Certificate,
+            _intermediates: &[Certificate],
+            _server_name: &ServerName,
+            _scts: &mut dyn Iterator<Item = &[u8]>,
+            _ocsp_response: &[u8],
+            _now: SystemTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            Ok(ServerCertVerified::assertion())
+        }
+    }
+
+    pub fn connector() -> TlsConnector {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(AcceptAnyCert))
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    }
+
+    /// Perform a TLS handshake with `ip` over `stream`, sending `name` as
+    /// SNI if it is a valid DNS name
+    pub async fn probe(
+        connector: &TlsConnector,
+        stream: &mut TcpStream,
+        ip: IpAddr,
+        name: Option<&str>,
+    ) -> Option<QScanTlsInfo> {
+        let server_name = name
+            .and_then(|name| ServerName::try_from(name).ok())
+            .unwrap_or(ServerName::IpAddress(ip));
+        let tls = connector.connect(server_name, stream).await.ok()?;
+        let (_, conn) = tls.get_ref();
+
+        let version = match conn.protocol_version()? {
+            ProtocolVersion::TLSv1_3 => "TLSv1.3".to_string(),
+            ProtocolVersion::TLSv1_2 => "TLSv1.2".to_string(),
+            v => format!("{:?}", v),
+        };
+        let (subject_cn, not_after) = conn
+            .peer_certificates()
+            .and_then(|certs| certs.first())
+            .and_then(|leaf| leaf_info(&leaf.0))
+            .unwrap_or((None, None));
+
+        Some(QScanTlsInfo {
+            version,
+            subject_cn,
+            not_after,
+        })
+    }
+
+    /// Subject common name and expiry of a DER encoded X.509 certificate
+    pub fn leaf_info(der: &[u8]) -> Option<(Option<String>, Option<String>)> {
+        let (_, cert, _) = der_next(der, TAG_SEQUENCE)?;
+        let (_, mut tbs, _) = der_next(cert, TAG_SEQUENCE)?;
+
+        if tbs.first() == Some(&TAG_VERSION) {
+            tbs = der_read(tbs)?.2;
+        }
+        // serialNumber, signature, issuer
+        for _ in 0..3 {
+            tbs = der_read(tbs)?.2;
+        }
+        let (_, validity, tbs) = der_next(tbs, TAG_SEQUENCE)?;
+        let (_, subject, _) = der_next(tbs, TAG_SEQUENCE)?;
+
+        let not_after = der_read(validity)
+            .and_then(|(_, _, rest)| der_read(rest))
+            .and_then(|(tag, time, _)| time_parse(tag, time));
+
+        Some((common_name(subject), not_after))
+    }
+
+    /// First common name of a distinguished name (sequence of sets of
+    /// attribute type and value)
+    fn common_name(mut name: &[u8]) -> Option<String> {
+        while !name.is_empty() {
+            let (_, mut rdn, rest) = der_next(name, TAG_SET)?;
+            while !rdn.is_empty() {
+                let (_, attr, next) = der_next(rdn, TAG_SEQUENCE)?;
+                let (_, oid, value) = der_next(attr, TAG_OID)?;
+                if oid == OID_COMMON_NAME {
+                    let (_, value, _) = der_read(value)?;
+                    return Some(String::from_utf8_lossy(value).into_owned());
+                }
+                rdn = next;
+            }
+            name = rest;
+        }
+        None
+    }
+
+    /// Format UTCTime (YYMMDDHHMMSSZ) and GeneralizedTime (YYYYMMDDHHMMSSZ)
+    /// as YYYY-MM-DDTHH:MM:SSZ
+    fn time_parse(tag: u8, time: &[u8]) -> Option<String> {
+        let time = std::str::from_utf8(time).ok()?;
+        let full = match tag {
+            TAG_UTC_TIME => {
+                let yy: u8 = time.get(..2)?.parse().ok()?;
+                format!("{}{}", if yy < 50 { "20" } else { "19" }, time)
+            }
+            TAG_GENERALIZED_TIME => time.to_string(),
+            _ => return None,
+        };
+        if full.len() != 15
+            || !full.ends_with('Z')
+            || !full[..14].bytes().all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+
+        Some(format!(
+            "{}-{}-{}T{}:{}:{}Z",
+            &full[..4],
+            &full[4..6],
+            &full[6..8],
+            &full[8..10],
+            &full[10..12],
+            &full[12..14]
+        ))
+    }
+
+    /// Read a DER element with the expected tag
+    fn der_next(buf: &[u8], tag: u8) -> Option<(u8, &[u8], &[u8])> {
+        der_read(buf).filter(|&(t, _, _)| t == tag)
+    }
+
+    /// Read a DER element, returning its tag, its content and the remaining
+    /// bytes
+    fn der_read(buf: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+        let (&tag, buf) = buf.split_first()?;
+        let (&len, mut buf) = buf.split_first()?;
+
+        let len = if len & 0x80 == 0 {
+            len as usize
+        } else {
+            let nbytes = (len & 0x7f) as usize;
+            if nbytes == 0 || nbytes > std::mem::size_of::<usize>() || buf.len() < nbytes {
+                return None;
+            }
+            let (len_bytes, rest) = buf.split_at(nbytes);
+            buf = rest;
+            len_bytes
+                .iter()
+                .fold(0usize, |acc, &b| (acc << 8) | b as usize)
+        };
+
+        if buf.len() < len {
+            return None;
+        }
+        let (content, rest) = buf.split_at(len);
+        Some((tag, content, rest))
+    }
+}
+
+/// Iteration over ip:port targets, see [SockIter](sockiter::SockIter)
+pub mod sockiter {
+    use itertools::{iproduct, Product};
+    use rand::rngs::StdRng;
+    use rand::seq::SliceRandom;
+    use rand::{Rng, SeedableRng};
+    use std::collections::{HashMap, HashSet};
+    use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+
+    /// Iterator over the ips x ports product of a scan, yielding the
+    /// targets as [SocketAddr]s in the order [QScanner](super::QScanner)
+    /// scans them.
+    pub struct SockIter<'a> {
+        inner: Inner<'a>,
+        scopes: Option<&'a HashMap<Ipv6Addr, u32>>,
+        groups: std::vec::IntoIter<(&'a [IpAddr], &'a [u16])>,
+        skip: Option<&'a HashSet<SocketAddr>>,
+        /// Probability of each target of being yielded
+        sample: Option<(f64, StdRng)>,
+    }
+
+    enum Inner<'a> {
+        Product(
+            Product<Box<std::slice::Iter<'a, u16>>, Box<std::slice::Iter<'a, std::net::IpAddr>>>,
+        ),
+        Sockets(std::vec::IntoIter<SocketAddr>),
+        Lazy {
+            ips: Box<dyn Iterator<Item = IpAddr> + Send + 'a>,
+            ports: &'a [u16],
+            ip: Option<IpAddr>,
+            port_idx: usize,
+        },
+    }
+
+    impl<'a> SockIter<'a> {
+        /// Iterate the ips x ports product, port by port: each port of
+        /// `ports` is paired with all the `ips` before the next port.
+        ///
+        /// # Examples
+        ///
+        /// ```
+        /// use qscan::SockIter;
+        /// use std::net::{IpAddr, SocketAddr};
+        /// let ips: Vec<IpAddr> = vec!["10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap()];
+        /// let ports = [22, 80];
+        /// let socks: Vec<SocketAddr> = SockIter::new(&ips, &ports).collect();
+        /// assert_eq!(
+        ///     socks,
+        ///     ["10.0.0.1:22", "10.0.0.2:22", "10.0.0.1:80", "10.0.0.2:80"]
+        ///         .map(|s| s.parse::<SocketAddr>().unwrap())
+        /// );
+        /// ```
+        pub fn new(ips: &'a [IpAddr], ports: &'a [u16]) -> Self {
+            let ports = Box::new(ports.iter());
+            let ips = Box::new(ips.iter());
+            Self {
+                inner: Inner::Product(iproduct!(ports, ips)),
+                scopes: None,
+                groups: Vec::new().into_iter(),
+                skip: None,
+                sample: None,
+            }
+        }
+
+        /// Iterate the whole ips x ports product in random order. The same
+        /// `seed` always produces the same order.
+        pub fn new_shuffled(ips: &'a [IpAddr], ports: &'a [u16], seed: Option<u64>) -> Self {
+            let mut socks: Vec<SocketAddr> = Self::new(ips, ports).collect();
+            let mut rng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            socks.shuffle(&mut rng);
+
+            Self {
+                inner: Inner::Sockets(socks.into_iter()),
+                scopes: None,
+                groups: Vec::new().into_iter(),
+                skip: None,
+                sample: None,
+            }
+        }
+
+        /// Iterate exactly `socks`, in order
+        pub(crate) fn from_sockets(socks: Vec<SocketAddr>) -> Self {
+            Self {
+                inner: Inner::Sockets(socks.into_iter()),
+                scopes: None,
+                groups: Vec::new().into_iter(),
+                skip: None,
+                sample: None,
+            }
+        }
+
+        /// Iterate all the `ports` of each address pulled from `ips`, host by
+        /// host, without collecting the addresses (e.g., read from a file)
+        pub fn new_lazy(
+            ips: Box<dyn Iterator<Item = IpAddr> + Send + 'a>,
+            ports: &'a [u16],
+        ) -> Self {
+            Self {
+                inner: Inner::Lazy {
+                    ips,
+                    ports,
+                    ip: None,
+                    port_idx: ports.len(),
+                },
+                scopes: None,
+                groups: Vec::new().into_iter(),
+                skip: None,
+                sample: None,
+            }
+        }
+
+        /// Set the zone (scope) ids of the IPv6 addresses
+        pub fn with_scopes(mut self, scopes: &'a HashMap<Ipv6Addr, u32>) -> Self {
+            if !scopes.is_empty() {
+                self.scopes = Some(scopes);
+            }
+            self
+        }
+
+        /// Iterate, after the other targets, the ips x ports product of each
+        /// of `groups`
+        pub(crate) fn with_groups(mut self, groups: Vec<(&'a [IpAddr], &'a [u16])>) -> Self {
+            self.groups = groups.into_iter();
+            self
+        }
+
+        /// Skip the targets in `skip`
+        pub(crate) fn with_skip(mut self, skip: &'a HashSet<SocketAddr>) -> Self {
+            if !skip.is_empty() {
+                self.skip = Some(skip);
+            }
+            self
+        }
+
+        /// Yield each target with probability `p`, picked with `seed`, if
+        /// any
+        pub(crate) fn with_sample(mut self, p: f64, seed: Option<u64>) -> Self {
+            let rng = match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            };
+            self.sample = Some((p, rng));
+            self
+        }
+
+        fn next_unscoped(&mut self) -> Option<SocketAddr> {
+            loop {
+                if let Some(sock) = self.next_inner() {
+                    return Some(sock);
+                }
+                let (ips, ports) = self.groups.next()?;
+                self.inner = Self::new(ips, ports).inner;
+            }
+        }
+
+        fn next_inner(&mut self) -> Option<SocketAddr> {
+            match &mut self.inner {
+                Inner::Product(prod) => prod.next().map(|(port, ip)| SocketAddr::new(*ip, *port)),
+                Inner::Sockets(socks) => socks.next(),
+                Inner::Lazy {
+                    ips,
+                    ports,
+                    ip,
+                    port_idx,
+                } => {
+                    if ports.is_empty() {
+                        return None;
+                    }
+
+                    if *port_idx == ports.len() {
+                        *ip = Some(ips.next()?);
+                        *port_idx = 0;
+                    }
+
+                    let port = ports[*port_idx];
+                    *port_idx += 1;
+                    ip.map(|ip| SocketAddr::new(ip, port))
+                }
+            }
+        }
+    }
+
+    impl<'s> Iterator for SockIter<'s> {
+        type Item = SocketAddr;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            loop {
+                let mut sock = self.next_unscoped()?;
+
+                if let (Some(scopes), SocketAddr::V6(sock)) = (self.scopes, &mut sock) {
+                    if let Some(scope) = scopes.get(sock.ip()) {
+                        sock.set_scope_id(*scope);
+                    }
+                }
+
+                if self.skip.is_some_and(|skip| skip.contains(&sock)) {
+                    continue;
+                }
+                if let Some((p, rng)) = &mut self.sample {
+                    if !rng.gen_bool(*p) {
+                        continue;
+                    }
+                }
+                return Some(sock);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+    use std::time::Duration;
+    use trust_dns_resolver::{
+        config::{ResolverConfig, ResolverOpts},
+        Resolver,
+    };
+
+    use tokio::runtime::Runtime;
+
+    /// Logger capturing all the records, used to check diagnostics
+    struct CaptureLogger;
+
+    static CAPTURED_LOGS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+    impl log::Log for CaptureLogger {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            true
+        }
+
+        fn log(&self, record: &log::Record) {
+            CAPTURED_LOGS
+                .lock()
+                .unwrap()
+                .push(format!("{}:{}", record.level(), record.args()));
+        }
+
+        fn flush(&self) {}
+    }
+
+    fn capture_logs() {
+        static LOGGER: CaptureLogger = CaptureLogger;
+        if log::set_logger(&LOGGER).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace);
+        }
+    }
+
+    /// Scanner of `ips` and `ports` going through a SOCKS5 proxy that never
+    /// answers the greeting, which makes all the targets unresponsive. The
+    /// proxy listener must be kept alive for the duration of the test.
+    fn unresponsive_scanner(
+        ips: Vec<IpAddr>,
+        ports: Vec<u16>,
+    ) -> (super::QScanner, std::net::TcpListener) {
+        let proxy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut scanner = super::QScanner::new_from_vecs(ips, ports);
+        scanner.set_proxy(super::ProxyConfig::new(proxy.local_addr().unwrap()));
+        scanner.set_timeout_ms(200);
+        (scanner, proxy)
+    }
+
+    #[test]
+    fn parse_bad_target_logs_warning() {
+        capture_logs();
+        let res = super::addresses_parse(
+            "/qscan/not/a/file",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert!(res.is_empty());
+        assert!(CAPTURED_LOGS
+            .lock()
+            .unwrap()
+            .contains(&"WARN:not a file \"/qscan/not/a/file\"".to_string()));
+    }
+
+    #[test]
+    fn parse_empty_address() {
+        let res =
+            super::addresses_parse("", super::MAX_CIDR_HOSTS_DEF, super::MAX_TARGETS_DEF, None).ips;
+        assert_eq!(res, Vec::<IpAddr>::new());
+    }
+
+    #[test]
+    fn parse_commas_address() {
+        let res = super::addresses_parse(
+            ",,,,",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(res, Vec::<IpAddr>::new());
+    }
+
+    #[test]
+    fn parse_simple_address() {
+        let res = super::addresses_parse(
+            "127.0.0.1",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn parse_repeated_address1() {
+        let res = super::addresses_parse(
+            "127.0.0.1,127.0.0.1",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    }
+
+    #[test]
+    fn parse_repeated_address2() {
+        let res = super::addresses_parse(
+            "127.0.0.1,127.0.0.2,127.0.0.0/30",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "127.0.0.2".parse::<IpAddr>().unwrap(),
+                "127.0.0.0".parse::<IpAddr>().unwrap(),
+                "127.0.0.3".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_repeated_address3() {
+        let res = super::addresses_parse(
+            "127.0.0.1,192.168.1.1,127.0.0.0/30",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "192.168.1.1".parse::<IpAddr>().unwrap(),
+                "127.0.0.0".parse::<IpAddr>().unwrap(),
+                "127.0.0.2".parse::<IpAddr>().unwrap(),
+                "127.0.0.3".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_multiple_addresses() {
+        let res = super::addresses_parse(
+            "127.0.0.1,127.0.0.2",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "127.0.0.2".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_cidr() {
+        let res = super::addresses_parse(
+            "127.0.0.10/31",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                "127.0.0.10".parse::<IpAddr>().unwrap(),
+                "127.0.0.11".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_cidr_and_addresses() {
+        let res = super::addresses_parse(
+            "127.0.0.1,127.0.0.10/31, 127.0.0.2",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "127.0.0.10".parse::<IpAddr>().unwrap(),
+                "127.0.0.11".parse::<IpAddr>().unwrap(),
+                "127.0.0.2".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_ipv6_address() {
+        let res = super::addresses_parse(
+            "::1,2001:db8::1",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+                "2001:db8::1".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_ipv6_bracketed_address() {
+        let res = super::addresses_parse(
+            "[::1],[2001:db8::1]:80",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+                "2001:db8::1".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_ipv6_bracketed_invalid_port() {
+        assert_eq!(super::bracketed_address_parse("[::1]:http"), None);
+        assert_eq!(super::bracketed_address_parse("[::1]80"), None);
+        assert_eq!(super::bracketed_address_parse("[127.0.0.1]"), None);
+    }
+
+    #[test]
+    fn parse_ipv6_cidr() {
+        let res = super::addresses_parse(
+            "2001:db8::/126",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(
+            res,
+            vec![
+                "2001:db8::".parse::<IpAddr>().unwrap(),
+                "2001:db8::1".parse::<IpAddr>().unwrap(),
+                "2001:db8::2".parse::<IpAddr>().unwrap(),
+                "2001:db8::3".parse::<IpAddr>().unwrap(),
+            ]
+        );
+
+        let res = super::addresses_parse(
+            "2001:db8::/120",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(res.len(), 256);
+    }
+
+    #[test]
+    fn parse_cidr_over_max_hosts() {
+        let res = super::addresses_parse(
+            "2001:db8::/64,::1",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(res, vec![IpAddr::V6(Ipv6Addr::LOCALHOST)]);
+
+        let res = super::addresses_parse(
+            "::/0",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        )
+        .ips;
+        assert_eq!(res, Vec::<IpAddr>::new());
+
+        let res =
+            super::addresses_parse("127.0.0.0/29,10.0.0.0/30", 4, super::MAX_TARGETS_DEF, None).ips;
+        assert_eq!(res.len(), 4);
+    }
+
+    #[test]
+    fn parse_over_max_targets() {
+        let res = super::addresses_parse(
+            "10.0.0.0/8,127.0.0.1",
+            super::MAX_CIDR_HOSTS_DEF,
+            1024,
+            None,
+        )
+        .ips;
+        assert_eq!(res, vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+
+        let res = super::addresses_parse(
+            "10.0.0.0/30,10.0.1.0/30,10.0.2.0/31",
+            super::MAX_CIDR_HOSTS_DEF,
+            6,
+            None,
+        )
+        .ips;
+        assert_eq!(res.len(), 6);
+
+        let mut scanner = super::QScanner::new("127.0.0.1", "80").unwrap();
+        scanner.set_max_targets(2);
+        scanner.add_targets_addr("127.0.0.2,10.0.0.0/30");
+        assert_eq!(scanner.get_tagets_ips().len(), 2);
+    }
+
+    #[test]
+    fn sockiter_ipv6() {
+        let ips = vec![IpAddr::V6(Ipv6Addr::LOCALHOST)];
+        let ports = vec![80, 443];
+        let socks: Vec<SocketAddr> = super::sockiter::SockIter::new(&ips, &ports).collect();
+        assert_eq!(
+            socks,
+            vec![
+                "[::1]:80".parse::<SocketAddr>().unwrap(),
+                "[::1]:443".parse::<SocketAddr>().unwrap(),
+            ]
+        );
+        assert!(socks.iter().all(|s| s.is_ipv6()));
+    }
+
+    #[test]
+    fn write_results_csv() {
+        let results = vec![
+            super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+                target: "127.0.0.1:80".parse().unwrap(),
+                state: super::QScanTcpConnectState::Open,
+                latency: Some(Duration::from_micros(1500)),
+                hostname: None,
+                http: None,
+                tls: None,
+                probe: None,
+                attempts: 1,
+                error_kind: None,
+                skipped: None,
+                scanned_at: std::time::UNIX_EPOCH,
+            }),
+            super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+                target: "[::1]:443".parse().unwrap(),
+                state: super::QScanTcpConnectState::Filtered,
+                latency: None,
+                hostname: None,
+                http: None,
+                tls: None,
+                probe: None,
+                attempts: 1,
+                error_kind: None,
+                skipped: None,
+                scanned_at: std::time::UNIX_EPOCH,
+            }),
+            super::QScanResult::Ping(super::QScanPingResult {
+                target: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                state: super::QScanPingState::Up,
+            }),
+        ];
+
+        let mut csv = Vec::new();
+        super::write_results_csv(&mut csv, &results).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "ip,port,state,latency_ms\n\
+             127.0.0.1,80,OPEN,1.500\n\
+             ::1,443,FILTERED,\n\
+             127.0.0.1,,UP,\n"
+        );
+        assert_eq!(super::csv_field("a,\"b\""), "\"a,\"\"b\"\"\"");
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn write_results_sqlite() {
+        let results = vec![
+            super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+                target: "127.0.0.1:80".parse().unwrap(),
+                state: super::QScanTcpConnectState::Open,
+                latency: Some(Duration::from_micros(1500)),
+                hostname: None,
+                http: None,
+                tls: None,
+                probe: None,
+                attempts: 1,
+                error_kind: None,
+                skipped: None,
+                scanned_at: std::time::UNIX_EPOCH + Duration::from_millis(1234),
+            }),
+            super::QScanResult::Ping(super::QScanPingResult {
+                target: IpAddr::V6(Ipv6Addr::LOCALHOST),
+                state: super::QScanPingState::Down,
+            }),
+        ];
+
+        let mut conn = rusqlite::Connection::open_in_memory().unwrap();
+        super::sqlite_insert(&mut conn, &results).unwrap();
+        super::sqlite_insert(&mut conn, &results[..1]).unwrap();
+
+        type Row = (String, Option<u16>, String, Option<f64>, Option<i64>);
+        let mut query = conn
+            .prepare("SELECT ip, port, state, latency_ms, scanned_at FROM results ORDER BY rowid")
+            .unwrap();
+        let rows: Vec<Row> = query
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let open = (
+            "127.0.0.1".to_string(),
+            Some(80),
+            "OPEN".to_string(),
+            Some(1.5),
+            Some(1234),
+        );
+        assert_eq!(
+            rows,
+            vec![
+                open.clone(),
+                ("::1".to_string(), None, "DOWN".to_string(), None, None),
+                open,
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_results() {
+        let tcp = |target: &str, state| {
+            super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+                target: target.parse().unwrap(),
+                state,
+                latency: None,
+                hostname: None,
+                http: None,
+                tls: None,
+                probe: None,
+                attempts: 1,
+                error_kind: None,
+                skipped: None,
+                scanned_at: std::time::UNIX_EPOCH,
+            })
+        };
+        let udp = |target: &str, state| {
+            super::QScanResult::Udp(super::QScanUdpResult {
+                target: target.parse().unwrap(),
+                state,
+            })
+        };
+
+        let merged = super::merge_results(vec![
+            vec![
+                tcp("10.0.0.1:80", super::QScanTcpConnectState::Close),
+                tcp("10.0.0.1:443", super::QScanTcpConnectState::Open),
+                tcp("10.0.0.2:22", super::QScanTcpConnectState::Filtered),
+                udp("10.0.0.1:53", super::QScanUdpState::OpenFiltered),
+            ],
+            vec![
+                tcp("10.0.0.1:80", super::QScanTcpConnectState::Open),
+                tcp("10.0.0.1:443", super::QScanTcpConnectState::Filtered),
+                tcp("10.0.0.2:22", super::QScanTcpConnectState::Close),
+                tcp("10.0.0.1:53", super::QScanTcpConnectState::Close),
+                tcp("10.0.0.3:80", super::QScanTcpConnectState::Open),
+            ],
+        ]);
+
+        let states: Vec<(String, &str)> = merged
+            .iter()
+            .map(|r| {
+                let (ip, port, _) = super::result_fields(r);
+                (format!("{}:{}", ip, port.unwrap()), super::result_state(r))
+            })
+            .collect();
+        assert_eq!(
+            states,
+            vec![
+                ("10.0.0.1:80".to_string(), "OPEN"),
+                ("10.0.0.1:443".to_string(), "OPEN"),
+                ("10.0.0.2:22".to_string(), "FILTERED"),
+                ("10.0.0.1:53".to_string(), "OPEN_FILTERED"),
+                ("10.0.0.1:53".to_string(), "CLOSE"),
+                ("10.0.0.3:80".to_string(), "OPEN"),
+            ]
+        );
+    }
+
+    #[test]
+    fn filter_ubiquitous_ports() {
+        let tcp = |target: &str, state| {
+            super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+                target: target.parse().unwrap(),
+                state,
+                latency: None,
+                hostname: None,
+                http: None,
+                tls: None,
+                probe: None,
+                attempts: 1,
+                error_kind: None,
+                skipped: None,
+                scanned_at: std::time::UNIX_EPOCH,
+            })
+        };
+        let mut results = Vec::new();
+        for host in 1..=4 {
+            // Port 8080 open everywhere, 22 on a single host
+            results.push(tcp(
+                &format!("10.0.0.{}:8080", host),
+                super::QScanTcpConnectState::Open,
+            ));
+            let ssh = if host == 1 {
+                super::QScanTcpConnectState::Open
+            } else {
+                super::QScanTcpConnectState::Close
+            };
+            results.push(tcp(&format!("10.0.0.{}:22", host), ssh));
+        }
+        results.push(super::QScanResult::Ping(super::QScanPingResult {
+            target: "10.0.0.1".parse().unwrap(),
+            state: super::QScanPingState::Up,
+        }));
+
+        let ports = |results: &[super::QScanResult]| {
+            results
+                .iter()
+                .filter_map(|r| super::result_fields(r).1)
+                .collect::<std::collections::BTreeSet<u16>>()
+                .into_iter()
+                .collect::<Vec<u16>>()
+        };
+
+        let filtered = super::filter_ubiquitous_ports(results, 0.9);
+        assert_eq!(filtered.len(), 5);
+        assert_eq!(ports(&filtered), vec![22]);
+        assert!(matches!(filtered[4], super::QScanResult::Ping(_)));
+
+        // Open on exactly the threshold fraction of the hosts: kept
+        let results = vec![
+            tcp("10.0.0.1:80", super::QScanTcpConnectState::Open),
+            tcp("10.0.0.2:80", super::QScanTcpConnectState::Close),
+        ];
+        assert_eq!(super::filter_ubiquitous_ports(results, 0.5).len(), 2);
+    }
+
+    #[test]
+    fn format_results_ranges() {
+        let tcp = |target: &str, state| {
+            super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+                target: target.parse().unwrap(),
+                state,
+                latency: None,
+                hostname: None,
+                http: None,
+                tls: None,
+                probe: None,
+                attempts: 1,
+                error_kind: None,
+                skipped: None,
+                scanned_at: std::time::UNIX_EPOCH,
+            })
+        };
+        let results = vec![
+            tcp("192.168.1.1:8002", super::QScanTcpConnectState::Open),
+            tcp("192.168.1.1:80", super::QScanTcpConnectState::Open),
+            tcp("10.0.0.1:22", super::QScanTcpConnectState::Open),
+            tcp("192.168.1.1:8000", super::QScanTcpConnectState::Open),
+            tcp("192.168.1.1:443", super::QScanTcpConnectState::Open),
+            tcp("192.168.1.1:8001", super::QScanTcpConnectState::Open),
+            tcp("192.168.1.1:8003", super::QScanTcpConnectState::Close),
+            tcp("192.168.1.1:8001", super::QScanTcpConnectState::Open),
+            tcp("10.0.0.2:22", super::QScanTcpConnectState::Filtered),
+            super::QScanResult::Udp(super::QScanUdpResult {
+                target: "10.0.0.1:23".parse().unwrap(),
+                state: super::QScanUdpState::Open,
+            }),
+        ];
+
+        assert_eq!(
+            super::format_results_ranges(&results),
+            "10.0.0.1: 22-23\n192.168.1.1: 80, 443, 8000-8002"
+        );
+        assert_eq!(super::format_results_ranges(&results[8..9]), "");
+    }
+
+    #[test]
+    fn diff_results() {
+        let tcp = |target: &str, state| {
+            super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+                target: target.parse().unwrap(),
+                state,
+                latency: None,
+                hostname: None,
+                http: None,
+                tls: None,
+                probe: None,
+                attempts: 1,
+                error_kind: None,
+                skipped: None,
+                scanned_at: std::time::UNIX_EPOCH,
+            })
+        };
+        let old = vec![
+            tcp("10.0.0.1:22", super::QScanTcpConnectState::Open),
+            tcp("10.0.0.1:80", super::QScanTcpConnectState::Open),
+            tcp("10.0.0.1:443", super::QScanTcpConnectState::Close),
+            tcp("10.0.0.2:22", super::QScanTcpConnectState::Open),
+        ];
+        let new = vec![
+            tcp("10.0.0.1:22", super::QScanTcpConnectState::Open),
+            tcp("10.0.0.1:80", super::QScanTcpConnectState::Close),
+            tcp("10.0.0.1:443", super::QScanTcpConnectState::Open),
+            tcp("10.0.0.3:3306", super::QScanTcpConnectState::Open),
+        ];
+
+        let diff = super::diff_results(&old, &new);
+        let socks =
+            |s: &[&str]| -> Vec<SocketAddr> { s.iter().map(|s| s.parse().unwrap()).collect() };
+        assert_eq!(diff.opened, socks(&["10.0.0.1:443", "10.0.0.3:3306"]));
+        assert_eq!(diff.closed, socks(&["10.0.0.1:80", "10.0.0.2:22"]));
+
+        assert_eq!(super::diff_results(&new, &new), super::ScanDiff::default());
+    }
+
+    #[test]
+    fn parse_whitespace_separated_targets() {
+        let ips = |addr: &str| {
+            super::addresses_parse(
+                addr,
+                super::MAX_CIDR_HOSTS_DEF,
+                super::MAX_TARGETS_DEF,
+                None,
+            )
             .ips
-            .clone()
-            .into_iter()
-            .unique()
-            .collect::<Vec<IpAddr>>();
-        self.ports.extend(ports_parse(ports));
-        self.ports = self
-            .ports
-            .clone()
+        };
+        let v4 = |d| IpAddr::V4(Ipv4Addr::new(127, 0, 0, d));
+
+        assert_eq!(
+            ips("127.0.0.1 127.0.0.2  127.0.0.3"),
+            vec![v4(1), v4(2), v4(3)]
+        );
+        assert_eq!(
+            ips("127.0.0.1\n127.0.0.2\r\n\t127.0.0.3,\n127.0.0.4\n"),
+            vec![v4(1), v4(2), v4(3), v4(4)]
+        );
+        assert_eq!(ips("127.0.0.0 / 31 127.0.0.5"), vec![v4(0), v4(1), v4(5)]);
+        assert_eq!(
+            ips("127.0.0.1 - 2 [ ::1 ]"),
+            vec![v4(1), v4(2), "::1".parse().unwrap()]
+        );
+
+        assert_eq!(
+            super::targets_split("fe80::1 %eth0 [::1] :80, 10.0.0.1"),
+            vec!["fe80::1%eth0", "[::1]:80", "10.0.0.1"]
+        );
+    }
+
+    #[test]
+    fn parse_ipv4_range() {
+        let ips = |addr: &str| {
+            super::addresses_parse(
+                addr,
+                super::MAX_CIDR_HOSTS_DEF,
+                super::MAX_TARGETS_DEF,
+                None,
+            )
+            .ips
+        };
+
+        let full = ips("192.168.1.1-192.168.1.50");
+        assert_eq!(full.len(), 50);
+        assert_eq!(full[0], IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)));
+        assert_eq!(full[49], IpAddr::V4(Ipv4Addr::new(192, 168, 1, 50)));
+
+        assert_eq!(ips("192.168.1.1-50"), full);
+        assert_eq!(
+            ips("10.0.0.7-7"),
+            vec![IpAddr::V4(Ipv4Addr::new(10, 0, 0, 7))]
+        );
+    }
+
+    #[test]
+    fn parse_ipv4_range_invalid() {
+        assert_eq!(
+            super::ipv4_range_parse("192.168.1.50-192.168.1.1"),
+            Some(Err("the start is greater than the end"))
+        );
+        assert_eq!(
+            super::ipv4_range_parse("192.168.1.50-10"),
+            Some(Err("the start is greater than the end"))
+        );
+        assert_eq!(
+            super::ipv4_range_parse("192.168.1.1-192.168.2.1"),
+            Some(Err("the ends are in different /24 networks"))
+        );
+        assert_eq!(
+            super::ipv4_range_parse("192.168.1.1-300"),
+            Some(Err("invalid end address"))
+        );
+        assert_eq!(super::ipv4_range_parse("my-host.example.com"), None);
+
+        let parsed = super::addresses_parse(
+            "192.168.1.50-1,127.0.0.1",
+            super::MAX_CIDR_HOSTS_DEF,
+            super::MAX_TARGETS_DEF,
+            None,
+        );
+        assert_eq!(parsed.ips, vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        assert_eq!(parsed.unresolved, vec!["192.168.1.50-1".to_string()]);
+    }
+
+    #[test]
+    fn parse_ipv6_zone_id() {
+        let mut scopes = std::collections::HashMap::new();
+        let ips = super::scoped_address_parse(
+            "fe80::1%1",
+            None,
+            super::ResolvePolicy::All,
+            16,
+            &[],
+            &mut scopes,
+        );
+        let ip: Ipv6Addr = "fe80::1".parse().unwrap();
+        assert_eq!(ips, vec![IpAddr::V6(ip)]);
+        assert_eq!(scopes.get(&ip), Some(&1));
+
+        let ips = super::scoped_address_parse(
+            "fe80::2%qscan-no-such-if",
+            None,
+            super::ResolvePolicy::All,
+            16,
+            &[],
+            &mut scopes,
+        );
+        assert!(ips.is_empty());
+
+        let scanner = super::QScanner::new("fe80::1%1,fe80::2/127%2,::1", "80").unwrap();
+        let targets = scanner.enumerate_targets();
+        assert_eq!(targets.len(), 4);
+        assert!(targets.contains(&SocketAddr::V6(std::net::SocketAddrV6::new(ip, 80, 0, 1))));
+        assert!(targets.contains(&"[fe80::3%2]:80".parse().unwrap()));
+        assert!(targets.contains(&"[::1]:80".parse().unwrap()));
+    }
+
+    #[test]
+    fn dedup_targets() {
+        let mut scanner =
+            super::QScanner::new("192.168.1.5,192.168.1.0/29,192.168.1.5", "80,22-23,80,23")
+                .unwrap();
+        assert_eq!(scanner.get_tagets_ips().len(), 8);
+        assert_eq!(
+            scanner.get_tagets_ips()[0],
+            IpAddr::V4(Ipv4Addr::new(192, 168, 1, 5))
+        );
+        assert_eq!(scanner.get_tagets_ports(), &vec![80, 22, 23]);
+        assert_eq!(scanner.enumerate_targets().len(), 8 * 3);
+
+        scanner.add_targets_addr("192.168.1.2");
+        assert_eq!(scanner.get_tagets_ips().len(), 8);
+
+        scanner.set_dedup(false);
+        scanner.set_targets_addr("192.168.1.0/30,192.168.1.1");
+        assert_eq!(scanner.get_tagets_ips().len(), 5);
+    }
+
+    #[test]
+    fn unresolved_targets() {
+        let mut scanner = super::QScanner::new("localhost,qscan.invalid", "80").unwrap();
+        assert!(!scanner.get_tagets_ips().is_empty());
+        assert_eq!(scanner.unresolved_targets(), ["qscan.invalid"]);
+
+        let path = std::env::temp_dir().join(format!("qscan_unresolved_{}", std::process::id()));
+        std::fs::write(&path, "127.0.0.1\nfile.invalid\n\n10.0.0.0/30\n").unwrap();
+        scanner.add_targets_addr(&format!("{},other.invalid", path.to_str().unwrap()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            scanner.unresolved_targets(),
+            ["qscan.invalid", "file.invalid", "other.invalid"]
+        );
+
+        scanner.set_targets_addr("127.0.0.1");
+        assert!(scanner.unresolved_targets().is_empty());
+    }
+
+    #[test]
+    fn iteration_order() {
+        let mut scanner = super::QScanner::new("127.0.0.1,127.0.0.2", "22,80").unwrap();
+        let targets: Vec<SocketAddr> = [
+            "127.0.0.1:22",
+            "127.0.0.2:22",
+            "127.0.0.1:80",
+            "127.0.0.2:80",
+        ]
+        .iter()
+        .map(|t| t.parse().unwrap())
+        .collect();
+        assert_eq!(scanner.enumerate_targets(), targets);
+
+        scanner.set_iteration_order(super::IterOrder::HostMajor);
+        let targets: Vec<SocketAddr> = [
+            "127.0.0.1:22",
+            "127.0.0.1:80",
+            "127.0.0.2:22",
+            "127.0.0.2:80",
+        ]
+        .iter()
+        .map(|t| t.parse().unwrap())
+        .collect();
+        assert_eq!(scanner.enumerate_targets(), targets);
+    }
+
+    #[test]
+    fn sample_targets() {
+        let mut scanner = super::QScanner::new("10.0.0.0/24", "80").unwrap();
+        scanner.set_sample(super::Sample::Fraction(0.1));
+        scanner.set_sample_seed(42);
+        let picked = scanner.enumerate_targets();
+        assert!((15..=40).contains(&picked.len()), "{}", picked.len());
+        // Reproducible with the same seed
+        assert_eq!(scanner.enumerate_targets(), picked);
+
+        scanner.set_sample(super::Sample::Count(25));
+        let count = scanner.enumerate_targets().len();
+        assert!((15..=40).contains(&count), "{}", count);
+
+        scanner.set_sample(super::Sample::Fraction(1.0));
+        assert_eq!(scanner.enumerate_targets().len(), 256);
+        scanner.set_sample(super::Sample::Fraction(0.0));
+        assert!(scanner.enumerate_targets().is_empty());
+    }
+
+    #[test]
+    fn sockiter_shuffle_seed() {
+        let mut scanner = super::QScanner::new("127.0.0.0/29", "1-10").unwrap();
+        let ordered: Vec<SocketAddr> = scanner.sock_iter().collect();
+
+        scanner.set_shuffle(true);
+        scanner.set_shuffle_seed(42);
+        let shuffled1: Vec<SocketAddr> = scanner.sock_iter().collect();
+        let shuffled2: Vec<SocketAddr> = scanner.sock_iter().collect();
+
+        assert_eq!(shuffled1, shuffled2);
+        assert_ne!(shuffled1, ordered);
+
+        let mut sorted = shuffled1.clone();
+        sorted.sort();
+        let mut expected = ordered.clone();
+        expected.sort();
+        assert_eq!(sorted, expected);
+
+        scanner.set_shuffle_seed(43);
+        assert_ne!(scanner.sock_iter().collect::<Vec<SocketAddr>>(), shuffled1);
+    }
+
+    #[test]
+    fn parse_empty_port() {
+        let res = super::ports_parse("").unwrap();
+        assert_eq!(res, Vec::<u16>::new());
+    }
+
+    #[test]
+    fn parse_commas_port() {
+        let res = super::ports_parse(",,,").unwrap();
+        assert_eq!(res, Vec::<u16>::new());
+    }
+
+    #[test]
+    fn parse_single_port() {
+        let res = super::ports_parse("80").unwrap();
+        assert_eq!(res, vec![80]);
+    }
+
+    #[test]
+    fn parse_repeated_port1() {
+        let res = super::ports_parse("80,80").unwrap();
+        assert_eq!(res, vec![80]);
+    }
+
+    #[test]
+    fn parse_repeated_port2() {
+        let res = super::ports_parse("80,79-81").unwrap();
+        assert_eq!(res, vec![80, 79, 81]);
+    }
+
+    #[test]
+    fn parse_repeated_port3() {
+        let res = super::ports_parse("80,128,79-81").unwrap();
+        assert_eq!(res, vec![80, 128, 79, 81]);
+    }
+
+    #[test]
+    fn parse_multiple_ports() {
+        let res = super::ports_parse("80, 443,8080").unwrap();
+        assert_eq!(res, vec![80, 443, 8080]);
+    }
+
+    #[test]
+    fn parse_ports_range() {
+        let res = super::ports_parse("80-83").unwrap();
+        assert_eq!(res, vec![80, 81, 82, 83]);
+    }
+
+    #[test]
+    fn parse_ports_mixed() {
+        let res = super::ports_parse("21,80-83,443,8080-8081").unwrap();
+        assert_eq!(res, vec![21, 80, 81, 82, 83, 443, 8080, 8081]);
+    }
+
+    #[test]
+    fn parse_ports_empty_range_bound() {
+        assert_eq!(
+            super::ports_parse("80-"),
+            Err(super::PortParseError::EmptyToken("80-".to_string()))
+        );
+        assert_eq!(
+            super::ports_parse("22,-80"),
+            Err(super::PortParseError::EmptyToken("-80".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_ports_invalid_number() {
+        assert_eq!(
+            super::ports_parse("80,8o"),
+            Err(super::PortParseError::InvalidNumber("8o".to_string()))
+        );
+        assert_eq!(
+            super::ports_parse("1-70000"),
+            Err(super::PortParseError::InvalidNumber("70000".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_ports_service_names() {
+        assert_eq!(
+            super::ports_parse("http,https,ssh").unwrap(),
+            vec![80, 443, 22]
+        );
+        assert_eq!(
+            super::ports_parse("SSH,8000-8002,http-alt,53").unwrap(),
+            vec![22, 8000, 8001, 8002, 8080, 53]
+        );
+        assert_eq!(super::ports_parse("ssh-telnet").unwrap(), vec![22, 23]);
+        assert_eq!(super::ports_parse("http,80").unwrap(), vec![80]);
+        assert_eq!(
+            super::ports_parse("80,foo"),
+            Err(super::PortParseError::UnknownService("foo".to_string()))
+        );
+    }
+
+    #[test]
+    fn set_batch_zero() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], vec![port]);
+        scanner.set_batch(0);
+        assert_eq!(scanner.batch, 1);
+
+        let rt = Runtime::new().unwrap();
+        let res = rt.block_on(async {
+            tokio::time::timeout(Duration::from_secs(5), scanner.scan_tcp_connect())
+                .await
+                .map(|res| res.len())
+        });
+        assert_eq!(res, Ok(1));
+    }
+
+    #[test]
+    fn clamp_batch_to_fd_limit() {
+        assert_eq!(super::clamp_batch(5000, Some(1024)), 768);
+        assert_eq!(super::clamp_batch(500, Some(1024)), 500);
+        assert_eq!(super::clamp_batch(500, Some(1)), 1);
+        assert_eq!(super::clamp_batch(5000, None), 5000);
+
+        let mut scanner = super::QScanner::new("127.0.0.1", "80").unwrap();
+        scanner.set_batch(u16::MAX);
+        if let Some(limit) = super::fd_limit() {
+            assert!(scanner.scan_batch() as u64 <= limit);
+        }
+    }
+
+    #[test]
+    fn builder_build() {
+        let scanner = super::QScannerBuilder::new()
+            .targets("127.0.0.1,127.0.0.2")
+            .ports("22,80-81")
+            .batch(10)
+            .timeout_ms(200)
+            .tries(3)
+            .scan_type(super::QScanType::Udp)
+            .print_mode(super::QSPrintMode::RealTime)
+            .build()
+            .unwrap();
+
+        assert_eq!(scanner.get_tagets_ips().len(), 2);
+        assert_eq!(scanner.get_tagets_ports(), &vec![22, 80, 81]);
+        assert_eq!(scanner.batch, 10);
+        assert_eq!(scanner.to, Duration::from_millis(200));
+        assert_eq!(scanner.tries.get(), 3);
+        assert!(matches!(scanner.scan_type, super::QScanType::Udp));
+        assert!(matches!(scanner.print_mode, super::QSPrintMode::RealTime));
+    }
+
+    #[test]
+    fn builder_validation() {
+        let build = |b: super::QScannerBuilder| b.targets("127.0.0.1").ports("80").build();
+
+        assert!(matches!(
+            build(super::QScannerBuilder::new().batch(0)),
+            Err(super::BuildError::ZeroBatch)
+        ));
+        assert!(matches!(
+            build(super::QScannerBuilder::new().timeout_ms(0)),
+            Err(super::BuildError::ZeroTimeout)
+        ));
+        assert!(matches!(
+            build(super::QScannerBuilder::new().tries(0)),
+            Err(super::BuildError::ZeroTries)
+        ));
+        assert!(matches!(
+            super::QScannerBuilder::new().ports("80-").build(),
+            Err(super::BuildError::InvalidPorts(super::PortParseError::EmptyToken(t))) if t == "80-"
+        ));
+
+        let e: super::BuildError =
+            super::QScanError::InvalidTargets(vec!["foo".to_string()]).into();
+        assert!(matches!(
+            &e,
+            super::BuildError::Scanner(super::QScanError::InvalidTargets(t)) if t == &["foo"]
+        ));
+        assert_eq!(e.to_string(), "invalid targets: foo");
+    }
+
+    #[test]
+    fn enumerate_targets() {
+        let scanner = super::QScanner::new("127.0.0.0/30,::1", "22,80-81").unwrap();
+        let targets = scanner.enumerate_targets();
+
+        assert_eq!(targets.len(), 5 * 3);
+        assert_eq!(scanner.targets_count(), 5 * 3);
+        assert!(targets.contains(&"[::1]:81".parse().unwrap()));
+        assert!(targets.contains(&"127.0.0.3:22".parse().unwrap()));
+    }
+
+    #[test]
+    fn scan_tcp_connect_open_only() {
+        let listeners: Vec<_> = (0..2)
+            .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let mut ports: Vec<u16> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+        ports.push(closed);
+
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], ports);
+        let rt = Runtime::new().unwrap();
+
+        let mut open = rt.block_on(scanner.scan_tcp_connect_open_only());
+        assert!(scanner.get_last_results().is_none());
+
+        let mut expected: Vec<SocketAddr> = rt
+            .block_on(scanner.scan_tcp_connect())
+            .iter()
+            .filter_map(|r| match r {
+                super::QScanResult::TcpConnect(r)
+                    if r.state == super::QScanTcpConnectState::Open =>
+                {
+                    Some(r.target)
+                }
+                _ => None,
+            })
+            .collect();
+
+        open.sort();
+        expected.sort();
+        assert_eq!(open.len(), 2);
+        assert_eq!(open, expected);
+    }
+
+    #[test]
+    fn scan_tcp_connect_deadline() {
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            (1..=u16::MAX).collect(),
+        );
+        scanner.set_batch(8);
+        scanner.set_timeout_ms(100);
+        scanner.set_deadline(Duration::from_millis(20));
+
+        let start = std::time::Instant::now();
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(!res.is_empty());
+        assert!((res.len() as u64) < scanner.targets_count());
+    }
+
+    #[test]
+    fn scan_tcp_connect_refused_not_retried() {
+        let refused = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        // Each try waits for its slot: 500ms between tries
+        let mut scanner = super::QScanner::new_from_vecs(vec![refused.ip()], vec![refused.port()]);
+        scanner.set_ntries(3);
+        scanner.set_max_rate(2);
+        let rt = Runtime::new().unwrap();
+
+        let start = std::time::Instant::now();
+        let res = rt.block_on(scanner.check_socket(refused));
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        scanner.set_retry_on_refused(true);
+        let start = std::time::Instant::now();
+        let res = rt.block_on(scanner.check_socket(refused));
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+        assert!(start.elapsed() >= Duration::from_millis(900));
+    }
+
+    #[test]
+    fn scan_tcp_connect_with_stats() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let ips: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+        let ports = vec![open, closed];
+        let mut scanner = super::QScanner::new_from_vecs(ips.clone(), ports.clone());
+        let (res, stats) = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect_with_stats());
+
+        assert_eq!(res.len() as u64, stats.attempted);
+        assert_eq!(stats.attempted, (ips.len() * ports.len()) as u64);
+        assert_eq!(
+            stats.open + stats.closed + stats.filtered + stats.errored,
+            stats.attempted
+        );
+        assert!(stats.open >= 1);
+        assert!(stats.closed >= 1);
+    }
+
+    #[test]
+    fn scan_tcp_connect_retry_jitter() {
+        use rand::{Rng, SeedableRng};
+
+        let refused = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let mut scanner = super::QScanner::new_from_vecs(vec![refused.ip()], vec![refused.port()]);
+        scanner.set_ntries(3);
+        scanner.set_retry_on_refused(true);
+        let rt = Runtime::new().unwrap();
+
+        let start = std::time::Instant::now();
+        rt.block_on(scanner.check_socket(refused));
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        let max = Duration::from_millis(300);
+        scanner.set_retry_jitter(max);
+        scanner.set_retry_jitter_seed(7);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let expected: Duration = (0..2).map(|_| rng.gen_range(Duration::ZERO..max)).sum();
+
+        let start = std::time::Instant::now();
+        rt.block_on(scanner.check_socket(refused));
+        assert!(start.elapsed() >= expected);
+        assert!(expected > Duration::from_millis(100));
+    }
+
+    #[test]
+    fn scan_tcp_connect_sorted() {
+        let ips: Vec<IpAddr> = vec!["127.0.0.2".parse().unwrap(), "127.0.0.1".parse().unwrap()];
+        let mut scanner = super::QScanner::new_from_vecs(ips, vec![9003, 9001, 9002, 9000]);
+        scanner.set_shuffle(true);
+        scanner.set_sort_results(true);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        let targets: Vec<SocketAddr> = res
+            .iter()
+            .map(|r| match r {
+                super::QScanResult::TcpConnect(r) => r.target,
+                _ => panic!("unexpected result {:?}", r),
+            })
+            .collect();
+        let mut sorted = targets.clone();
+        sorted.sort_by_key(|t| (t.ip(), t.port()));
+
+        assert_eq!(targets.len(), 8);
+        assert_eq!(targets, sorted);
+    }
+
+    #[test]
+    fn scan_tcp_connect_parallel_tries() {
+        let target: SocketAddr = "127.0.0.1:80".parse().unwrap();
+
+        let (mut scanner, _proxy) = unresponsive_scanner(vec![target.ip()], vec![target.port()]);
+        scanner.set_ntries(3);
+        let rt = Runtime::new().unwrap();
+
+        let start = std::time::Instant::now();
+        let res = rt.block_on(scanner.check_socket(target));
+        let sequential = start.elapsed();
+        assert_eq!(res.state, super::QScanTcpConnectState::Filtered);
+
+        scanner.set_parallel_tries(true);
+        let start = std::time::Instant::now();
+        let res = rt.block_on(scanner.check_socket(target));
+        let parallel = start.elapsed();
+        assert_eq!(res.state, super::QScanTcpConnectState::Filtered);
+
+        assert!(sequential >= Duration::from_millis(600));
+        assert!(parallel < Duration::from_millis(400));
+    }
+
+    #[test]
+    fn scan_tcp_connect_error_kind() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let refused = closed.local_addr().unwrap();
+        drop(closed);
+
+        let scanner = super::QScanner::new("", "").unwrap();
+        let rt = Runtime::new().unwrap();
+        let res = rt.block_on(scanner.check_socket(refused));
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+        assert_eq!(res.error_kind, Some(std::io::ErrorKind::ConnectionRefused));
+
+        let res = rt.block_on(scanner.check_socket(open));
+        assert_eq!(res.state, super::QScanTcpConnectState::Open);
+        assert_eq!(res.error_kind, None);
+
+        let (scanner, _proxy) = unresponsive_scanner(Vec::new(), Vec::new());
+        let res = rt.block_on(scanner.check_socket(open));
+        assert_eq!(res.state, super::QScanTcpConnectState::Filtered);
+        assert_eq!(res.error_kind, Some(std::io::ErrorKind::TimedOut));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn error_kind_serde() {
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let refused = closed.local_addr().unwrap();
+        drop(closed);
+        scanner.set_vec_targets(vec![refused.ip()], vec![refused.port()]);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        let json = serde_json::to_string(&res[0]).unwrap();
+        assert!(json.contains(r#""error_kind":"ConnectionRefused""#));
+        let back: super::QScanResult = serde_json::from_str(&json).unwrap();
+        assert!(
+            matches!(back, super::QScanResult::TcpConnect(r) if r.error_kind == Some(std::io::ErrorKind::ConnectionRefused))
+        );
+        let unknown = json.replace("ConnectionRefused", "NewKind");
+        let back: super::QScanResult = serde_json::from_str(&unknown).unwrap();
+        assert!(
+            matches!(back, super::QScanResult::TcpConnect(r) if r.error_kind == Some(std::io::ErrorKind::Other))
+        );
+    }
+
+    #[test]
+    fn scan_tcp_connect_attempts() {
+        use std::io::{Read, Write};
+
+        // Flaky SOCKS5 proxy: the first connection is never answered, the
+        // following ones succeed
+        let proxy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let mut conns = Vec::new();
+            for (n, stream) in proxy.incoming().enumerate() {
+                let mut stream = stream.unwrap();
+                if n > 0 {
+                    let mut buf = [0u8; 10];
+                    stream.read_exact(&mut buf[..3]).unwrap();
+                    stream.write_all(&[0x05, 0x00]).unwrap();
+                    stream.read_exact(&mut buf).unwrap();
+                    stream
+                        .write_all(&[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                        .unwrap();
+                }
+                conns.push(stream);
+            }
+        });
+
+        let target: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        let mut scanner = super::QScanner::new_from_vecs(vec![target.ip()], vec![target.port()]);
+        scanner.set_proxy(super::ProxyConfig::new(proxy_addr));
+        scanner.set_ntries(3);
+        scanner.set_timeout_ms(300);
+        let rt = Runtime::new().unwrap();
+
+        let res = rt.block_on(scanner.check_socket(target));
+        assert_eq!(res.state, super::QScanTcpConnectState::Open);
+        assert_eq!(res.attempts, 2);
+
+        // A refused connection is conclusive at the first try
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_addr = closed.local_addr().unwrap();
+        drop(closed);
+        let scanner = super::QScanner::new_from_vecs(vec![closed_addr.ip()], vec![]);
+        let res = rt.block_on(scanner.check_socket(closed_addr));
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+        assert_eq!(res.attempts, 1);
+    }
+
+    #[test]
+    fn scan_tcp_connect_port_timeouts() {
+        let fast: SocketAddr = "127.0.0.1:22".parse().unwrap();
+
+        let (mut scanner, _proxy) = unresponsive_scanner(vec![fast.ip()], vec![22, 80]);
+        scanner.set_timeout_ms(5000);
+        scanner.set_port_timeouts(std::collections::HashMap::from([(
+            22,
+            Duration::from_millis(200),
+        )]));
+
+        assert_eq!(scanner.port_timeout(22), Duration::from_millis(200));
+        assert_eq!(scanner.port_timeout(80), Duration::from_millis(5000));
+
+        let start = std::time::Instant::now();
+        let res = Runtime::new().unwrap().block_on(scanner.check_socket(fast));
+        assert_eq!(res.state, super::QScanTcpConnectState::Filtered);
+        assert!(start.elapsed() < Duration::from_millis(1500));
+    }
+
+    #[test]
+    fn error_invalid_ports() {
+        match super::QScanner::new("127.0.0.1", "1-2-3") {
+            Err(super::QScanError::InvalidPorts(e)) => {
+                assert_eq!(e, super::PortParseError::InvalidRange("1-2-3".to_string()))
+            }
+            res => panic!("unexpected result {:?}", res.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn error_io() {
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        match scanner.set_targets_file(std::path::Path::new("/qscan/not/a/file")) {
+            Err(e @ super::QScanError::Io(_)) => {
+                let source = std::error::Error::source(&e).unwrap();
+                let source = source.downcast_ref::<std::io::Error>().unwrap();
+                assert_eq!(source.kind(), std::io::ErrorKind::NotFound);
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn error_resolver() {
+        let e: super::QScanError =
+            super::ResolveError::from(std::io::Error::from(std::io::ErrorKind::Other)).into();
+        assert!(matches!(e, super::QScanError::Resolver(_)));
+
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        assert!(scanner
+            .set_resolver_config(ResolverConfig::new(), ResolverOpts::default())
+            .is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn error_too_many_open_files() {
+        let e: super::QScanError = std::io::Error::from_raw_os_error(libc::EMFILE).into();
+        assert!(matches!(e, super::QScanError::TooManyOpenFiles(_)));
+
+        let target: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        let e = super::QScanError::connect(target, std::io::Error::from_raw_os_error(libc::EMFILE));
+        assert!(matches!(e, super::QScanError::TooManyOpenFiles(_)));
+    }
+
+    #[test]
+    fn error_connect() {
+        let refused = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let scanner = super::QScanner::new_from_vecs(vec![refused.ip()], vec![refused.port()]);
+        let res = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_socket_tcp_connect(refused, None));
+        match res.map(|_| ()).unwrap_err().error {
+            super::QScanError::Connect { target, source } => {
+                assert_eq!(target, refused);
+                assert_eq!(source.kind(), std::io::ErrorKind::ConnectionRefused);
+            }
+            e => panic!("unexpected error {}", e),
+        }
+    }
+
+    #[test]
+    fn verbose_line() {
+        let open = super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+            target: "127.0.0.1:80".parse().unwrap(),
+            state: super::QScanTcpConnectState::Open,
+            latency: Some(Duration::from_micros(152)),
+            hostname: None,
+            http: None,
+            tls: None,
+            probe: None,
+            attempts: 1,
+            error_kind: None,
+            skipped: None,
+            scanned_at: std::time::UNIX_EPOCH,
+        });
+        let closed = super::QScanResult::Udp(super::QScanUdpResult {
+            target: "[::1]:53".parse().unwrap(),
+            state: super::QScanUdpState::Close,
+        });
+        let up = super::QScanResult::Ping(super::QScanPingResult {
+            target: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            state: super::QScanPingState::Up,
+        });
+
+        assert_eq!(super::verbose_line(&open), "127.0.0.1:80:OPEN:0.152ms");
+        assert_eq!(super::verbose_line(&closed), "::1:53:CLOSE");
+        assert_eq!(super::verbose_line(&up), "127.0.0.1:UP");
+    }
+
+    #[test]
+    fn shared_scanner_across_tasks() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<super::QScanner>();
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let scanner = std::sync::Arc::new(super::QScanner::new_from_vecs(
+            vec![open.ip()],
+            vec![open.port(), closed.port()],
+        ));
+        let rt = Runtime::new().unwrap();
+
+        let tasks: Vec<_> = [open, closed]
             .into_iter()
-            .unique()
-            .collect::<Vec<u16>>();
+            .map(|target| {
+                let scanner = scanner.clone();
+                rt.spawn(async move { scanner.check_socket(target).await.state })
+            })
+            .collect();
+        let full_scan = {
+            let scanner = scanner.clone();
+            rt.spawn(async move { scanner.scan_tcp_connect_open_only().await })
+        };
+
+        let states: Vec<_> = tasks.into_iter().map(|t| rt.block_on(t).unwrap()).collect();
+        assert_eq!(
+            states,
+            vec![
+                super::QScanTcpConnectState::Open,
+                super::QScanTcpConnectState::Close
+            ]
+        );
+        assert_eq!(rt.block_on(full_scan).unwrap(), vec![open]);
+    }
+
+    #[test]
+    fn scan_tcp_connect_ramp_up() {
+        use std::sync::{Arc, Mutex};
+
+        // The connections to a SOCKS5 proxy never answering stay pending
+        // until the timeout
+        let proxy = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let accepted = Arc::new(Mutex::new(Vec::new()));
+        {
+            let accepted = accepted.clone();
+            std::thread::spawn(move || {
+                for stream in proxy.incoming() {
+                    accepted
+                        .lock()
+                        .unwrap()
+                        .push((std::time::Instant::now(), stream));
+                }
+            });
+        }
+
+        let count_first_second = |scanner: &mut super::QScanner| {
+            accepted.lock().unwrap().clear();
+            let start = std::time::Instant::now();
+            Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+            accepted
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|(t, _)| t.duration_since(start) < Duration::from_secs(1))
+                .count()
+        };
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            (1..=100).collect(),
+        );
+        scanner.set_proxy(super::ProxyConfig::new(proxy_addr));
+        scanner.set_batch(100);
+        scanner.set_timeout_ms(1500);
+        assert_eq!(count_first_second(&mut scanner), 100);
+
+        scanner.set_ramp_up(Duration::from_secs(2));
+        let n = count_first_second(&mut scanner);
+        assert!(n > 0);
+        assert!(n < 100);
+    }
+
+    #[test]
+    fn scan_tcp_connect_by_host() {
+        let listeners: Vec<_> = (0..2)
+            .map(|_| std::net::TcpListener::bind("0.0.0.0:0").unwrap())
+            .collect();
+        let open: Vec<u16> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+        let closed = std::net::TcpListener::bind("0.0.0.0:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let ips: Vec<IpAddr> = vec!["127.0.0.1".parse().unwrap(), "127.0.0.2".parse().unwrap()];
+        let ports = vec![open[1], closed, open[0]];
+        let mut scanner = super::QScanner::new_from_vecs(ips.clone(), ports);
+        scanner.set_shuffle(true);
+        let hosts = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect_by_host());
+
+        assert_eq!(hosts.len(), 2);
+        for ip in ips {
+            let host = &hosts[&ip];
+            assert_eq!(host.open_ports, vec![open[1], open[0]]);
+            assert_eq!(host.closed, 1);
+            assert_eq!(host.filtered, 0);
+            assert_eq!(host.hostname, None);
+        }
+    }
+
+    #[test]
+    fn scan_udp_probes() {
+        use std::collections::HashMap;
+
+        let rt = Runtime::new().unwrap();
+        let dns = rt
+            .block_on(tokio::net::UdpSocket::bind("127.0.0.1:0"))
+            .unwrap();
+        let dns_port = dns.local_addr().unwrap().port();
+        let echo = rt
+            .block_on(tokio::net::UdpSocket::bind("127.0.0.1:0"))
+            .unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+
+        // Minimal DNS responder: the query echoed back with the QR bit set
+        rt.spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = dns.recv_from(&mut buf).await {
+                buf[2] |= 0x80;
+                let _ = dns.send_to(&buf[..n], peer).await;
+            }
+        });
+        // Not a DNS server: the query is echoed back unchanged
+        rt.spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..n], peer).await;
+            }
+        });
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![dns_port, echo_port],
+        );
+        scanner.set_udp_probes(HashMap::from([
+            (dns_port, super::UdpProbe::dns()),
+            (echo_port, super::UdpProbe::dns()),
+        ]));
+        scanner.set_timeout_ms(300);
+        let res = rt.block_on(scanner.scan_udp());
+
+        assert_eq!(res.len(), 2);
+        for r in res {
+            if let super::QScanResult::Udp(ur) = r {
+                if ur.target.port() == dns_port {
+                    assert_eq!(ur.state, super::QScanUdpState::Open);
+                } else {
+                    assert_eq!(ur.state, super::QScanUdpState::OpenFiltered);
+                }
+            } else {
+                panic!("unexpected result {:?}", r);
+            }
+        }
+    }
+
+    #[test]
+    fn target_groups() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "1").unwrap();
+        scanner.add_target_group("10.0.0.0/31", "80,443").unwrap();
+        scanner.add_target_group("10.0.1.1", "22").unwrap();
+        assert!(scanner.add_target_group("10.0.2.1", "1-2-3").is_err());
+
+        let socks: Vec<String> = scanner
+            .enumerate_targets()
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(
+            socks,
+            vec![
+                "127.0.0.1:1",
+                "10.0.0.0:80",
+                "10.0.0.1:80",
+                "10.0.0.0:443",
+                "10.0.0.1:443",
+                "10.0.1.1:22",
+            ]
+        );
+        assert_eq!(scanner.targets_count(), 6);
+    }
+
+    #[test]
+    fn scan_tcp_connect_stop_on_first_open() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut ports = vec![listener.local_addr().unwrap().port()];
+        for _ in 0..9 {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            ports.push(closed.local_addr().unwrap().port());
+        }
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            ],
+            ports,
+        );
+        scanner.set_batch(1);
+        scanner.set_stop_on_first_open(true);
+        let (res, stats) = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect_with_stats());
+
+        let count = |ip: &str, skipped: Option<super::QScanSkipReason>| {
+            res.iter()
+                .filter(|r| matches!(r, super::QScanResult::TcpConnect(r) if r.target.ip().to_string() == ip && r.skipped == skipped))
+                .count()
+        };
+        // The first port of 127.0.0.1 is open, its other ports are skipped,
+        // 127.0.0.2 has no open ports
+        let host_open = Some(super::QScanSkipReason::HostOpen);
+        assert_eq!(count("127.0.0.1", None), 1);
+        assert_eq!(count("127.0.0.1", host_open), 9);
+        assert_eq!(count("127.0.0.2", None), 10);
+        assert_eq!(stats.skipped, 9);
+        assert_eq!(stats.attempted + stats.skipped, 20);
+    }
+
+    #[test]
+    fn scan_tcp_connect_require_reachable() {
+        let (mut scanner, _proxy) = unresponsive_scanner(
+            vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            ],
+            (1..=1000).collect(),
+        );
+        scanner.set_batch(10);
+        scanner.set_require_reachable(true);
+        let rt = Runtime::new().unwrap();
+
+        let start = std::time::Instant::now();
+        match rt.block_on(scanner.check_reachable()) {
+            Err(super::QScanError::Unreachable { probed }) => {
+                assert_eq!(probed.len(), 2);
+                assert_ne!(probed[0].ip(), probed[1].ip());
+            }
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(rt.block_on(scanner.scan_tcp_connect()).is_empty());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // Every scan is aborted, with the error when it can be returned
+        assert!(matches!(
+            rt.block_on(scanner.scan_tcp_connect_checked()),
+            Err(super::QScanError::Unreachable { .. })
+        ));
+        let (res, stats) = rt.block_on(scanner.scan_tcp_connect_with_stats());
+        assert!(res.is_empty());
+        assert!(stats.unreachable);
+        let start = std::time::Instant::now();
+        assert!(rt.block_on(scanner.scan_tcp_connect_open_only()).is_empty());
+        assert!(rt.block_on(scanner.scan_tcp_connect_by_host()).is_empty());
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        // A refused connection is a response
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], vec![port]);
+        scanner.set_require_reachable(true);
+        assert!(rt.block_on(scanner.check_reachable()).is_ok());
+        assert_eq!(rt.block_on(scanner.scan_tcp_connect()).len(), 1);
+        assert_eq!(
+            rt.block_on(scanner.scan_tcp_connect_checked())
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn scan_tcp_connect_host_down_threshold() {
+        let (mut scanner, _proxy) = unresponsive_scanner(
+            vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            ],
+            (1..=10).collect(),
+        );
+        scanner.set_batch(1);
+        scanner.set_host_down_threshold(2);
+
+        let start = std::time::Instant::now();
+        let (res, stats) = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect_with_stats());
+        // 2 timeouts for each host instead of 10
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(res.len(), 20);
+        assert_eq!(stats.attempted, 4);
+        assert_eq!(stats.filtered, 4);
+        assert_eq!(stats.skipped, 16);
+
+        let mut attempted: std::collections::HashMap<IpAddr, usize> = Default::default();
+        for r in res {
+            match r {
+                super::QScanResult::TcpConnect(r) => {
+                    assert_eq!(r.state, super::QScanTcpConnectState::Filtered);
+                    if r.attempts > 0 {
+                        *attempted.entry(r.target.ip()).or_default() += 1;
+                        assert_eq!(r.skipped, None);
+                        assert_eq!(r.error_kind, Some(std::io::ErrorKind::TimedOut));
+                    } else {
+                        assert_eq!(r.skipped, Some(super::QScanSkipReason::HostDown));
+                        assert_eq!(r.error_kind, None);
+                    }
+                }
+                _ => panic!("unexpected result {:?}", r),
+            }
+        }
+        assert_eq!(attempted.len(), 2);
+        assert!(attempted.values().all(|n| *n == 2));
+
+        // A host which responded is not considered down
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![port, port, port],
+        );
+        scanner.set_host_down_threshold(1);
+        scanner.set_dedup(false);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        assert!(res
+            .iter()
+            .all(|r| matches!(r, super::QScanResult::TcpConnect(r) if r.attempts > 0)));
+    }
+
+    #[test]
+    fn diagnose_egress() {
+        let open: Vec<_> = (0..2)
+            .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let open_ports: Vec<u16> = open
+            .iter()
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let scanner = super::QScanner::new("127.0.0.1", "").unwrap();
+        let rt = Runtime::new().unwrap();
+        let ports = [open_ports[0], closed_port, open_ports[1]];
+        let report = rt.block_on(scanner.diagnose_egress(&ports)).unwrap();
+        assert_eq!(
+            report,
+            super::EgressReport {
+                host: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                permitted: open_ports,
+                refused: vec![closed_port],
+                blocked: Vec::new(),
+            }
+        );
+
+        let scanner = super::QScanner::new("", "").unwrap();
+        assert!(rt.block_on(scanner.diagnose_egress(&ports)).is_err());
+    }
+
+    #[test]
+    fn scan_tcp_connect_buffered_output() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        // Open ports, as the closed ones could be bound by the other tests
+        let listeners: Vec<std::net::TcpListener> = (0..50)
+            .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let ports: Vec<u16> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], ports.clone());
+        scanner.set_print_mode(super::QSPrintMode::RealTimeAll);
+        let out = Shared::default();
+        scanner.output = Some(Mutex::new(super::OutputBuffer::new(
+            Box::new(out.clone()),
+            Duration::from_secs(3600),
+        )));
+        Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        // Flushed at the end of the scan
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let mut printed: Vec<&str> = out.lines().collect();
+        printed.sort_unstable();
+        let mut expected: Vec<String> = ports
+            .iter()
+            .map(|p| format!("127.0.0.1:{}:OPEN", p))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(printed, expected);
+    }
+
+    #[test]
+    fn scan_tcp_connect_service_probe() {
+        use futures::future::BoxFuture;
+        use tokio::io::AsyncBufReadExt;
+
+        struct FirstLine;
+
+        impl super::ServiceProbe for FirstLine {
+            fn probe(&self, stream: tokio::net::TcpStream) -> BoxFuture<'_, super::ProbeResult> {
+                Box::pin(async move {
+                    let mut line = String::new();
+                    let _ = tokio::io::BufReader::new(stream).read_line(&mut line).await;
+                    super::ProbeResult {
+                        service: None,
+                        data: Some(line.trim_end().to_string()),
+                    }
+                })
+            }
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let target = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            use std::io::Write;
+            let (mut stream, _) = listener.accept().unwrap();
+            stream
+                .write_all(b"SSH-2.0-OpenSSH_9.0\r\nmore\r\n")
+                .unwrap();
+        });
+
+        let mut scanner = super::QScanner::new_from_vecs(vec![target.ip()], vec![target.port()]);
+        scanner.set_service_probe(Box::new(FirstLine));
+        let res = Runtime::new()
+            .unwrap()
+            .block_on(scanner.check_socket(target));
+        server.join().unwrap();
+
+        assert_eq!(res.state, super::QScanTcpConnectState::Open);
+        assert_eq!(
+            res.probe,
+            Some(super::ProbeResult {
+                service: None,
+                data: Some("SSH-2.0-OpenSSH_9.0".to_string()),
+            })
+        );
+    }
+
+    #[test]
+    fn scan_tcp_connect_local_port_range() {
+        let mut listeners = Vec::new();
+        for _ in 0..5 {
+            listeners.push(std::net::TcpListener::bind("127.0.0.1:0").unwrap());
+        }
+        let ports: Vec<u16> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], ports);
+        #[allow(clippy::reversed_empty_ranges)]
+        let empty = 45100..=45000;
+        assert!(scanner.set_local_port_range(empty).is_err());
+        scanner.set_local_port_range(45000..=45099).unwrap();
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        assert!(res.iter().all(|r| matches!(r, super::QScanResult::TcpConnect(r) if r.state == super::QScanTcpConnectState::Open)));
+
+        for listener in listeners {
+            let (_, peer) = listener.accept().unwrap();
+            assert!((45000..=45099).contains(&peer.port()));
+        }
+    }
+
+    #[test]
+    fn scan_tcp_connect_fast_open() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let close = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![open, close],
+        );
+        scanner.set_tcp_fast_open(true);
+        scanner.set_timeout_ms(1000);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        let mut states: Vec<(u16, super::QScanTcpConnectState)> = res
+            .iter()
+            .map(|r| match r {
+                super::QScanResult::TcpConnect(r) => (r.target.port(), r.state),
+                _ => panic!("unexpected result {:?}", r),
+            })
+            .collect();
+        states.sort_by_key(|(port, _)| *port != open);
+        assert_eq!(
+            states,
+            vec![
+                (open, super::QScanTcpConnectState::Open),
+                (close, super::QScanTcpConnectState::Close),
+            ]
+        );
+    }
+
+    #[test]
+    fn scan_tcp_connect_resolve_at_connect() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        scanner.set_resolve_at_connect(true);
+        scanner.set_resolve_policy(super::ResolvePolicy::AllV4);
+        scanner
+            .set_targets("localhost, nonexistent.invalid", &port.to_string())
+            .unwrap();
+        // Host names are not resolved when parsing the targets
+        assert!(scanner.get_tagets_ips().is_empty());
+        assert_eq!(scanner.targets_count(), 2);
+
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        let result = |name: &str| {
+            res.iter()
+                .find_map(|r| match r {
+                    super::QScanResult::TcpConnect(r) if r.hostname.as_deref() == Some(name) => {
+                        Some(r)
+                    }
+                    _ => None,
+                })
+                .unwrap()
+        };
+
+        let localhost = result("localhost");
+        assert_eq!(localhost.target, listener.local_addr().unwrap());
+        assert_eq!(localhost.state, super::QScanTcpConnectState::Open);
+
+        let invalid = result("nonexistent.invalid");
+        assert!(invalid.target.ip().is_unspecified());
+        assert_eq!(invalid.target.port(), port);
+        assert_eq!(invalid.state, super::QScanTcpConnectState::Filtered);
+        assert!(invalid.error_kind.is_some());
+        assert!(scanner.unresolved_targets().is_empty());
+    }
+
+    #[test]
+    fn scan_tcp_connect_resolve_at_connect_stop_on_first_open() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut ports = vec![listener.local_addr().unwrap().port()];
+        for _ in 0..9 {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            ports.push(closed.local_addr().unwrap().port());
+        }
+
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        scanner.set_resolve_at_connect(true);
+        scanner.set_resolve_policy(super::ResolvePolicy::AllV4);
+        scanner
+            .set_targets(
+                "localhost",
+                &ports
+                    .iter()
+                    .map(u16::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            )
+            .unwrap();
+        scanner.set_batch(1);
+        scanner.set_stop_on_first_open(true);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        // The first port of localhost is open, its other ports are skipped
+        assert_eq!(res.len(), 10);
+        assert!(matches!(
+            &res[0],
+            super::QScanResult::TcpConnect(r) if r.state == super::QScanTcpConnectState::Open
+        ));
+        assert!(res[1..].iter().all(|r| matches!(
+            r,
+            super::QScanResult::TcpConnect(r)
+                if r.skipped == Some(super::QScanSkipReason::HostOpen)
+                    && r.hostname.as_deref() == Some("localhost")
+        )));
+    }
+
+    #[test]
+    fn scan_tcp_connect_max_open_results() {
+        let mut listeners = Vec::new();
+        for _ in 0..10 {
+            listeners.push(std::net::TcpListener::bind("127.0.0.1:0").unwrap());
+        }
+        let ports: Vec<u16> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+
+        for batch in [1, 4, 100] {
+            let mut scanner = super::QScanner::new_from_vecs(
+                vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+                ports.clone(),
+            );
+            scanner.set_batch(batch);
+            scanner.set_max_open_results(3);
+            let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+            let open = res
+                .iter()
+                .filter(|r| matches!(r, super::QScanResult::TcpConnect(r) if r.state == super::QScanTcpConnectState::Open))
+                .count();
+            assert_eq!(open, 3);
+            assert!(res.len() <= 3);
+        }
+    }
+
+    #[test]
+    fn scan_tcp_connect_audit_log() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let close = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let path = std::env::temp_dir().join(format!("qscan_audit_{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![open, close],
+        );
+        // Independent of the print mode
+        scanner.set_print_mode(super::QSPrintMode::NonRealTime);
+        scanner.set_audit_log(&path).unwrap();
+        let rt = Runtime::new().unwrap();
+        rt.block_on(scanner.scan_tcp_connect());
+        // Appended
+        rt.block_on(scanner.scan_tcp_connect());
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<Vec<&str>> = log.lines().map(|l| l.split(' ').collect()).collect();
+        // One line for each scanned socket of each scan
+        assert_eq!(lines.len(), 4);
+        for l in &lines {
+            assert_eq!(l.len(), 4, "unexpected line {:?}", l);
+            let (secs, millis) = l[0].split_once('.').unwrap();
+            assert!(secs.parse::<u64>().is_ok() && millis.len() == 3);
+            assert_eq!(l[1], "127.0.0.1");
+        }
+        let mut states: Vec<(u16, &str)> = lines
+            .iter()
+            .map(|l| (l[2].parse().unwrap(), l[3]))
+            .collect();
+        states.sort_unstable();
+        let mut expected = vec![
+            (open, "OPEN"),
+            (open, "OPEN"),
+            (close, "CLOSE"),
+            (close, "CLOSE"),
+        ];
+        expected.sort_unstable();
+        assert_eq!(states, expected);
+
+        let dir = std::env::temp_dir();
+        assert!(scanner.set_audit_log(&dir).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn check_unix_socket() {
+        let path = std::env::temp_dir().join(format!("qscan_unix_{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let scanner = super::QScanner::new("", "").unwrap();
+        let rt = Runtime::new().unwrap();
+        let res = rt.block_on(scanner.check_unix_socket(&path));
+        assert_eq!(res.path, path);
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+
+        let listener = std::os::unix::net::UnixListener::bind(&path).unwrap();
+        let res = rt.block_on(scanner.check_unix_socket(&path));
+        assert_eq!(res.state, super::QScanTcpConnectState::Open);
+
+        // Not listening any more
+        drop(listener);
+        let res = rt.block_on(scanner.check_unix_socket(&path));
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+        std::fs::remove_file(&path).unwrap();
+
+        // Not a socket
+        let file = std::env::temp_dir().join(format!("qscan_unix_{}.txt", std::process::id()));
+        std::fs::write(&file, "").unwrap();
+        let res = rt.block_on(scanner.check_unix_socket(&file));
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn scan_tcp_connect_report_require_reachable() {
+        let (mut scanner, _proxy) =
+            unresponsive_scanner(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], (1..=100).collect());
+        scanner.set_require_reachable(true);
+
+        let (res, report) = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect_report());
+        assert!(res.is_empty());
+        assert!(
+            report.starts_with("scan aborted: no response from "),
+            "{}",
+            report
+        );
+    }
+
+    #[test]
+    fn scan_tcp_connect_report() {
+        use std::sync::{Arc, Mutex};
+
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let close = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![open, close],
+        );
+        scanner.set_print_mode(super::QSPrintMode::RealTimeAll);
+        let out = Shared::default();
+        scanner.output = Some(Mutex::new(super::OutputBuffer::new(
+            Box::new(out.clone()),
+            Duration::ZERO,
+        )));
+
+        let (res, report) = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect_report());
+        assert_eq!(res.len(), 2);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines.len(), 2, "unexpected report {:?}", report);
+        assert_eq!(lines[0], format!("127.0.0.1: {}", open));
+        assert!(lines[1].starts_with("2 targets in "));
+        assert!(lines[1].ends_with(": 1 open, 1 closed, 0 filtered, 0 errored, 0 skipped"));
+        // The print mode is ignored
+        assert!(out.0.lock().unwrap().is_empty());
+
+        // The port after the open one is skipped
+        scanner.set_batch(1);
+        scanner.set_stop_on_first_open(true);
+        let (_, report) = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect_report());
+        let summary = report.lines().last().unwrap();
+        assert!(summary.starts_with("2 targets in "), "{}", summary);
+        assert!(summary.ends_with(": 1 open, 0 closed, 0 filtered, 0 errored, 1 skipped"));
+    }
+
+    #[test]
+    fn scan_from_baseline() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let now_closed = closed.local_addr().unwrap();
+        drop(closed);
+
+        let path = std::env::temp_dir().join(format!("qscan_baseline_{}.txt", std::process::id()));
+        std::fs::write(&path, format!("{}\n\n{}\nnot a socket\n", now_closed, open)).unwrap();
+
+        // The targets of the scanner are not scanned
+        let mut scanner = super::QScanner::new("127.0.0.1", "1-100").unwrap();
+        let progress = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = progress.clone();
+        scanner.set_progress_callback(
+            Duration::ZERO,
+            Box::new(move |p| seen.lock().unwrap().push(p)),
+        );
+        let res = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_from_baseline(&path))
+            .unwrap();
+
+        let mut states: Vec<(SocketAddr, super::QScanTcpConnectState)> = res
+            .iter()
+            .map(|r| match r {
+                super::QScanResult::TcpConnect(r) => (r.target, r.state),
+                _ => panic!("unexpected result {:?}", r),
+            })
+            .collect();
+        states.sort_by_key(|(target, _)| *target == open);
+        assert_eq!(
+            states,
+            vec![
+                (now_closed, super::QScanTcpConnectState::Close),
+                (open, super::QScanTcpConnectState::Open),
+            ]
+        );
+        let last = *progress.lock().unwrap().last().unwrap();
+        assert_eq!((last.completed, last.total), (2, 2));
+
+        // The excluded addresses are not scanned
+        scanner.set_exclude_addresses("127.0.0.1");
+        let res = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_from_baseline(&path))
+            .unwrap();
+        assert!(res.is_empty());
+        std::fs::remove_file(&path).unwrap();
+
+        let missing = std::env::temp_dir().join("qscan_baseline_missing.txt");
+        let rt = Runtime::new().unwrap();
+        assert!(rt.block_on(scanner.scan_from_baseline(&missing)).is_err());
     }
 
-    /// Set targets addresses. Old targets are discarded
-    ///
-    /// # Arguments
-    ///
-    /// * `ips` - Target IPs
-    ///
-    /// # Examples
-    ///
-    ///```
-    /// use qscan::qscanner::QScanner;
-    /// use std::net::{IpAddr, Ipv4Addr};
-    /// let mut qs = QScanner::new("", "");
-    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))];
-    /// qs.set_vec_targets_addr(target_ips);
-    /// ```
-    pub fn set_vec_targets_addr(&mut self, ips: Vec<IpAddr>) {
-        self.ips = ips;
+    #[test]
+    fn scan_tcp_connect_resume() {
+        let path =
+            std::env::temp_dir().join(format!("qscan_checkpoint_{}.txt", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut ports = Vec::new();
+        for _ in 0..5 {
+            let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            ports.push(closed.local_addr().unwrap().port());
+        }
+        let rt = Runtime::new().unwrap();
+
+        // Interrupted scan: only the first 3 ports are completed
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            ports[..3].to_vec(),
+        );
+        scanner.set_checkpoint_file(&path);
+        assert_eq!(rt.block_on(scanner.scan_tcp_connect()).len(), 3);
+
+        let recorded = std::fs::read_to_string(&path).unwrap();
+        let mut recorded: Vec<&str> = recorded.lines().collect();
+        recorded.sort_unstable();
+        let mut expected: Vec<String> = ports[..3]
+            .iter()
+            .map(|p| format!("127.0.0.1:{}", p))
+            .collect();
+        expected.sort_unstable();
+        assert_eq!(recorded, expected);
+
+        // The resumed scan only covers the remaining ports
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], ports.clone());
+        scanner.resume_from(&path).unwrap();
+        scanner.set_checkpoint_file(&path);
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        let mut scanned: Vec<u16> = res
+            .iter()
+            .map(|r| match r {
+                super::QScanResult::TcpConnect(r) => r.target.port(),
+                r => panic!("unexpected result {:?}", r),
+            })
+            .collect();
+        scanned.sort_unstable();
+        let mut remaining = ports[3..].to_vec();
+        remaining.sort_unstable();
+        assert_eq!(scanned, remaining);
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 5);
+
+        std::fs::remove_file(&path).unwrap();
     }
-    /// Set targets port. Old targets are discarded
-    ///
-    /// # Arguments
-    ///
-    /// * `ports` - Target ports
-    ///
-    /// # Examples
-    ///
-    ///```
-    /// use qscan::qscanner::QScanner;
-    /// use std::net::{IpAddr, Ipv4Addr};
-    /// let mut qs = QScanner::new("", "");
-    /// let target_ports = vec![80];
-    /// qs.set_vec_targets_port(target_ports);
-    /// ```
-    pub fn set_vec_targets_port(&mut self, ports: Vec<u16>) {
-        self.ports = ports;
+
+    #[test]
+    fn close_mode_reset() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let mut scanner = super::QScanner::new_from_vecs(vec![addr.ip()], vec![addr.port()]);
+        scanner.set_close_mode(super::CloseMode::Reset);
+
+        let rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            assert_eq!(stream.linger().unwrap(), None);
+            scanner.close(&mut stream).await.unwrap();
+            assert_eq!(stream.linger().unwrap(), Some(Duration::ZERO));
+        });
+
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        if let Some(super::QScanResult::TcpConnect(r)) = res.first() {
+            assert_eq!(r.state, super::QScanTcpConnectState::Open);
+        } else {
+            panic!("unexpected results {:?}", res);
+        }
     }
 
-    /// Set targets. Old targets are discarded
-    ///
-    /// # Arguments
-    ///
-    /// * `ips` - Target IPs
-    /// * `ports` - Target ports
-    ///
-    /// # Examples
-    ///
-    ///```
-    /// use qscan::qscanner::QScanner;
-    /// use std::net::{IpAddr, Ipv4Addr};
-    /// let mut qs = QScanner::new("", "");
-    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))];
-    /// let target_ports = vec![80];
-    /// qs.set_vec_targets(target_ips, target_ports);
-    /// ```
-    pub fn set_vec_targets(&mut self, ips: Vec<IpAddr>, ports: Vec<u16>) {
-        self.ips = ips;
-        self.ports = ports;
+    #[test]
+    fn check_socket() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        scanner.set_timeout_ms(500);
+        scanner.set_ntries(2);
+
+        let rt = Runtime::new().unwrap();
+        let res = rt.block_on(scanner.check_socket(open));
+        assert_eq!(res.target, open);
+        assert_eq!(res.state, super::QScanTcpConnectState::Open);
+        assert!(res.latency.is_some());
+
+        let res = rt.block_on(scanner.check_socket(closed));
+        assert_eq!(res.target, closed);
+        assert_eq!(res.state, super::QScanTcpConnectState::Close);
+        assert_eq!(res.latency, None);
+        assert!(scanner.get_last_results().is_none());
     }
 
-    /// Add new targets (addresses)
-    ///
-    /// # Arguments
-    ///
-    /// * `ips` - Target IPs
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use qscan::qscanner::QScanner;
-    /// use std::net::{IpAddr, Ipv4Addr};
-    /// let mut qs = QScanner::new("127.0.0.1", "80");
-    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))];
-    /// qs.add_vec_targets_addr(target_ips);
-    /// ```
-    pub fn add_vec_targets_addr(&mut self, ips: Vec<IpAddr>) {
-        self.ips.extend(ips);
-        self.ips = self
-            .ips
-            .clone()
-            .into_iter()
-            .unique()
-            .collect::<Vec<IpAddr>>();
+    #[test]
+    fn port_priority() {
+        let mut scanner = super::QScanner::new("127.0.0.1,127.0.0.2", "21,22,80,443,8080").unwrap();
+        scanner.set_port_priority(vec![443, 22, 3389]);
+        let ports: Vec<u16> = scanner
+            .enumerate_targets()
+            .iter()
+            .map(|t| t.port())
+            .collect();
+        assert_eq!(ports, vec![443, 443, 22, 22, 21, 21, 80, 80, 8080, 8080]);
+
+        // The priority is kept when the ports change
+        scanner.set_targets_port("80,8080,22").unwrap();
+        let targets = scanner.enumerate_targets();
+        assert_eq!(targets.len(), 6);
+        assert_eq!(targets[0], "127.0.0.1:22".parse().unwrap());
+        assert_eq!(targets[1], "127.0.0.2:22".parse().unwrap());
     }
 
-    /// Add new targets (port)
-    ///
-    /// # Arguments
-    ///
-    /// * `ports` - Target ports
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use qscan::qscanner::QScanner;
-    /// use std::net::{IpAddr, Ipv4Addr};
-    /// let mut qs = QScanner::new("127.0.0.1", "80");
-    /// let target_ports = vec![443];
-    /// qs.add_vec_targets_port(target_ports);
-    /// ```
-    pub fn add_vec_targets_port(&mut self, ports: Vec<u16>) {
-        self.ports.extend(ports);
-        self.ports = self
-            .ports
-            .clone()
-            .into_iter()
-            .unique()
-            .collect::<Vec<u16>>();
+    #[test]
+    fn scan_tcp_connect_adaptive_batch() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            (1..=300).chain(std::iter::once(port)).collect(),
+        );
+        scanner.set_adaptive_batch(true);
+
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        assert_eq!(res.len(), scanner.targets_count() as usize);
     }
 
-    /// Add new targets
-    ///
-    /// # Arguments
-    ///
-    /// * `ips` - Target IPs
-    /// * `ports` - Target ports
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use qscan::qscanner::QScanner;
-    /// use std::net::{IpAddr, Ipv4Addr};
-    /// let mut qs = QScanner::new("127.0.0.1", "80");
-    /// let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))];
-    /// let target_ports = vec![443];
-    /// qs.add_vec_targets(target_ips, target_ports);
-    /// ```
-    pub fn add_vec_targets(&mut self, ips: Vec<IpAddr>, ports: Vec<u16>) {
-        self.ips.extend(ips);
-        self.ips = self
-            .ips
-            .clone()
-            .into_iter()
-            .unique()
-            .collect::<Vec<IpAddr>>();
-        self.ports.extend(ports);
-        self.ports = self
-            .ports
-            .clone()
-            .into_iter()
-            .unique()
-            .collect::<Vec<u16>>();
+    #[test]
+    fn scan_tcp_connect_adaptive_timeout() {
+        let listeners: Vec<std::net::TcpListener> = (0..5)
+            .map(|_| std::net::TcpListener::bind("127.0.0.1:0").unwrap())
+            .collect();
+        let ports: Vec<u16> = listeners
+            .iter()
+            .map(|l| l.local_addr().unwrap().port())
+            .collect();
+
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], ports.clone());
+        scanner.set_timeout_ms(5000);
+        let local = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 1);
+        let other = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)), 1);
+        assert_eq!(scanner.socket_timeout(local), Duration::from_secs(5));
+
+        scanner.set_adaptive_timeout(true);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        assert!(res.iter().all(|r| matches!(r, super::QScanResult::TcpConnect(r) if r.state == super::QScanTcpConnectState::Open)));
+
+        // The fast local host gets a shorter timeout, the others the global one
+        let to = scanner.socket_timeout(local);
+        assert!(to >= Duration::from_millis(100) && to < Duration::from_secs(5));
+        assert_eq!(scanner.socket_timeout(other), Duration::from_secs(5));
+
+        let mut rtt = super::adaptive::RttEstimator::default();
+        let ip = local.ip();
+        for _ in 0..2 {
+            rtt.record(ip, Duration::from_millis(200));
+        }
+        assert_eq!(
+            rtt.timeout(ip, Duration::from_secs(5)),
+            Duration::from_secs(5)
+        );
+        rtt.record(ip, Duration::from_millis(200));
+        let to = rtt.timeout(ip, Duration::from_secs(5));
+        assert!(to > Duration::from_millis(200) && to < Duration::from_secs(1));
+        assert_eq!(
+            rtt.timeout(ip, Duration::from_millis(50)),
+            Duration::from_millis(50)
+        );
     }
 
-    #[cfg(feature = "serialize")]
-    pub fn get_last_results_as_json_string(&self) -> serde_json::Result<String> {
-        serde_json::to_string(&self.last_results)
+    #[test]
+    fn adaptive_batch_grows_with_low_errors() {
+        let mut c = super::adaptive::BatchController::new(1000);
+        assert_eq!(c.limit(), 64);
+        for _ in 0..64 {
+            c.record(false);
+        }
+        assert_eq!(c.limit(), 128);
+        for _ in 0..10000 {
+            c.record(false);
+        }
+        assert_eq!(c.limit(), 1000);
     }
 
-    /// Async TCP connect scan
-    ///
-    /// # Return
-    ///
-    /// A vector of [SocketAddr] for each open port found.
-    ///
-    /// # Examples
-    ///
-    /// ```
-    /// use qscan::qscanner::QScanner;
-    /// use tokio::runtime::Runtime;
-    /// let mut scanner = QScanner::new("127.0.0.1", "80");
-    /// let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
-    /// ```
-    ///
-    pub async fn scan_tcp_connect(&mut self) -> &Vec<QScanResult> {
-        let mut sock_res: Vec<QScanResult> = Vec::new();
-        let mut sock_it: sockiter::SockIter = sockiter::SockIter::new(&self.ips, &self.ports);
-        let mut ftrs = FuturesUnordered::new();
+    #[test]
+    fn adaptive_batch_shrinks_with_high_errors() {
+        let mut c = super::adaptive::BatchController::new(1000);
+        for i in 0..64 {
+            c.record(i % 2 == 0);
+        }
+        assert_eq!(c.limit(), 32);
+        for _ in 0..1000 {
+            c.record(true);
+        }
+        assert_eq!(c.limit(), 1);
 
-        for _ in 0..self.batch {
-            if let Some(socket) = sock_it.next() {
-                ftrs.push(self.scan_socket_tcp_connect(socket));
-            } else {
-                break;
-            }
+        c.back_off();
+        assert_eq!(c.limit(), 1);
+    }
+
+    #[test]
+    fn adaptive_batch_steady_with_some_errors() {
+        let mut c = super::adaptive::BatchController::new(1000);
+        for i in 0..640 {
+            c.record(i % 10 == 0);
         }
+        assert_eq!(c.limit(), 64);
+    }
 
-        while let Some(result) = ftrs.next().await {
-            if let Some(socket) = sock_it.next() {
-                ftrs.push(self.scan_socket_tcp_connect(socket));
+    #[test]
+    fn set_top_ports() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "1-10").unwrap();
+
+        scanner.set_top_ports(10);
+        assert_eq!(
+            scanner.get_tagets_ports(),
+            &vec![80, 23, 443, 21, 22, 25, 3389, 110, 445, 139]
+        );
+
+        scanner.set_top_ports(1000);
+        assert_eq!(scanner.get_tagets_ports().len(), super::TOP_PORTS.len());
+    }
+
+    #[test]
+    fn parse_ports_file() {
+        let path = std::env::temp_dir().join(format!("qscan_ports_{}.txt", std::process::id()));
+        std::fs::write(&path, "22\n80-82, 443\n\nhttps\n").unwrap();
+
+        let ports = format!("8080,{}", path.display());
+        let res = super::ports_parse(&ports);
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(res, Ok(vec![8080, 22, 80, 81, 82, 443]));
+    }
+
+    #[test]
+    fn parse_ports_file_invalid_line() {
+        let path = std::env::temp_dir().join(format!("qscan_bad_ports_{}.txt", std::process::id()));
+        std::fs::write(&path, "22\n100-50\n").unwrap();
+
+        let res = super::ports_parse(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(res, Err(super::PortParseError::ReversedRange(100, 50)));
+    }
+
+    #[test]
+    fn parse_ports_invalid_range() {
+        assert_eq!(
+            super::ports_parse("1-2-3"),
+            Err(super::PortParseError::InvalidRange("1-2-3".to_string()))
+        );
+    }
+
+    #[test]
+    fn parse_ports_reversed_range() {
+        assert_eq!(
+            super::ports_parse("200-100"),
+            Err(super::PortParseError::ReversedRange(200, 100))
+        );
+    }
+
+    #[test]
+    fn parse_ports_too_many() {
+        let all = vec!["1-65535"; 16].join(",");
+        assert_eq!(
+            super::ports_parse_with_duplicates(&all).unwrap().len(),
+            16 * 65535
+        );
+
+        let all = vec!["1-65535"; 17].join(",");
+        assert_eq!(
+            super::ports_parse(&all),
+            Err(super::PortParseError::TooManyPorts(super::PORTS_MAX))
+        );
+    }
+
+    #[test]
+    fn validate_inputs() {
+        assert_eq!(
+            super::QScanner::validate_inputs("127.0.0.1, ::1 10.0.0.0/29", "80,443,8000-8009")
+                .unwrap(),
+            (10, 12)
+        );
+        assert_eq!(
+            super::QScanner::validate_inputs("127.0.0.1,127.0.0.1", "22,22").unwrap(),
+            (1, 1)
+        );
+
+        match super::QScanner::validate_inputs("127.0.0.1,10.0.0.256,not-a-host.invalid", "80") {
+            Err(super::QScanError::InvalidTargets(targets)) => {
+                assert_eq!(targets, ["10.0.0.256", "not-a-host.invalid"])
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(matches!(
+            super::QScanner::validate_inputs("127.0.0.1", "80,9o"),
+            Err(super::QScanError::InvalidPorts(
+                super::PortParseError::InvalidNumber(_)
+            ))
+        ));
+        // Ports are validated before the targets
+        assert!(matches!(
+            super::QScanner::validate_inputs("10.0.0.256", "80-"),
+            Err(super::QScanError::InvalidPorts(_))
+        ));
+
+        match super::QScanner::validate_inputs("127.0.0.1,10.0.0.0/7", "80") {
+            Err(super::QScanError::TooManyHosts { target, max }) => {
+                assert_eq!(target, "10.0.0.0/7");
+                assert_eq!(max, super::MAX_CIDR_HOSTS_DEF);
             }
+            r => panic!("unexpected result {:?}", r),
+        }
+        assert!(matches!(
+            super::QScanner::validate_inputs("", "80"),
+            Err(super::QScanError::NoTargets)
+        ));
+        assert!(matches!(
+            super::QScanner::validate_inputs(" , ", "80"),
+            Err(super::QScanError::NoTargets)
+        ));
+    }
 
-            match result {
-                Ok(socket) => {
-                    match self.print_mode {
-                        QSPrintMode::RealTime => {
-                            println!("{}:{}", socket.ip(), socket.port());
-                        }
-                        QSPrintMode::RealTimeAll => {
-                            println!("{}:{}:OPEN", socket.ip(), socket.port());
-                        }
-                        _ => {}
-                    }
+    #[test]
+    fn local_subnet_targets() {
+        let local = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 37));
+        let ips = super::subnet_targets(local);
+        assert_eq!(ips.len(), 253);
+        assert!(!ips.contains(&local));
+        assert!(ips.iter().all(|ip| match ip {
+            IpAddr::V4(ip) => ip.octets()[..3] == [192, 168, 1] && ip.octets()[3] != 0,
+            _ => false,
+        }));
+
+        let local: IpAddr = "2001:db8:1:2::3".parse().unwrap();
+        let ips = super::subnet_targets(local);
+        assert_eq!(ips.len(), 65534);
+        assert!(!ips.contains(&local));
+        assert_eq!(ips[0], "2001:db8:1:2::1".parse::<IpAddr>().unwrap());
+        assert_eq!(ips[65533], "2001:db8:1:2::ffff".parse::<IpAddr>().unwrap());
+
+        // The primary address of the host, if any, is in the same subnet
+        if let Ok(ips) = super::QScanner::local_subnet_targets() {
+            let local = super::primary_ip(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+                .or_else(|_| super::primary_ip("2001:db8::1".parse().unwrap()))
+                .unwrap();
+            assert!(!ips.contains(&local));
+            assert_eq!(ips, super::subnet_targets(local));
+        }
+    }
+
+    #[test]
+    fn targets_count_all_ports() {
+        let scanner = super::QScanner::new("10.0.0.0/16", "1-65535").unwrap();
+        assert_eq!(scanner.targets_count(), 65536 * 65535);
+    }
+
+    #[test]
+    fn new_invalid_ports() {
+        assert!(super::QScanner::new("127.0.0.1", "1-2-3").is_err());
+    }
+
+    #[test]
+    fn set_invalid_targets_keeps_old() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "80").unwrap();
+        assert!(scanner.set_targets("1.1.1.1", "80-").is_err());
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(*scanner.get_tagets_ports(), vec![80]);
+    }
+
+    #[test]
+    fn exclude_address_from_cidr() {
+        let mut scanner = super::QScanner::new("192.168.1.0/24", "80").unwrap();
+        scanner.set_exclude_addresses("192.168.1.1");
+        let ips = scanner.get_tagets_ips();
+        assert_eq!(ips.len(), 255);
+        assert!(!ips.contains(&"192.168.1.1".parse::<IpAddr>().unwrap()));
+        assert!(ips.contains(&"192.168.1.2".parse::<IpAddr>().unwrap()));
+    }
+
+    #[test]
+    fn exclude_addresses_whitespace_separated() {
+        let mut scanner = super::QScanner::new("10.0.0.0/29", "80").unwrap();
+        scanner.set_exclude_addresses("10.0.0.1 10.0.0.2\n10.0.0.4 / 30");
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec![
+                "10.0.0.0".parse::<IpAddr>().unwrap(),
+                "10.0.0.3".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn exclude_cidr_applied_to_new_targets() {
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        scanner.set_exclude_addresses("127.0.0.0/30");
+        scanner.set_targets_addr("127.0.0.0/29");
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec![
+                "127.0.0.4".parse::<IpAddr>().unwrap(),
+                "127.0.0.5".parse::<IpAddr>().unwrap(),
+                "127.0.0.6".parse::<IpAddr>().unwrap(),
+                "127.0.0.7".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
 
-                    sock_res.push(QScanResult::TcpConnect(QScanTcpConnectResult {
-                        target: socket,
-                        state: QScanTcpConnectState::Open,
-                    }));
-                }
-                Err(error) => {
-                    if let QSPrintMode::RealTimeAll = self.print_mode {
-                        println!("{}:{}:CLOSE", error.sock.ip(), error.sock.port());
-                    }
+    #[test]
+    fn scan_tcp_connect_targets_file() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
 
-                    sock_res.push(QScanResult::TcpConnect(QScanTcpConnectResult {
-                        target: error.sock,
-                        state: QScanTcpConnectState::Close,
-                    }));
-                }
-            }
+        let path = std::env::temp_dir().join(format!("qscan_targets_{}.txt", std::process::id()));
+        std::fs::write(&path, "127.0.0.1\n\n127.0.0.2/31\nlocalhost\n10.0.0.1\n").unwrap();
+
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        scanner.set_vec_targets_port(vec![port]);
+        scanner.set_exclude_addresses("10.0.0.1");
+        scanner.set_targets_file(&path).unwrap();
+        scanner.add_targets_addr("127.0.0.4");
+
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        std::fs::remove_file(&path).unwrap();
+
+        let targets: Vec<SocketAddr> = res
+            .iter()
+            .map(|r| match r {
+                super::QScanResult::TcpConnect(r) => r.target,
+                _ => panic!("unexpected result {:?}", r),
+            })
+            .collect();
+        // 127.0.0.4, the file addresses and localhost
+        assert_eq!(targets.len(), 5);
+        for ip in [
+            [127, 0, 0, 1],
+            [127, 0, 0, 2],
+            [127, 0, 0, 3],
+            [127, 0, 0, 4],
+        ] {
+            assert!(targets.contains(&SocketAddr::new(IpAddr::from(ip), port)));
         }
+    }
 
-        drop(ftrs);
-        self.last_results = Some(sock_res);
-        self.last_results.as_ref().unwrap()
+    #[test]
+    fn targets_file_is_lazy() {
+        let path = std::env::temp_dir().join(format!("qscan_lazy_{}.txt", std::process::id()));
+        std::fs::write(&path, "10.0.0.0/8\n").unwrap();
+
+        let mut scanner = super::QScanner::new("", "80,443").unwrap();
+        scanner.set_targets_file(&path).unwrap();
+        let first: Vec<SocketAddr> = scanner.sock_iter().take(3).collect();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            first,
+            vec![
+                "10.0.0.0:80".parse().unwrap(),
+                "10.0.0.0:443".parse().unwrap(),
+                "10.0.0.1:80".parse().unwrap(),
+            ]
+        );
+        assert!(scanner
+            .set_targets_file(std::path::Path::new("/qscan/not/a/file"))
+            .is_err());
     }
 
-    /// TODO: add comments
-    pub async fn scan_ping(&mut self) -> &Vec<QScanResult> {
-        let client_v4 = surge_ping::Client::new(&surge_ping::Config::default())
-            .expect("Error creating ping IPv4 Client");
-        let client_v6 = surge_ping::Client::new(
-            &surge_ping::Config::builder()
-                .kind(surge_ping::ICMP::V6)
-                .build(),
-        )
-        .expect("Error creating ping IPv6 client");
-        let mut ip_res: Vec<QScanResult> = Vec::new();
-        let mut ftrs = FuturesUnordered::new();
-        let mut ip_it = self.ips.iter();
+    #[test]
+    fn scan_tcp_connect_targets_cidr() {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let mut scanner = super::QScanner::new("", &port.to_string()).unwrap();
+        scanner.set_targets_cidr("127.0.0.0/20").unwrap();
+        scanner.set_batch(1000);
+        scanner.set_timeout_ms(1000);
+        scanner.set_ntries(1);
+        assert!(scanner.ips.is_empty());
+        assert_eq!(scanner.targets_count(), 4096);
 
-        for _ in 0..self.batch {
-            if let Some(ip) = ip_it.next() {
-                ftrs.push(self.scan_ip_ping(*ip, &client_v4, &client_v6));
-            } else {
-                break;
-            }
-        }
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        assert_eq!(res.len(), 4096);
+        assert!(scanner.ips.is_empty());
+
+        assert!(matches!(
+            scanner.set_targets_cidr("127.0.0.0/33"),
+            Err(super::QScanError::InvalidTargets(_))
+        ));
+        assert!(scanner.targets_cidr.is_some());
+    }
 
-        while let Some(result) = ftrs.next().await {
-            if let Some(ip) = ip_it.next() {
-                ftrs.push(self.scan_ip_ping(*ip, &client_v4, &client_v6));
-            }
+    #[test]
+    fn exclude_cidr_from_cidr() {
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        scanner.set_exclude_addresses("10.0.0.128/25");
+        scanner.set_targets_addr("10.0.0.0/24");
+
+        let ips = scanner.get_tagets_ips();
+        assert_eq!(ips.len(), 128);
+        assert_eq!(ips[0], IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)));
+        assert_eq!(ips[127], IpAddr::V4(Ipv4Addr::new(10, 0, 0, 127)));
+
+        // The limit applies to the surviving addresses only
+        scanner.set_max_cidr_hosts(1 << 15);
+        scanner.set_exclude_addresses("10.1.128.0/17");
+        scanner.set_targets_addr("10.1.0.0/16");
+        assert_eq!(scanner.get_tagets_ips().len(), 1 << 15);
+    }
 
-            match result {
-                Ok(ip) => {
-                    match self.print_mode {
-                        QSPrintMode::RealTime => {
-                            println!("{}", ip);
-                        }
-                        QSPrintMode::RealTimeAll => {
-                            println!("{}:UP", ip);
-                        }
-                        _ => {}
-                    }
+    #[test]
+    fn cidr_subtract() {
+        let cidr = |s: &str| super::IpCidr::from_str(s).unwrap();
 
-                    ip_res.push(QScanResult::Ping(QScanPingResult {
-                        target: ip,
-                        state: QScanPingState::Up,
-                    }));
-                }
-                Err(ip) => {
-                    if let QSPrintMode::RealTimeAll = self.print_mode {
-                        println!("{}:DOWN", ip);
-                    }
+        let blocks = super::cidr_subtract(cidr("10.0.0.0/24"), &[cidr("10.0.0.64/26")]);
+        assert_eq!(blocks, vec![cidr("10.0.0.0/26"), cidr("10.0.0.128/25")]);
 
-                    ip_res.push(QScanResult::Ping(QScanPingResult {
-                        target: ip,
-                        state: QScanPingState::Down,
-                    }));
-                }
-            }
-        }
+        let blocks = super::cidr_subtract(cidr("10.0.0.0/30"), &[cidr("10.0.0.2/32")]);
+        assert_eq!(blocks, vec![cidr("10.0.0.0/31"), cidr("10.0.0.3/32")]);
 
-        drop(ftrs);
-        self.last_results = Some(ip_res);
-        self.last_results.as_ref().unwrap()
+        let blocks = super::cidr_subtract(cidr("2001:db8::/64"), &[cidr("2001:db8::/48")]);
+        assert!(blocks.is_empty());
+
+        let blocks = super::cidr_subtract(cidr("10.0.0.0/24"), &[cidr("2001:db8::/48")]);
+        assert_eq!(blocks, vec![cidr("10.0.0.0/24")]);
     }
 
-    async fn scan_socket_tcp_connect(&self, socket: SocketAddr) -> Result<SocketAddr, QScanError> {
-        let tries = self.tries.get();
+    #[test]
+    fn exclude_address_from_file() {
+        let path = std::env::temp_dir().join(format!("qscan_exclude_{}.txt", std::process::id()));
+        std::fs::write(&path, "10.0.0.1\n10.0.0.2\n10.0.0.3\n").unwrap();
 
-        for ntry in 0..tries {
-            match self.tcp_connect(socket).await {
-                Ok(Ok(mut x)) => {
-                    if x.shutdown().await.is_err() {
-                        return Err(QScanError {
-                            msg: "Shutdown error".to_string(),
-                            sock: socket,
-                        });
-                    } else {
-                        return Ok(socket);
-                    }
-                }
-                Ok(Err(e)) => {
-                    let mut err_str = e.to_string();
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        scanner.set_exclude_addresses("10.0.0.2");
+        scanner.set_targets_addr(path.to_str().unwrap());
+        std::fs::remove_file(&path).unwrap();
 
-                    if err_str.to_lowercase().contains("too many open files") {
-                        panic!("Too many open files, reduce batch size {}", self.batch);
-                    }
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec![
+                "10.0.0.1".parse::<IpAddr>().unwrap(),
+                "10.0.0.3".parse::<IpAddr>().unwrap(),
+            ]
+        );
+    }
 
-                    if ntry == tries - 1 {
-                        err_str.push(' ');
-                        err_str.push_str(&socket.ip().to_string());
-                        return Err(QScanError {
-                            msg: err_str,
-                            sock: socket,
-                        });
-                    }
-                }
-                Err(e) => {
-                    let mut err_str = e.to_string();
+    #[test]
+    fn exclude_ports() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "20-25,80").unwrap();
+        scanner.set_exclude_ports("21-23").unwrap();
+        assert_eq!(*scanner.get_tagets_ports(), vec![20, 24, 25, 80]);
+        scanner.add_targets_port("22,443").unwrap();
+        assert_eq!(*scanner.get_tagets_ports(), vec![20, 24, 25, 80, 443]);
+        assert!(scanner.set_exclude_ports("1-").is_err());
+    }
 
-                    if ntry == tries - 1 {
-                        err_str.push(' ');
-                        err_str.push_str(&socket.ip().to_string());
-                        return Err(QScanError {
-                            msg: err_str,
-                            sock: socket,
-                        });
-                    }
-                }
-            };
-        }
-        unreachable!();
+    #[test]
+    fn retry_backoff_timeouts() {
+        let base = Duration::from_millis(100);
+
+        let fixed = super::RetryBackoff::Fixed;
+        assert_eq!(fixed.timeout(base, 0), base);
+        assert_eq!(fixed.timeout(base, 5), base);
+
+        let linear = super::RetryBackoff::Linear { step_ms: 50 };
+        assert_eq!(linear.timeout(base, 0), Duration::from_millis(100));
+        assert_eq!(linear.timeout(base, 1), Duration::from_millis(150));
+        assert_eq!(linear.timeout(base, 4), Duration::from_millis(300));
+
+        let exp = super::RetryBackoff::Exponential { factor: 2 };
+        assert_eq!(exp.timeout(base, 0), Duration::from_millis(100));
+        assert_eq!(exp.timeout(base, 1), Duration::from_millis(200));
+        assert_eq!(exp.timeout(base, 3), Duration::from_millis(800));
+        assert_eq!(exp.timeout(base, u8::MAX), base * u32::MAX);
     }
 
-    async fn scan_ip_ping(
-        &self,
-        ip: IpAddr,
-        client4: &surge_ping::Client,
-        client6: &surge_ping::Client,
-    ) -> Result<IpAddr, IpAddr> {
-        let mut client = client4;
+    #[test]
+    fn set_new_targets() {
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        scanner.set_targets("1.1.1.1", "80").unwrap();
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec!["1.1.1.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(*scanner.get_tagets_ports(), vec![80]);
+    }
 
-        if ip.is_ipv6() {
-            client = client6;
-        }
+    #[test]
+    fn add_new_targets() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "80").unwrap();
+        scanner
+            .add_targets("127.0.0.0/30,192.168.1.1", "79-80,81")
+            .unwrap();
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "127.0.0.0".parse::<IpAddr>().unwrap(),
+                "127.0.0.2".parse::<IpAddr>().unwrap(),
+                "127.0.0.3".parse::<IpAddr>().unwrap(),
+                "192.168.1.1".parse::<IpAddr>().unwrap(),
+            ]
+        );
+        assert_eq!(*scanner.get_tagets_ports(), vec![80, 79, 81]);
+    }
 
-        match self.ping(client, ip).await {
-            QScanPingState::Up => Ok(ip),
-            QScanPingState::Down => Err(ip),
-        }
+    #[test]
+    fn set_vec_new_targets() {
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))];
+        let target_ports = vec![80];
+        scanner.set_vec_targets(target_ips, target_ports);
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+        );
+        assert_eq!(*scanner.get_tagets_ports(), vec![80]);
     }
 
-    async fn tcp_connect(&self, socket: SocketAddr) -> Result<io::Result<TcpStream>, Elapsed> {
-        // See https://stackoverflow.com/questions/30022084/how-do-i-set-connect-timeout-on-tcpstream
-        timeout(self.to, TcpStream::connect(socket)).await
+    #[test]
+    fn add_vec_new_targets() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "80").unwrap();
+        let target_ips = vec![
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+        ];
+        let target_ports = vec![443, 80, 53];
+        scanner.add_vec_targets(target_ips, target_ports);
+        assert_eq!(
+            *scanner.get_tagets_ips(),
+            vec![
+                "127.0.0.1".parse::<IpAddr>().unwrap(),
+                "127.0.0.2".parse::<IpAddr>().unwrap(),
+            ]
+        );
+        assert_eq!(*scanner.get_tagets_ports(), vec![80, 443, 53]);
     }
 
-    async fn ping(&self, client: &surge_ping::Client, addr: IpAddr) -> QScanPingState {
-        let mut pinger = client
-            .pinger(addr, surge_ping::PingIdentifier(rand::random()))
-            .await;
-        pinger.timeout(self.to);
-        let mut interval = time::interval(self.ping_interval);
-        for idx in 0..self.tries.get() {
-            match pinger
-                .ping(surge_ping::PingSequence(idx as u16), &self.ping_payload)
-                .await
-            {
-                Ok((surge_ping::IcmpPacket::V4(_), _)) => {
-                    return QScanPingState::Up;
-                }
-                Ok((surge_ping::IcmpPacket::V6(_), _)) => {
-                    return QScanPingState::Up;
+    #[test]
+    fn scan_tcp_connect_google_dns() {
+        let mut scanner = super::QScanner::new("8.8.8.8", "53,54,55-60").unwrap();
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        for r in res {
+            if let super::QScanResult::TcpConnect(sa) = r {
+                if sa.state == super::QScanTcpConnectState::Open {
+                    assert_eq!(
+                        sa.target,
+                        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 53)
+                    );
                 }
-                _ => {}
             }
-            interval.tick().await;
         }
-        QScanPingState::Down
     }
-}
 
-/// Parse ports strings, comma separated strings and ranges.
-/// E.g., "80", "80,443", "80,100-200,443"
-fn ports_parse(ports: &str) -> Vec<u16> {
-    let mut pv: Vec<u16> = Vec::new();
-    let ps: String = ports.chars().filter(|c| !c.is_whitespace()).collect();
+    #[test]
+    fn scan_tcp_connect_stream_localhost() {
+        use futures::StreamExt;
 
-    for p in ps.split(',') {
-        if p.is_empty() {
-            continue;
-        }
+        let listener1 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener2 = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![
+                listener1.local_addr().unwrap().port(),
+                closed_port,
+                listener2.local_addr().unwrap().port(),
+            ],
+        );
+
+        let (open, total) =
+            Runtime::new()
+                .unwrap()
+                .block_on(scanner.scan_tcp_connect_stream().fold(
+                    (0, 0),
+                    |(open, total), r| async move {
+                        match r {
+                            super::QScanResult::TcpConnect(sa)
+                                if sa.state == super::QScanTcpConnectState::Open =>
+                            {
+                                (open + 1, total + 1)
+                            }
+                            _ => (open, total + 1),
+                        }
+                    },
+                ));
+
+        assert_eq!(open, 2);
+        assert_eq!(total, 3);
+    }
 
-        let range = p
-            .split('-')
-            .map(str::parse)
-            .collect::<Result<Vec<u16>, std::num::ParseIntError>>()
+    #[test]
+    fn scan_udp_localhost() {
+        let rt = Runtime::new().unwrap();
+        let echo = rt
+            .block_on(tokio::net::UdpSocket::bind("127.0.0.1:0"))
             .unwrap();
+        let echo_port = echo.local_addr().unwrap().port();
+        let closed = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        rt.spawn(async move {
+            let mut buf = [0u8; 64];
+            while let Ok((n, peer)) = echo.recv_from(&mut buf).await {
+                let _ = echo.send_to(&buf[..n], peer).await;
+            }
+        });
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![echo_port, closed_port],
+        );
+        scanner.set_scan_type(crate::QScanType::Udp);
+        scanner.set_udp_payload(b"qscan");
+        scanner.set_timeout_ms(500);
+        let res = rt.block_on(scanner.scan_udp());
 
-        match range.len() {
-            1 => pv.push(range[0]),
-            2 => pv.extend(range[0]..=range[1]),
-            _ => {
-                panic!("Invalid Range: {:?}", range);
+        assert_eq!(res.len(), 2);
+        for r in res {
+            if let super::QScanResult::Udp(ur) = r {
+                if ur.target.port() == echo_port {
+                    assert_eq!(ur.state, super::QScanUdpState::Open);
+                } else {
+                    assert_eq!(ur.state, super::QScanUdpState::Close);
+                }
+            } else {
+                panic!("unexpected result {:?}", r);
             }
         }
     }
 
-    pv.into_iter().unique().collect::<Vec<u16>>()
-}
-
-/// Parse IP addresses strings.
-/// E.g., "1.2.3.4", "1.2.3.4,8.8.8.8", 192.168.1.0/24"
-fn addresses_parse(addresses: &str) -> Vec<IpAddr> {
-    let mut ips: Vec<IpAddr> = Vec::new();
-    let alt_resolver =
-        Resolver::new(ResolverConfig::cloudflare_tls(), ResolverOpts::default()).unwrap();
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_tcp_connect_result() {
+        let res = super::QScanResult::TcpConnect(super::QScanTcpConnectResult {
+            target: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 80),
+            state: super::QScanTcpConnectState::Open,
+            latency: Some(Duration::from_millis(12)),
+            hostname: Some("localhost".to_string()),
+            http: None,
+            tls: None,
+            probe: None,
+            attempts: 1,
+            error_kind: None,
+            skipped: None,
+            scanned_at: std::time::UNIX_EPOCH,
+        });
+        let json = serde_json::to_string(&res).unwrap();
+        assert_eq!(
+            json,
+            r#"{"type":"TcpConnect","target":"127.0.0.1:80","state":"OPEN","latency_ms":12.0,"hostname":"localhost","http":null,"tls":null,"probe":null,"attempts":1,"error_kind":null,"skipped":null,"scanned_at":0}"#
+        );
 
-    let addrs: String = addresses.chars().filter(|c| !c.is_whitespace()).collect();
+        let de: super::QScanResult = serde_json::from_str(&json).unwrap();
+        if let super::QScanResult::TcpConnect(r) = de {
+            assert_eq!(
+                r.target,
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 80)
+            );
+            assert_eq!(r.state, super::QScanTcpConnectState::Open);
+            assert_eq!(r.latency, Some(Duration::from_millis(12)));
+            assert_eq!(r.hostname.as_deref(), Some("localhost"));
+        } else {
+            panic!("unexpected result {:?}", de);
+        }
+    }
 
-    for addr in addrs.split(',') {
-        if addr.is_empty() {
-            continue;
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_udp_result() {
+        let json = r#"{"type":"Udp","target":"[::1]:53","state":"OPEN_FILTERED"}"#;
+        let de: super::QScanResult = serde_json::from_str(json).unwrap();
+        if let super::QScanResult::Udp(r) = &de {
+            assert_eq!(r.target, "[::1]:53".parse::<SocketAddr>().unwrap());
+            assert_eq!(r.state, super::QScanUdpState::OpenFiltered);
+        } else {
+            panic!("unexpected result {:?}", de);
         }
+        assert_eq!(serde_json::to_string(&de).unwrap(), json);
+    }
 
-        let parsed_addr = address_parse(addr, &alt_resolver);
+    #[cfg(feature = "serialize")]
+    #[test]
+    fn last_results_json_string() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "80").unwrap();
+        assert_eq!(scanner.get_last_results_as_json_string().unwrap(), "null");
+
+        scanner.last_results = Some(vec![
+            super::QScanResult::Udp(super::QScanUdpResult {
+                target: "127.0.0.1:53".parse().unwrap(),
+                state: super::QScanUdpState::Open,
+            }),
+            super::QScanResult::Ping(super::QScanPingResult {
+                target: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                state: super::QScanPingState::Down,
+            }),
+        ]);
+        assert_eq!(
+            scanner.get_last_results_as_json_string().unwrap(),
+            r#"[{"IP":"127.0.0.1","port":53,"state":"OPEN"},{"IP":"127.0.0.1","state":"DOWN"}]"#
+        );
+    }
 
-        if !parsed_addr.is_empty() {
-            ips.extend(parsed_addr);
-        } else {
-            // Check if we have a file to read addresses from
-            let file_path = Path::new(addr);
-            if !file_path.is_file() {
-                println!("Error: not a file {:?}", addr);
-                continue;
-            }
+    #[test]
+    fn scan_tcp_connect_latency() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let closed_port = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![open_port, closed_port],
+        );
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
 
-            if let Ok(x) = read_addresses_from_file(file_path, &alt_resolver) {
-                ips.extend(x);
-            } else {
-                println!("Error: unknown target {:?}", addr);
+        assert_eq!(res.len(), 2);
+        for r in res {
+            if let super::QScanResult::TcpConnect(sa) = r {
+                if sa.target.port() == open_port {
+                    assert_eq!(sa.state, super::QScanTcpConnectState::Open);
+                    let latency = sa.latency.unwrap();
+                    assert!(latency > Duration::ZERO && latency < Duration::from_secs(1));
+                } else {
+                    assert_eq!(sa.state, super::QScanTcpConnectState::Close);
+                    assert_eq!(sa.latency, None);
+                }
             }
         }
     }
 
-    ips.into_iter().unique().collect::<Vec<IpAddr>>()
-}
+    #[test]
+    fn scan_tcp_connect_max_rate() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
 
-fn address_parse(addr: &str, resolver: &Resolver) -> Vec<IpAddr> {
-    IpCidr::from_str(&addr)
-        .map(|cidr| cidr.iter().collect())
-        .ok()
-        .or_else(|| {
-            format!("{}:{}", &addr, 80)
-                .to_socket_addrs()
-                .ok()
-                .map(|mut iter| vec![iter.next().unwrap().ip()])
-        })
-        .unwrap_or_else(|| domain_name_resolve_to_ip(addr, resolver))
-}
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![port, port + 1, port + 2, port + 3, port + 4],
+        );
+        scanner.set_max_rate(10);
 
-fn domain_name_resolve_to_ip(source: &str, alt_resolver: &Resolver) -> Vec<IpAddr> {
-    let mut ips: Vec<IpAddr> = Vec::new();
+        let start = std::time::Instant::now();
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        let elapsed = start.elapsed();
 
-    if let Ok(addrs) = source.to_socket_addrs() {
-        for ip in addrs {
-            ips.push(ip.ip());
-        }
-    } else if let Ok(addrs) = alt_resolver.lookup_ip(source) {
-        ips.extend(addrs.iter());
+        assert_eq!(res.len(), 5);
+        // First attempt is immediate, then one every 100ms
+        assert!(elapsed >= Duration::from_millis(400), "{:?}", elapsed);
     }
 
-    ips
-}
+    #[test]
+    fn scan_tcp_connect_source_addr() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
 
-// Read ips or fomain name from a file
-fn read_addresses_from_file(
-    addrs_file_path: &Path,
-    backup_resolver: &Resolver,
-) -> Result<Vec<IpAddr>, std::io::Error> {
-    let file = File::open(addrs_file_path)?;
-    let reader = BufReader::new(file);
-    let mut ips: Vec<IpAddr> = Vec::new();
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(Ipv6Addr::LOCALHOST),
+            ],
+            vec![port],
+        );
+        scanner
+            .set_source_addr(IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .unwrap();
+        // Not an address of the host
+        assert!(scanner
+            .set_source_addr(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)))
+            .is_err());
+
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        for r in res {
+            if let super::QScanResult::TcpConnect(r) = r {
+                // The IPv6 target does not match the source family
+                match r.target.ip() {
+                    IpAddr::V4(_) => assert_eq!(r.state, super::QScanTcpConnectState::Open),
+                    IpAddr::V6(_) => assert_eq!(r.state, super::QScanTcpConnectState::Close),
+                }
+            } else {
+                panic!("unexpected result {:?}", r);
+            }
+        }
+        assert_eq!(res.len(), 2);
+    }
 
-    for (idx, address_line) in reader.lines().enumerate() {
-        if let Ok(address) = address_line {
-            ips.extend(address_parse(&address, backup_resolver));
-        } else {
-            println!("Error: Line {} in file is not valid", idx);
+    /// Minimal SOCKS5 server requiring the "user"/"pass" credentials
+    async fn socks5_server(listener: tokio::net::TcpListener) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        loop {
+            let (mut client, _) = listener.accept().await.unwrap();
+            tokio::spawn(async move {
+                let mut buf = [0u8; 2];
+                client.read_exact(&mut buf).await.unwrap();
+                let mut methods = vec![0u8; buf[1] as usize];
+                client.read_exact(&mut methods).await.unwrap();
+                if !methods.contains(&0x02) {
+                    client.write_all(&[0x05, 0xff]).await.unwrap();
+                    return;
+                }
+                client.write_all(&[0x05, 0x02]).await.unwrap();
+
+                client.read_exact(&mut buf).await.unwrap();
+                let mut user = vec![0u8; buf[1] as usize];
+                client.read_exact(&mut user).await.unwrap();
+                let mut pass = vec![0u8; client.read_u8().await.unwrap() as usize];
+                client.read_exact(&mut pass).await.unwrap();
+                if user != b"user" || pass != b"pass" {
+                    client.write_all(&[0x01, 0x01]).await.unwrap();
+                    return;
+                }
+                client.write_all(&[0x01, 0x00]).await.unwrap();
+
+                let mut req = [0u8; 10];
+                client.read_exact(&mut req).await.unwrap();
+                let ip = Ipv4Addr::new(req[4], req[5], req[6], req[7]);
+                let port = u16::from_be_bytes([req[8], req[9]]);
+                let rep = match tokio::net::TcpStream::connect((ip, port)).await {
+                    Ok(_) => 0x00,
+                    Err(_) => 0x05,
+                };
+                client
+                    .write_all(&[0x05, rep, 0x00, 0x01, 0, 0, 0, 0, 0, 0])
+                    .await
+                    .unwrap();
+            });
         }
     }
 
-    Ok(ips)
-}
+    #[test]
+    fn scan_tcp_connect_socks5() {
+        let rt = Runtime::new().unwrap();
+
+        let open = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open_port = open.local_addr().unwrap().port();
+        let closed_port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        let proxy = rt
+            .block_on(tokio::net::TcpListener::bind("127.0.0.1:0"))
+            .unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        rt.spawn(socks5_server(proxy));
 
-mod sockiter {
-    use itertools::{iproduct, Product};
-    use std::net::{IpAddr, SocketAddr};
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![open_port, closed_port],
+        );
+        scanner.set_proxy(super::ProxyConfig::with_credentials(
+            proxy_addr, "user", "pass",
+        ));
 
-    pub struct SockIter<'a> {
-        prod: Product<Box<std::slice::Iter<'a, u16>>, Box<std::slice::Iter<'a, std::net::IpAddr>>>,
-    }
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        assert_eq!(res.len(), 2);
+        for r in res {
+            if let super::QScanResult::TcpConnect(r) = r {
+                if r.target.port() == open_port {
+                    assert_eq!(r.state, super::QScanTcpConnectState::Open);
+                } else {
+                    assert_eq!(r.state, super::QScanTcpConnectState::Close);
+                }
+            } else {
+                panic!("unexpected result {:?}", r);
+            }
+        }
 
-    impl<'a> SockIter<'a> {
-        pub fn new(ips: &'a [IpAddr], ports: &'a [u16]) -> Self {
-            let ports = Box::new(ports.iter());
-            let ips = Box::new(ips.iter());
-            Self {
-                prod: iproduct!(ports, ips),
+        // Wrong credentials
+        scanner.set_proxy(super::ProxyConfig::with_credentials(
+            proxy_addr, "user", "wrong",
+        ));
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        for r in res {
+            if let super::QScanResult::TcpConnect(r) = r {
+                assert_eq!(r.state, super::QScanTcpConnectState::Close);
             }
         }
     }
 
-    impl<'s> Iterator for SockIter<'s> {
-        type Item = SocketAddr;
+    #[test]
+    fn scan_tcp_connect_http_probe() {
+        use std::io::{Read, Write};
+
+        let http = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let http_port = http.local_addr().unwrap().port();
+        let raw = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let raw_port = raw.local_addr().unwrap().port();
+
+        std::thread::spawn(move || {
+            let (mut s, _) = http.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let n = s.read(&mut buf).unwrap();
+            assert!(buf[..n].starts_with(b"GET / HTTP/1.0\r\n"));
+            s.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\nServer: qscan-test/1.0\r\n\r\nhello")
+                .unwrap();
+        });
+        std::thread::spawn(move || {
+            let (mut s, _) = raw.accept().unwrap();
+            s.write_all(b"SSH-2.0-OpenSSH_9.0\r\n").unwrap();
+        });
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![http_port, raw_port],
+        );
+        scanner.set_timeout_ms(500);
+        scanner.set_http_probe(true);
+        scanner.set_http_ports(&[http_port, raw_port]);
 
-        fn next(&mut self) -> Option<Self::Item> {
-            self.prod
-                .next()
-                .map(|(port, ip)| SocketAddr::new(*ip, *port))
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        assert_eq!(res.len(), 2);
+        for r in res {
+            if let super::QScanResult::TcpConnect(r) = r {
+                assert_eq!(r.state, super::QScanTcpConnectState::Open);
+                if r.target.port() == http_port {
+                    assert_eq!(
+                        r.http,
+                        Some(super::QScanHttpInfo {
+                            status_line: "HTTP/1.0 200 OK".to_string(),
+                            status_code: Some(200),
+                            server: Some("qscan-test/1.0".to_string()),
+                        })
+                    );
+                } else {
+                    assert_eq!(r.http, None);
+                }
+            } else {
+                panic!("unexpected result {:?}", r);
+            }
         }
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-    use trust_dns_resolver::{
-        config::{ResolverConfig, ResolverOpts},
-        Resolver,
-    };
-
-    use tokio::runtime::Runtime;
 
     #[test]
-    fn parse_empty_address() {
-        let res = super::addresses_parse("");
-        assert_eq!(res, Vec::<IpAddr>::new());
+    fn scan_tcp_connect_tls_probe() {
+        use std::sync::Arc;
+        use tokio::io::AsyncWriteExt;
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+        let rt = Runtime::new().unwrap();
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(
+                    include_bytes!("../tests/data/tls_cert.der").to_vec(),
+                )],
+                PrivateKey(include_bytes!("../tests/data/tls_key.der").to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+
+        let (tls_port, plain_port) = rt.block_on(async {
+            let tls = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let plain = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let ports = (
+                tls.local_addr().unwrap().port(),
+                plain.local_addr().unwrap().port(),
+            );
+
+            tokio::spawn(async move {
+                let (s, _) = tls.accept().await.unwrap();
+                let _ = acceptor.accept(s).await;
+            });
+            tokio::spawn(async move {
+                let (mut s, _) = plain.accept().await.unwrap();
+                s.write_all(b"220 ready\r\n").await.unwrap();
+            });
+            ports
+        });
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![tls_port, plain_port],
+        );
+        scanner.set_timeout_ms(500);
+        scanner.set_tls_probe(true);
+
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        assert_eq!(res.len(), 2);
+        for r in res {
+            if let super::QScanResult::TcpConnect(r) = r {
+                assert_eq!(r.state, super::QScanTcpConnectState::Open);
+                if r.target.port() == tls_port {
+                    assert_eq!(
+                        r.tls,
+                        Some(super::QScanTlsInfo {
+                            version: "TLSv1.3".to_string(),
+                            subject_cn: Some("qscan-test".to_string()),
+                            not_after: Some("2054-03-01T16:17:42Z".to_string()),
+                        })
+                    );
+                } else {
+                    assert_eq!(r.tls, None);
+                }
+            } else {
+                panic!("unexpected result {:?}", r);
+            }
+        }
     }
 
     #[test]
-    fn parse_commas_address() {
-        let res = super::addresses_parse(",,,,");
-        assert_eq!(res, Vec::<IpAddr>::new());
+    fn scan_tcp_connect_tls_probe_sni() {
+        use std::sync::{Arc, Mutex};
+        use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+
+        let rt = Runtime::new().unwrap();
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(
+                    include_bytes!("../tests/data/tls_cert.der").to_vec(),
+                )],
+                PrivateKey(include_bytes!("../tests/data/tls_key.der").to_vec()),
+            )
+            .unwrap();
+        let acceptor = tokio_rustls::TlsAcceptor::from(Arc::new(config));
+        let received: Arc<Mutex<Vec<Option<String>>>> = Arc::default();
+
+        let port = rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            let received = received.clone();
+
+            tokio::spawn(async move {
+                loop {
+                    let (s, _) = listener.accept().await.unwrap();
+                    if let Ok(tls) = acceptor.accept(s).await {
+                        let sni = tls.get_ref().1.sni_hostname().map(str::to_string);
+                        received.lock().unwrap().push(sni);
+                    }
+                }
+            });
+            port
+        });
+
+        let mut scanner = super::QScanner::new("", "").unwrap();
+        scanner.set_resolve_at_connect(true);
+        scanner.set_resolve_policy(super::ResolvePolicy::AllV4);
+        scanner
+            .set_targets("localhost,127.0.0.1", &port.to_string())
+            .unwrap();
+        scanner.set_timeout_ms(1000);
+        scanner.set_tls_probe(true);
+
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        assert_eq!(res.len(), 2);
+        assert!(res.iter().all(|r| matches!(
+            r,
+            super::QScanResult::TcpConnect(r) if r.tls.is_some()
+        )));
+
+        // The server may complete its handshakes after the scan ended
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while received.lock().unwrap().len() < 2 && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let mut received = received.lock().unwrap().clone();
+        received.sort();
+        assert_eq!(received, vec![None, Some("localhost".to_string())]);
     }
 
     #[test]
-    fn parse_simple_address() {
-        let res = super::addresses_parse("127.0.0.1");
-        assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    #[ignore]
+    fn scan_tcp_connect_tls_probe_cloudflare_dns() {
+        let mut scanner = super::QScanner::new("1.1.1.1", "443").unwrap();
+        scanner.set_tls_probe(true);
+
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        if let Some(super::QScanResult::TcpConnect(r)) = res.first() {
+            let tls = r.tls.as_ref().unwrap();
+            assert_eq!(tls.subject_cn.as_deref(), Some("cloudflare-dns.com"));
+            assert!(tls.not_after.is_some());
+        } else {
+            panic!("unexpected results {:?}", res);
+        }
     }
 
     #[test]
-    fn parse_repeated_address1() {
-        let res = super::addresses_parse("127.0.0.1,127.0.0.1");
-        assert_eq!(res, vec!["127.0.0.1".parse::<IpAddr>().unwrap()]);
+    fn scan_tcp_connect_closed_filtered() {
+        let rt = Runtime::new().unwrap();
+
+        let refusing = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+
+        // A listener with a full accept queue silently drops new SYNs
+        let dropping = rt
+            .block_on(async {
+                let s = tokio::net::TcpSocket::new_v4().unwrap();
+                s.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+                s.listen(0)
+            })
+            .unwrap();
+        let dropping_addr = dropping.local_addr().unwrap();
+        let _queued = std::net::TcpStream::connect(dropping_addr).unwrap();
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![refusing, dropping_addr.port()],
+        );
+        scanner.set_timeout_ms(200);
+
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        assert_eq!(res.len(), 2);
+        for r in res {
+            if let super::QScanResult::TcpConnect(r) = r {
+                if r.target.port() == refusing {
+                    assert_eq!(r.state, super::QScanTcpConnectState::Close);
+                } else {
+                    assert_eq!(r.state, super::QScanTcpConnectState::Filtered);
+                }
+            } else {
+                panic!("unexpected result {:?}", r);
+            }
+        }
     }
 
     #[test]
-    fn parse_repeated_address2() {
-        let res = super::addresses_parse("127.0.0.1,127.0.0.2,127.0.0.0/30");
-        assert_eq!(
-            res,
+    fn scan_tcp_connect_on_result() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut scanner = super::QScanner::new_from_vecs(
             vec![
-                "127.0.0.1".parse::<IpAddr>().unwrap(),
-                "127.0.0.2".parse::<IpAddr>().unwrap(),
-                "127.0.0.0".parse::<IpAddr>().unwrap(),
-                "127.0.0.3".parse::<IpAddr>().unwrap(),
-            ]
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            ],
+            vec![port, port + 1, port + 2],
         );
+
+        let count = Arc::new(AtomicUsize::new(0));
+        let c = count.clone();
+        scanner.set_on_result(Box::new(move |_| {
+            c.fetch_add(1, Ordering::SeqCst);
+        }));
+
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        assert_eq!(res.len(), 6);
+        assert_eq!(count.load(Ordering::SeqCst), 6);
     }
 
     #[test]
-    fn parse_repeated_address3() {
-        let res = super::addresses_parse("127.0.0.1,192.168.1.1,127.0.0.0/30");
+    fn scan_tcp_connect_closed_cache() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let cached = listener.local_addr().unwrap();
+        let other = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = other.local_addr().unwrap();
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![cached.port(), open.port()],
+        );
+        scanner.set_closed_cache(
+            std::collections::HashSet::from([cached]),
+            Duration::from_secs(60),
+        );
+        let rt = Runtime::new().unwrap();
+        let (res, stats) = rt.block_on(scanner.scan_tcp_connect_with_stats());
+
+        let results: std::collections::HashMap<SocketAddr, &super::QScanTcpConnectResult> = res
+            .iter()
+            .map(|r| match r {
+                super::QScanResult::TcpConnect(r) => (r.target, r),
+                _ => panic!("unexpected result {:?}", r),
+            })
+            .collect();
+        let r = results[&cached];
         assert_eq!(
-            res,
-            vec![
-                "127.0.0.1".parse::<IpAddr>().unwrap(),
-                "192.168.1.1".parse::<IpAddr>().unwrap(),
-                "127.0.0.0".parse::<IpAddr>().unwrap(),
-                "127.0.0.2".parse::<IpAddr>().unwrap(),
-                "127.0.0.3".parse::<IpAddr>().unwrap(),
-            ]
+            (r.state, r.attempts),
+            (super::QScanTcpConnectState::Close, 0)
+        );
+        assert_eq!(r.skipped, Some(super::QScanSkipReason::CachedClosed));
+        assert_eq!(r.error_kind, None);
+        assert_eq!(results[&open].state, super::QScanTcpConnectState::Open);
+        assert_eq!(results[&open].skipped, None);
+        assert_eq!((stats.attempted, stats.open, stats.closed), (1, 1, 0));
+        assert_eq!(stats.skipped, 1);
+        assert_eq!(
+            listener.accept().unwrap_err().kind(),
+            std::io::ErrorKind::WouldBlock
         );
+
+        // Expired cache
+        scanner.set_closed_cache(std::collections::HashSet::from([cached]), Duration::ZERO);
+        let res = rt.block_on(scanner.scan_tcp_connect());
+        assert!(res.iter().all(|r| matches!(
+            r,
+            super::QScanResult::TcpConnect(r) if r.state == super::QScanTcpConnectState::Open
+        )));
     }
 
     #[test]
-    fn parse_multiple_addresses() {
-        let res = super::addresses_parse("127.0.0.1,127.0.0.2");
-        assert_eq!(
-            res,
-            vec![
-                "127.0.0.1".parse::<IpAddr>().unwrap(),
-                "127.0.0.2".parse::<IpAddr>().unwrap(),
-            ]
+    fn scan_tcp_connect_scanned_at() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let close = closed.local_addr().unwrap().port();
+        drop(closed);
+
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![IpAddr::V4(Ipv4Addr::LOCALHOST)],
+            vec![open, close],
         );
+        let start = std::time::SystemTime::now();
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        let end = std::time::SystemTime::now();
+
+        assert_eq!(res.len(), 2);
+        for r in res {
+            match r {
+                super::QScanResult::TcpConnect(r) => {
+                    assert!(start <= r.scanned_at && r.scanned_at <= end)
+                }
+                _ => panic!("unexpected result {:?}", r),
+            }
+        }
     }
 
     #[test]
-    fn parse_cidr() {
-        let res = super::addresses_parse("127.0.0.10/31");
-        assert_eq!(
-            res,
-            vec![
-                "127.0.0.10".parse::<IpAddr>().unwrap(),
-                "127.0.0.11".parse::<IpAddr>().unwrap(),
-            ]
-        );
+    fn scan_tcp_connect_scanned_at_before_probe() {
+        // Never answering the HTTP probe, which lasts the whole timeout
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], vec![port]);
+        scanner.set_timeout_ms(500);
+        scanner.set_http_probe(true);
+        scanner.set_http_ports(&[port]);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        let end = std::time::SystemTime::now();
+
+        match &res[0] {
+            super::QScanResult::TcpConnect(r) => {
+                assert_eq!(r.state, super::QScanTcpConnectState::Open);
+                assert!(end.duration_since(r.scanned_at).unwrap() >= Duration::from_millis(400));
+            }
+            r => panic!("unexpected result {:?}", r),
+        }
     }
 
     #[test]
-    fn parse_cidr_and_addresses() {
-        let res = super::addresses_parse("127.0.0.1,127.0.0.10/31, 127.0.0.2");
+    fn scan_tcp_connect_on_result_control() {
+        use std::ops::ControlFlow;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let open = listener.local_addr().unwrap().port();
+        let closed: Vec<u16> = (0..52)
+            .map(|_| {
+                let l = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+                l.local_addr().unwrap().port()
+            })
+            .collect();
+
+        let mut ports = vec![closed[0], closed[1], open];
+        ports.extend(&closed[2..]);
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], ports);
+        scanner.set_batch(1);
+        scanner.set_on_result_control(Box::new(|r| match r {
+            super::QScanResult::TcpConnect(r) if r.state == super::QScanTcpConnectState::Open => {
+                ControlFlow::Break(())
+            }
+            _ => ControlFlow::Continue(()),
+        }));
+
+        let token = tokio_util::sync::CancellationToken::new();
+        let rt = Runtime::new().unwrap();
+        let res = rt.block_on(scanner.scan_tcp_connect_with_cancel(token.clone()));
+        let states: Vec<super::QScanTcpConnectState> = res
+            .iter()
+            .map(|r| match r {
+                super::QScanResult::TcpConnect(r) => r.state,
+                _ => panic!("unexpected result {:?}", r),
+            })
+            .collect();
         assert_eq!(
-            res,
-            vec![
-                "127.0.0.1".parse::<IpAddr>().unwrap(),
-                "127.0.0.10".parse::<IpAddr>().unwrap(),
-                "127.0.0.11".parse::<IpAddr>().unwrap(),
-                "127.0.0.2".parse::<IpAddr>().unwrap(),
+            states,
+            [
+                super::QScanTcpConnectState::Close,
+                super::QScanTcpConnectState::Close,
+                super::QScanTcpConnectState::Open,
             ]
         );
+        // The caller token is not cancelled
+        assert!(!token.is_cancelled());
     }
 
     #[test]
-    fn parse_empty_port() {
-        let res = super::ports_parse("");
-        assert_eq!(res, Vec::<u16>::new());
-    }
+    fn scan_tcp_connect_progress() {
+        use std::sync::{Arc, Mutex};
 
-    #[test]
-    fn parse_commas_port() {
-        let res = super::ports_parse(",,,");
-        assert_eq!(res, Vec::<u16>::new());
-    }
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
 
-    #[test]
-    fn parse_single_port() {
-        let res = super::ports_parse("80");
-        assert_eq!(res, vec![80]);
-    }
+        let mut scanner = super::QScanner::new_from_vecs(
+            vec![
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+            ],
+            vec![port, port + 1, port + 2],
+        );
 
-    #[test]
-    fn parse_repeated_port1() {
-        let res = super::ports_parse("80,80");
-        assert_eq!(res, vec![80]);
-    }
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let r = reports.clone();
+        scanner.set_progress_callback(
+            Duration::from_millis(10),
+            Box::new(move |p| r.lock().unwrap().push(p)),
+        );
 
-    #[test]
-    fn parse_repeated_port2() {
-        let res = super::ports_parse("80,79-81");
-        assert_eq!(res, vec![80, 79, 81]);
+        Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+
+        let last = {
+            let reports = reports.lock().unwrap();
+            assert!(!reports.is_empty());
+            assert!(reports.iter().all(|p| p.total == 6));
+            *reports.last().unwrap()
+        };
+        assert_eq!(last.completed, 6);
+        assert_eq!(last.open_found, 1);
+
+        // The last report of a sampled scan has the exact total
+        scanner.set_sample(super::Sample::Fraction(0.5));
+        scanner.set_sample_seed(1);
+        let scanned = Runtime::new()
+            .unwrap()
+            .block_on(scanner.scan_tcp_connect())
+            .len() as u64;
+        let last = *reports.lock().unwrap().last().unwrap();
+        assert_eq!((last.completed, last.total), (scanned, scanned));
+
+        // A scan cut short reports once it ends
+        scanner.set_sample(super::Sample::Fraction(1.0));
+        scanner.set_batch(1);
+        scanner.set_max_open_results(1);
+        reports.lock().unwrap().clear();
+        Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+        let last = *reports.lock().unwrap().last().unwrap();
+        assert_eq!((last.completed, last.total, last.open_found), (1, 6, 1));
     }
 
     #[test]
-    fn parse_repeated_port3() {
-        let res = super::ports_parse("80,128,79-81");
-        assert_eq!(res, vec![80, 128, 79, 81]);
+    fn scan_tcp_connect_cancel() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let mut scanner =
+            super::QScanner::new_from_vecs(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)], vec![port; 100]);
+        // 100 targets at 10/s would take about 10s
+        scanner.set_max_rate(10);
+
+        let rt = Runtime::new().unwrap();
+        let token = super::CancellationToken::new();
+        let child = token.clone();
+        rt.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            child.cancel();
+        });
+
+        let start = std::time::Instant::now();
+        let res = rt.block_on(scanner.scan_tcp_connect_with_cancel(token));
+        let elapsed = start.elapsed();
+
+        assert!(elapsed < Duration::from_secs(2), "{:?}", elapsed);
+        assert!(!res.is_empty());
+        assert!(res.len() < 100);
     }
 
     #[test]
-    fn parse_multiple_ports() {
-        let res = super::ports_parse("80, 443,8080");
-        assert_eq!(res, vec![80, 443, 8080]);
-    }
+    #[ignore]
+    fn scan_tcp_connect_resolve_ptr_google_dns() {
+        let mut scanner = super::QScanner::new("8.8.8.8", "53").unwrap();
+        scanner.set_resolve_ptr(true);
+        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
 
-    #[test]
-    fn parse_ports_range() {
-        let res = super::ports_parse("80-83");
-        assert_eq!(res, vec![80, 81, 82, 83]);
+        if let super::QScanResult::TcpConnect(r) = &res[0] {
+            assert_eq!(r.hostname.as_deref(), Some("dns.google"));
+        } else {
+            panic!("unexpected result {:?}", res[0]);
+        }
     }
 
     #[test]
-    fn parse_ports_mixed() {
-        let res = super::ports_parse("21,80-83,443,8080-8081");
-        assert_eq!(res, vec![21, 80, 81, 82, 83, 443, 8080, 8081]);
+    fn custom_resolver_config() {
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        assert!(scanner.fallback_resolver().is_none());
+
+        scanner
+            .set_resolver_config(ResolverConfig::new(), ResolverOpts::default())
+            .unwrap();
+        assert!(scanner.fallback_resolver().is_some());
+
+        // The system resolver is still tried first
+        scanner.set_targets_addr("localhost,127.0.0.2");
+        assert!(scanner
+            .get_tagets_ips()
+            .contains(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert!(scanner
+            .get_tagets_ips()
+            .contains(&IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2))));
     }
 
     #[test]
-    fn set_new_targets() {
-        let mut scanner = super::QScanner::new("", "");
-        scanner.set_targets("1.1.1.1", "80");
-        assert_eq!(
-            *scanner.get_tagets_ips(),
-            vec!["1.1.1.1".parse::<IpAddr>().unwrap()]
-        );
-        assert_eq!(*scanner.get_tagets_ports(), vec![80]);
+    fn dns_timeout_and_attempts() {
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        scanner.set_dns_timeout(Duration::from_millis(200));
+        scanner.set_dns_attempts(1);
+        assert!(scanner.resolver_config().is_none());
+
+        scanner
+            .set_resolver_config(ResolverConfig::new(), ResolverOpts::default())
+            .unwrap();
+        let (_, opts) = scanner.resolver_config().unwrap();
+        assert_eq!(opts.timeout, Duration::from_millis(200));
+        assert_eq!(opts.attempts, 1);
+        assert!(scanner.fallback_resolver().is_some());
+
+        // The configured options are used when not overridden
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        let defaults = ResolverOpts::default();
+        scanner
+            .set_resolver_config(ResolverConfig::new(), defaults)
+            .unwrap();
+        let (_, opts) = scanner.resolver_config().unwrap();
+        assert_eq!(opts.timeout, defaults.timeout);
+        assert_eq!(opts.attempts, defaults.attempts);
     }
 
     #[test]
-    fn add_new_targets() {
-        let mut scanner = super::QScanner::new("127.0.0.1", "80");
-        scanner.add_targets("127.0.0.0/30,192.168.1.1", "79-80,81");
+    fn read_addresses() {
+        let mut parsed = super::ParsedAddresses::default();
+        let input = std::io::Cursor::new("127.0.0.1\n\n10.0.0.0/31\n10.0.0.256\n");
+        super::read_addresses(
+            input,
+            None,
+            super::ResolvePolicy::All,
+            super::MAX_CIDR_HOSTS_DEF,
+            &[],
+            1,
+            &mut parsed,
+        )
+        .unwrap();
         assert_eq!(
-            *scanner.get_tagets_ips(),
+            parsed.ips,
             vec![
-                "127.0.0.1".parse::<IpAddr>().unwrap(),
-                "127.0.0.0".parse::<IpAddr>().unwrap(),
-                "127.0.0.2".parse::<IpAddr>().unwrap(),
-                "127.0.0.3".parse::<IpAddr>().unwrap(),
-                "192.168.1.1".parse::<IpAddr>().unwrap(),
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
             ]
         );
-        assert_eq!(*scanner.get_tagets_ports(), vec![80, 79, 81]);
-    }
+        assert_eq!(parsed.unresolved, ["10.0.0.256"]);
 
-    #[test]
-    fn set_vec_new_targets() {
-        let mut scanner = super::QScanner::new("", "");
-        let target_ips = vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))];
-        let target_ports = vec![80];
-        scanner.set_vec_targets(target_ips, target_ports);
+        assert_eq!(super::targets_split("-"), ["-"]);
+        assert_eq!(super::targets_split("127.0.0.1,-"), ["127.0.0.1", "-"]);
+        assert_eq!(super::targets_split("- 10.0.0.1"), ["-", "10.0.0.1"]);
         assert_eq!(
-            *scanner.get_tagets_ips(),
-            vec!["127.0.0.1".parse::<IpAddr>().unwrap()]
+            super::targets_split("host.example -"),
+            ["host.example", "-"]
+        );
+        assert_eq!(
+            super::targets_split("10.0.0.1 - 10, -"),
+            ["10.0.0.1-10", "-"]
         );
-        assert_eq!(*scanner.get_tagets_ports(), vec![80]);
     }
 
     #[test]
-    fn add_vec_new_targets() {
-        let mut scanner = super::QScanner::new("127.0.0.1", "80");
-        let target_ips = vec![
-            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
-            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
-        ];
-        let target_ports = vec![443, 80, 53];
-        scanner.add_vec_targets(target_ips, target_ports);
+    fn parse_addresses_dns_concurrency() {
+        let targets = "localhost,127.0.0.2,not-a-host.invalid,LOCALHOST,10.0.0.0/31,localhost";
+        let parse = |targets: &str, concurrency| {
+            super::addresses_parse_with_duplicates(
+                targets,
+                super::MAX_CIDR_HOSTS_DEF,
+                super::MAX_TARGETS_DEF,
+                None,
+                super::ResolvePolicy::AllV4,
+                &[],
+                concurrency,
+            )
+        };
+
+        let seq = parse(targets, 1);
+        let par = parse(targets, 8);
+        let lhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
         assert_eq!(
-            *scanner.get_tagets_ips(),
+            par.ips,
             vec![
-                "127.0.0.1".parse::<IpAddr>().unwrap(),
-                "127.0.0.2".parse::<IpAddr>().unwrap(),
+                lhost,
+                IpAddr::V4(Ipv4Addr::new(127, 0, 0, 2)),
+                lhost,
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 0)),
+                IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)),
+                lhost,
             ]
         );
-        assert_eq!(*scanner.get_tagets_ports(), vec![80, 443, 53]);
+        assert_eq!(par.ips, seq.ips);
+        assert_eq!(par.unresolved, ["not-a-host.invalid"]);
+        assert_eq!(par.unresolved, seq.unresolved);
+
+        // Host names of targets files are resolved in parallel too
+        let path = std::env::temp_dir().join(format!("qscan_dns_{}.txt", std::process::id()));
+        std::fs::write(&path, targets.replace(',', "\n")).unwrap();
+        let file = parse(path.to_str().unwrap(), 8);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file.ips, seq.ips);
+        assert_eq!(file.unresolved, seq.unresolved);
     }
 
     #[test]
-    fn scan_tcp_connect_google_dns() {
-        let mut scanner = super::QScanner::new("8.8.8.8", "53,54,55-60");
-        let res = Runtime::new().unwrap().block_on(scanner.scan_tcp_connect());
+    fn socket_addrs_ips() {
+        use std::net::ToSocketAddrs;
+
+        let socks: Vec<SocketAddr> = [
+            "192.0.2.1:80",
+            "[2001:db8::1]:80",
+            "192.0.2.1:80",
+            "192.0.2.2:80",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        let ips: Vec<IpAddr> = ["192.0.2.1", "2001:db8::1", "192.0.2.2"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        assert_eq!(super::socket_addrs_ips(socks.into_iter()), ips);
+        assert!(super::socket_addrs_ips(std::iter::empty()).is_empty());
+
+        // All the addresses a name resolves to are targets
+        let resolved: Vec<IpAddr> =
+            super::socket_addrs_ips(("localhost", 80).to_socket_addrs().unwrap());
+        let parsed = super::address_parse("localhost", None, super::ResolvePolicy::All, 1, &[]);
+        assert!(!parsed.is_empty());
+        assert_eq!(parsed, resolved);
+    }
 
-        for r in res {
-            if let super::QScanResult::TcpConnect(sa) = r {
-                if sa.state == super::QScanTcpConnectState::Open {
-                    assert_eq!(
-                        sa.target,
-                        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 53)
-                    );
-                }
-            }
-        }
+    #[test]
+    fn resolve_policy() {
+        use super::ResolvePolicy;
+
+        let v4 = |d| IpAddr::V4(Ipv4Addr::new(192, 0, 2, d));
+        let v6 = |s| IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, s));
+        let dual = vec![v6(1), v4(1), v4(2), v6(2)];
+
+        assert_eq!(ResolvePolicy::First.select(dual.clone()), vec![v6(1)]);
+        assert_eq!(
+            ResolvePolicy::AllV4.select(dual.clone()),
+            vec![v4(1), v4(2)]
+        );
+        assert_eq!(
+            ResolvePolicy::AllV6.select(dual.clone()),
+            vec![v6(1), v6(2)]
+        );
+        assert_eq!(ResolvePolicy::All.select(dual.clone()), dual);
+        assert!(ResolvePolicy::First.select(Vec::new()).is_empty());
+
+        // Literal addresses are kept whatever the policy
+        let mut scanner = super::QScanner::new("", "80").unwrap();
+        scanner.set_resolve_policy(ResolvePolicy::AllV4);
+        scanner.set_targets_addr("::1,localhost");
+        let ips = scanner.get_tagets_ips();
+        assert_eq!(ips[0], IpAddr::V6(Ipv6Addr::LOCALHOST));
+        assert!(ips.contains(&IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert!(ips[1..].iter().all(IpAddr::is_ipv4));
+
+        // Whatever localhost resolves to, the policies select from the same
+        // addresses
+        let resolved = |policy| {
+            let mut scanner = super::QScanner::new("", "80").unwrap();
+            scanner.set_resolve_policy(policy);
+            scanner.set_targets_addr("localhost");
+            scanner.get_tagets_ips().clone()
+        };
+        let all = resolved(ResolvePolicy::All);
+        assert!(!all.is_empty());
+        assert_eq!(resolved(ResolvePolicy::First), all[..1]);
+        assert_eq!(
+            resolved(ResolvePolicy::AllV4),
+            ResolvePolicy::AllV4.select(all.clone())
+        );
+        assert_eq!(
+            resolved(ResolvePolicy::AllV6),
+            ResolvePolicy::AllV6.select(all)
+        );
     }
 
     #[test]
     fn resolve_localhost() {
         let resolver =
             Resolver::new(ResolverConfig::cloudflare_tls(), ResolverOpts::default()).unwrap();
-        let res = super::domain_name_resolve_to_ip("localhost", &resolver);
+        let res = super::domain_name_resolve_to_ip("localhost", Some(&resolver));
         assert_eq!(res, vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]);
     }
 
     #[test]
+    #[allow(clippy::len_zero)]
     fn resolve_lhost() {
         let resolver =
             Resolver::new(ResolverConfig::cloudflare_tls(), ResolverOpts::default()).unwrap();
-        let res = super::domain_name_resolve_to_ip("www.google.com", &resolver);
+        let res = super::domain_name_resolve_to_ip("www.google.com", Some(&resolver));
         assert!(res.len() > 0);
     }
 
+    #[test]
+    fn ping_sweep_localhost() {
+        let mut scanner = super::QScanner::new("127.0.0.1", "").unwrap();
+        scanner.set_timeout_ms(500);
+
+        match Runtime::new().unwrap().block_on(scanner.ping_sweep()) {
+            Ok(up) => assert_eq!(up, vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]),
+            // Raw sockets not allowed
+            Err(super::QScanError::Io(e)) => {
+                assert_eq!(e.kind(), std::io::ErrorKind::PermissionDenied)
+            }
+            Err(e) => panic!("unexpected error {}", e),
+        }
+        assert!(scanner.get_last_results().is_none());
+    }
+
     #[test]
     #[ignore]
     fn scan_ping_google_dns() {
-        let mut scanner = super::QScanner::new("8.8.8.8", "");
+        let mut scanner = super::QScanner::new("8.8.8.8", "").unwrap();
         scanner.set_scan_type(crate::QScanType::Ping);
         scanner.set_ntries(5);
         let res = Runtime::new().unwrap().block_on(scanner.scan_ping());
@@ -1168,7 +11055,7 @@ mod tests {
                     assert_eq!(pr.target, IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)));
                 }
                 if pr.state == super::QScanPingState::Down {
-                    assert!(false);
+                    panic!("{} unexpectedly down", pr.target);
                 }
             }
         }
@@ -1177,7 +11064,7 @@ mod tests {
     #[test]
     #[ignore]
     fn scan_ping_localhost() {
-        let mut scanner = super::QScanner::new("127.0.0.0/24", "");
+        let mut scanner = super::QScanner::new("127.0.0.0/24", "").unwrap();
         scanner.set_scan_type(crate::QScanType::Ping);
         scanner.set_ntries(5);
         scanner.set_ping_payload(&[0x41; 56]);
@@ -1195,10 +11082,31 @@ mod tests {
         assert!(up_ctr > 0);
     }
 
+    #[test]
+    #[ignore]
+    fn scan_ping_with_cancel() {
+        let mut scanner = super::QScanner::new("169.254.101.100", "").unwrap();
+        scanner.set_scan_type(crate::QScanType::Ping);
+        scanner.set_timeout_ms(30000);
+        let token = tokio_util::sync::CancellationToken::new();
+        let cancel = token.clone();
+
+        let start = std::time::Instant::now();
+        let rt = Runtime::new().unwrap();
+        rt.spawn(async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            cancel.cancel();
+        });
+        let res = rt.block_on(scanner.scan_ping_with_cancel(token)).unwrap();
+
+        assert!(res.is_empty());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
     #[test]
     #[ignore]
     fn scan_ping_unreachable() {
-        let mut scanner = super::QScanner::new("169.254.101.100,8.8.8.8", "");
+        let mut scanner = super::QScanner::new("169.254.101.100,8.8.8.8", "").unwrap();
         scanner.set_scan_type(crate::QScanType::Ping);
         scanner.set_ntries(3);
         scanner.set_timeout_ms(1000);
@@ -1219,8 +11127,9 @@ mod tests {
 
     #[test]
     #[ignore]
+    #[allow(clippy::collapsible_if)]
     fn scan_ping_multiple() {
-        let mut scanner = super::QScanner::new("8.8.8.8,1.1.1.1,8.8.4.4,1.0.0.1", "");
+        let mut scanner = super::QScanner::new("8.8.8.8,1.1.1.1,8.8.4.4,1.0.0.1", "").unwrap();
         scanner.set_scan_type(crate::QScanType::Ping);
         scanner.set_ntries(5);
         let res = Runtime::new().unwrap().block_on(scanner.scan_ping());